@@ -1,19 +1,25 @@
 //! The core Agent logic module.
 
-use crate::{AppState, ChatMessage, WebviewWindow};
-use async_openai::{
-    config::OpenAIConfig,
-    types::{
-        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
-        ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-        CreateChatCompletionRequestArgs,
-    },
-    Client,
+use crate::decision::{AgentState, StateNotes, STATE_INSTRUCTION};
+use crate::error::{AgentError, ErrorKind};
+use crate::llm::LlmClient;
+use crate::history::{ChatMessage, GenerationInfo, Role, ToolCallInfo, ToolCallStatus};
+use crate::memory;
+use crate::plan::{self, Plan, PlanStatus, StepStatus};
+use crate::tools;
+use crate::{AppState, WebviewWindow};
+use async_openai::types::{
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
+    CreateChatCompletionRequestArgs,
 };
-use rmcp::model::{CallToolRequestParam, JsonObject};
+use rmcp::model::{CallToolRequestParam, CallToolResult, JsonObject};
+use rmcp::service::ServiceError;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tauri::Emitter;
 use tracing::{info, instrument, warn};
 
@@ -32,10 +38,164 @@ pub enum AgentStatus {
     UsingTool {
         tool_name: String,
     },
+    Retrying {
+        attempt: u32,
+        delay_ms: u64,
+        error: AgentError,
+    },
+}
+
+/// Sent as `agent_plan` whenever the plan of a running task changes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanEvent {
+    pub session_id: String,
+    pub plan: Plan,
 }
 
 // --- Agent Core Structures ---
 
+/// How to carry out a request.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub generation: GenerationInfo,
+    /// Plan the task as a list of steps first and work through them one by one.
+    pub plan: bool,
+    pub limits: RunLimits,
+}
+
+/// Budgets for one run. A request's limits override its profile's, which override the global
+/// `limits` in settings.json. What is set nowhere is unlimited, except `max_steps` and
+/// `context_messages`, which have defaults.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct RunLimits {
+    /// Replies from the model, each of which may call a tool. Defaults to 20.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_steps: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tool_calls: Option<u32>,
+    /// Wall-clock time, checked before each reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_seconds: Option<u64>,
+    /// Prompt and completion tokens over all requests of the run, as reported by the provider.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u64>,
+    /// In USD, computed from the token counts with `input_price` and `output_price`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_cost: Option<f64>,
+    /// USD per million prompt tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_price: Option<f64>,
+    /// USD per million completion tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_price: Option<f64>,
+    /// How many of the most recent messages are sent along with the system prompt. Defaults to 45.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_messages: Option<usize>,
+}
+
+impl RunLimits {
+    /// These limits with the ones set in `other` taking precedence.
+    pub fn overridden_by(&self, other: &RunLimits) -> RunLimits {
+        RunLimits {
+            max_steps: other.max_steps.or(self.max_steps),
+            max_tool_calls: other.max_tool_calls.or(self.max_tool_calls),
+            max_seconds: other.max_seconds.or(self.max_seconds),
+            max_tokens: other.max_tokens.or(self.max_tokens),
+            max_cost: other.max_cost.or(self.max_cost),
+            input_price: other.input_price.or(self.input_price),
+            output_price: other.output_price.or(self.output_price),
+            context_messages: other.context_messages.or(self.context_messages),
+        }
+    }
+
+    /// Checks the resolved limits before a run. Budgets that would end the run before it starts
+    /// and prices that are negative are refused. A context window too small to hold the request
+    /// is raised to the minimum, and `max_cost` without prices is dropped, with a warning.
+    pub fn validated(mut self) -> Result<RunLimits, String> {
+        let budgets = [
+            ("max_steps", self.max_steps.map(u64::from)),
+            ("max_seconds", self.max_seconds),
+            ("max_tokens", self.max_tokens),
+        ];
+        for (name, value) in budgets {
+            if value == Some(0) {
+                return Err(format!("The limit {} must be at least 1", name));
+            }
+        }
+        if let Some(max) = self.max_cost.filter(|max| !(max.is_finite() && *max > 0.0)) {
+            return Err(format!("The limit max_cost must be above 0, not {}", max));
+        }
+        for (name, price) in [("input_price", self.input_price), ("output_price", self.output_price)] {
+            if let Some(price) = price.filter(|price| !(price.is_finite() && *price >= 0.0)) {
+                return Err(format!("The {} must be 0 or more, not {}", name, price));
+            }
+        }
+        if let Some(context) = self.context_messages.filter(|c| *c < MIN_CONTEXT_WINDOW_SIZE) {
+            warn!(context_messages = context, "context_messages is too small to keep the request; using {}", MIN_CONTEXT_WINDOW_SIZE);
+            self.context_messages = Some(MIN_CONTEXT_WINDOW_SIZE);
+        }
+        if self.max_cost.is_some() && self.input_price.is_none() && self.output_price.is_none() {
+            warn!("max_cost is set without input_price or output_price; ignoring it");
+            self.max_cost = None;
+        }
+        Ok(self)
+    }
+
+    /// The cost of `usage` in USD, if prices are set.
+    fn cost(&self, usage: &Usage) -> Option<f64> {
+        if self.input_price.is_none() && self.output_price.is_none() {
+            return None;
+        }
+        Some(
+            usage.prompt_tokens as f64 / 1e6 * self.input_price.unwrap_or(0.0)
+                + usage.completion_tokens as f64 / 1e6 * self.output_price.unwrap_or(0.0),
+        )
+    }
+
+    /// The budget that `usage` has used up, if any, phrased for the model.
+    fn reached(&self, usage: &Usage) -> Option<String> {
+        let max_steps = self.max_steps.unwrap_or(DEFAULT_MAX_STEPS);
+        if usage.iterations >= max_steps {
+            return Some(format!("the limit of {} steps", max_steps));
+        }
+        if let Some(max) = self.max_tool_calls.filter(|max| usage.tool_calls >= *max) {
+            return Some(format!("the limit of {} tool calls", max));
+        }
+        if let Some(max) = self.max_seconds.filter(|max| usage.started.elapsed().as_secs() >= *max) {
+            return Some(format!("the time limit of {} seconds", max));
+        }
+        if let Some(max) = self.max_tokens.filter(|max| usage.prompt_tokens + usage.completion_tokens >= *max) {
+            return Some(format!("the limit of {} tokens", max));
+        }
+        match (self.max_cost, self.cost(usage)) {
+            (Some(max), Some(cost)) if cost >= max => Some(format!("the cost limit of ${:.2}", max)),
+            _ => None,
+        }
+    }
+}
+
+/// What a run has used so far, checked against its `RunLimits`.
+struct Usage {
+    started: Instant,
+    iterations: u32,
+    tool_calls: u32,
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl Usage {
+    fn new() -> Self {
+        Usage {
+            started: Instant::now(),
+            iterations: 0,
+            tool_calls: 0,
+            prompt_tokens: 0,
+            completion_tokens: 0,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tool {
     pub server_name: String,
@@ -44,14 +204,41 @@ pub struct Tool {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(from = "RawToolCall")]
 struct ToolCall {
     tool_name: String,
     arguments: serde_json::Value,
+    #[serde(flatten)]
+    notes: StateNotes,
+}
+
+/// A tool call as sent by the model. Only `tool_name` and `arguments` have to be well-formed;
+/// the notes are read from the remaining fields leniently.
+#[derive(Deserialize)]
+struct RawToolCall {
+    tool_name: String,
+    arguments: serde_json::Value,
+    #[serde(flatten)]
+    rest: serde_json::Map<String, serde_json::Value>,
+}
+
+impl From<RawToolCall> for ToolCall {
+    fn from(raw: RawToolCall) -> Self {
+        ToolCall {
+            tool_name: raw.tool_name,
+            arguments: raw.arguments,
+            notes: StateNotes::lenient(&raw.rest),
+        }
+    }
 }
 
 // --- 新增：定义严格的工具调用响应格式 ---
 const TOOL_CALL_FORMAT_INSTRUCTION: &str = r#"To use a tool, you MUST respond with ONLY a single, valid JSON object containing two keys: 'tool_name' (string) and 'arguments' (object or null). Do not include any other text, markdown, or explanation, either before or after the JSON. Example: {"tool_name": "read_file", "arguments": {"path": "/path/to/file.txt"}}"#;
 
+// MCP calls are not idempotent, so only failures where the request never left this process are retried.
+const MAX_TOOL_SEND_ATTEMPTS: u32 = 3;
+const TOOL_RETRY_DELAY_MS: u64 = 500;
+
 pub struct Agent {}
 
 /// Extracts a JSON object from a string that might contain other text or markdown fences.
@@ -104,37 +291,112 @@ fn parse_strict_tool_call(response_text: &str) -> Result<ToolCall, String> {
     }
 }
 
+/// Calls an MCP tool, retrying only when the request could not be sent to the server.
+async fn call_tool_with_retry(
+    client: &rmcp::service::RunningService<rmcp::service::RoleClient, ()>,
+    param: CallToolRequestParam,
+) -> Result<CallToolResult, ServiceError> {
+    let mut attempt = 1;
+    loop {
+        match client.call_tool(param.clone()).await {
+            Err(ServiceError::TransportSend(e)) if attempt < MAX_TOOL_SEND_ATTEMPTS => {
+                warn!(tool_name = %param.name, attempt, "Failed to send tool call, retrying: {}", e);
+                tokio::time::sleep(Duration::from_millis(TOOL_RETRY_DELAY_MS * attempt as u64)).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Converts a stored chat message into a request message.
+///
+/// Tool calls are driven by the JSON protocol in `TOOL_CALL_FORMAT_INSTRUCTION` rather than the
+/// provider's native function calling, so tool results go back to the model as user messages,
+/// the same way the agent loop feeds them in.
+fn to_request_message(msg: &ChatMessage) -> ChatCompletionRequestMessage {
+    match msg.role {
+        Role::System => ChatCompletionRequestSystemMessageArgs::default()
+            .content(&*msg.content)
+            .build()
+            .unwrap()
+            .into(),
+        Role::User => ChatCompletionRequestUserMessageArgs::default()
+            .content(&*msg.content)
+            .build()
+            .unwrap()
+            .into(),
+        Role::Assistant => ChatCompletionRequestAssistantMessageArgs::default()
+            .content(&*msg.content)
+            .build()
+            .unwrap()
+            .into(),
+        Role::Tool => {
+            let tool_name = msg.tool_call.as_ref().map_or("unknown", |t| t.tool_name.as_str());
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(format!("Tool result for '{}':\n{}", tool_name, msg.content))
+                .build()
+                .unwrap()
+                .into()
+        }
+    }
+}
+
+/// The memories matching the latest prompt, formatted for the system prompt.
+fn relevant_memories(state: &AppState, history: &[ChatMessage], limit: usize) -> Option<String> {
+    let memory = state.memory.as_ref().filter(|_| limit > 0)?;
+    let prompt = history.iter().rev().find(|m| m.role == Role::User)?;
+    match memory.recall(&prompt.content, limit) {
+        Ok(entries) => memory::prompt_section(&entries),
+        Err(e) => {
+            warn!("Failed to recall memories: {}", e);
+            None
+        }
+    }
+}
 
 impl Agent {
     pub fn new() -> Self {
         Self {}
     }
 
-    #[instrument(skip(self, history, available_tools, state, window))]
+    /// Runs the agent loop and returns the final answer. With `options.plan` the task is planned
+    /// first and carried out step by step, see `Run::plan_and_execute`.
+    ///
+    /// Every tool call made along the way is appended to `steps` as an assistant message holding
+    /// the request followed by a `Tool` message holding the result, so the caller can persist the
+    /// trail even when the run ends in an error. `agent_state` is the session's working state; it
+    /// is updated as the run goes and the caller saves it with the session.
+    #[instrument(skip(self, history, available_tools, state, window, options, steps, agent_state))]
+    #[allow(clippy::too_many_arguments)]
     pub async fn run_task(
         &self,
+        session_id: &str,
         history: &[
             ChatMessage
         ],
         available_tools: Vec<Tool>,
         state: Arc<AppState>,
         window: &WebviewWindow,
-    ) -> Result<String, String> {
-        info!(num_messages = history.len(), num_tools = available_tools.len(), "Running agent task");
+        options: &RunOptions,
+        steps: &mut Vec<ChatMessage>,
+        agent_state: &mut AgentState,
+    ) -> Result<String, AgentError> {
+        info!(num_messages = history.len(), num_tools = available_tools.len(), model = %options.generation.model, plan = options.plan, "Running agent task");
 
         let config = state.config.lock().map_err(|e| format!("Failed to lock config: {}", e))?.clone();
         let mcp_clients_clone = state.mcp_clients.lock().map_err(|e| format!("Failed to lock mcp_clients: {}", e))?.clone();
         
         if config.openai.api_key.is_empty() {
-            return Err("OpenAI API key is not set in the configuration file.".to_string());
+            return Err(AgentError::new(
+                ErrorKind::Auth,
+                "OpenAI API key is not set in the configuration file.",
+            ));
         }
 
-        let openai_config = OpenAIConfig::new()
-            .with_api_key(config.openai.api_key)
-            .with_api_base(config.openai.base_url);
-        let openai_client = Client::with_config(openai_config);
+        let llm_client = LlmClient::new(config.openai.api_key, config.openai.base_url)?;
 
-        let system_prompt = if available_tools.is_empty() {
+        let mut system_prompt = if available_tools.is_empty() {
             "You are a helpful AI assistant.".to_string()
         } else {
             let tool_list_str = available_tools
@@ -145,10 +407,18 @@ impl Agent {
 
             // Combine tool list with strict format instruction and a stronger emphasis
             format!(
-                "You are a powerful AI assistant with a fixed set of capabilities provided by the following tools. You ALWAYS have access to these tools, regardless of the current conversation topic:\n\n{}\n\n{}",
-                tool_list_str, TOOL_CALL_FORMAT_INSTRUCTION
+                "You are a powerful AI assistant with a fixed set of capabilities provided by the following tools. You ALWAYS have access to these tools, regardless of the current conversation topic:\n\n{}\n\n{}\n\n{}",
+                tool_list_str, TOOL_CALL_FORMAT_INSTRUCTION, STATE_INSTRUCTION
             )
         };
+        if let Some(section) = agent_state.prompt_section() {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(&section);
+        }
+        if let Some(section) = relevant_memories(&state, history, config.memory.max_injected) {
+            system_prompt.push_str("\n\n");
+            system_prompt.push_str(&section);
+        }
 
         let mut messages: Vec<ChatCompletionRequestMessage> = vec![
             ChatCompletionRequestSystemMessageArgs::default()
@@ -159,79 +429,217 @@ impl Agent {
         ];
 
         for msg in history {
-            match msg.role.as_str() {
-                "user" => messages.push(
-                    ChatCompletionRequestUserMessageArgs::default()
-                        .content(&*msg.content)
-                        .build()
-                        .unwrap()
-                        .into(),
-                ),
-                "assistant" => messages.push(
-                    ChatCompletionRequestAssistantMessageArgs::default()
-                        .content(&*msg.content)
-                        .build()
-                        .unwrap()
-                        .into(),
-                ),
-                _ => (),
-            }
+            messages.push(to_request_message(msg));
         }
 
-        const MAX_ITERATIONS: u32 = 20;
-        // Increased context window size slightly to accommodate reminder messages
-        const CONTEXT_WINDOW_SIZE: usize = 45;
-
-        for i in 0..MAX_ITERATIONS {
-            info!(iteration = i + 1, "Agent loop iteration");
-
-            window
-                .emit(
-                    "agent_event",
-                    AgentEvent {
-                        status: AgentStatus::Thinking,
-                    },
-                )
-                .ok();
-
-            let final_messages = if messages.len() > CONTEXT_WINDOW_SIZE {
-                info!(
-                    "Message history length ({}) exceeds context window size ({}). Truncating.",
-                    messages.len(),
-                    CONTEXT_WINDOW_SIZE
-                );
-                let mut truncated_messages = vec![messages[0].clone()];
-                let recent_messages = messages.iter().skip(messages.len() - CONTEXT_WINDOW_SIZE);
-                truncated_messages.extend(recent_messages.cloned());
-                truncated_messages
-            } else {
-                messages.clone()
-            };
+        let mut run = Run {
+            session_id,
+            available_tools: &available_tools,
+            state: &state,
+            window,
+            generation: &options.generation,
+            limits: &options.limits,
+            llm_client,
+            mcp_clients: mcp_clients_clone,
+            steps,
+            agent_state,
+            usage: Usage::new(),
+        };
+        if options.plan {
+            return run.plan_and_execute(messages).await;
+        }
+        let outcome = run.tool_loop(&mut messages, None).await?;
+        run.answer_or_best_effort(&mut messages, outcome).await
+    }
+}
 
-            let request = CreateChatCompletionRequestArgs::default()
-                .model(config.openai.model.clone())
-                .messages(final_messages)
-                .build()
-                .map_err(|e| e.to_string())?;
+/// What one run of the agent works with, shared by the tool loop and the steps of a plan.
+struct Run<'a> {
+    session_id: &'a str,
+    available_tools: &'a [Tool],
+    state: &'a AppState,
+    window: &'a WebviewWindow,
+    generation: &'a GenerationInfo,
+    limits: &'a RunLimits,
+    llm_client: LlmClient,
+    mcp_clients: HashMap<String, Arc<rmcp::service::RunningService<rmcp::service::RoleClient, ()>>>,
+    steps: &'a mut Vec<ChatMessage>,
+    agent_state: &'a mut AgentState,
+    usage: Usage,
+}
+
+/// How a tool loop ended.
+enum LoopOutcome {
+    Answer(String),
+    /// The loop's own cap on replies ran out; holds it, phrased for the model.
+    OutOfSteps(String),
+    /// A budget of the whole run is used up; holds which one, phrased for the model.
+    LimitReached(String),
+}
+
+const DEFAULT_MAX_STEPS: u32 = 20;
+// Increased context window size slightly to accommodate reminder messages
+const CONTEXT_WINDOW_SIZE: usize = 45;
+// Smallest window we shrink to after a context overflow before giving up.
+const MIN_CONTEXT_WINDOW_SIZE: usize = 4;
+
+fn user_message(content: String) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestUserMessageArgs::default()
+        .content(content)
+        .build()
+        .unwrap()
+        .into()
+}
 
-            let response = openai_client
-                .chat()
-                .create(request)
-                .await
-                .map_err(|e| e.to_string())?;
+fn assistant_message(content: String) -> ChatCompletionRequestMessage {
+    ChatCompletionRequestAssistantMessageArgs::default()
+        .content(content)
+        .build()
+        .unwrap()
+        .into()
+}
 
-            let assistant_message = response
-                .choices
-                .get(0)
-                .and_then(|choice| choice.message.content.clone())
-                .unwrap_or_else(|| "No response received".to_string());
+fn limit_notice(reason: &str) -> String {
+    format!("You have reached {} for this task, so no more tools can be used.", reason)
+}
+
+impl Run<'_> {
+    fn context_messages(&self) -> usize {
+        self.limits.context_messages.unwrap_or(CONTEXT_WINDOW_SIZE)
+    }
+
+    /// The budget of the run that is used up, if any, phrased for the model.
+    fn limit_reached(&self) -> Option<String> {
+        self.limits.reached(&self.usage)
+    }
+
+    /// Returns the answer of a tool loop. If the loop was stopped by a limit, asks the model for
+    /// the best answer it can give from what it has gathered, rather than failing the run.
+    async fn answer_or_best_effort(
+        &mut self,
+        messages: &mut Vec<ChatCompletionRequestMessage>,
+        outcome: LoopOutcome,
+    ) -> Result<String, AgentError> {
+        let reason = match outcome {
+            LoopOutcome::Answer(answer) => return Ok(answer),
+            LoopOutcome::OutOfSteps(reason) | LoopOutcome::LimitReached(reason) => reason,
+        };
+        warn!(%reason, "Stopping the run, asking for a best-effort answer");
+        messages.push(user_message(format!(
+            "{} Give your best final answer now from the results gathered so far, and say briefly what is missing or uncertain.",
+            limit_notice(&reason)
+        )));
+        self.chat(messages, self.context_messages()).await
+    }
+
+    /// Sends the system prompt and the last `context_window_size` other messages to the model and
+    /// returns its reply.
+    async fn chat(&mut self, messages: &[ChatCompletionRequestMessage], context_window_size: usize) -> Result<String, AgentError> {
+        self.window
+            .emit(
+                "agent_event",
+                AgentEvent {
+                    status: AgentStatus::Thinking,
+                },
+            )
+            .ok();
+
+        let final_messages = if messages.len() > context_window_size {
+            info!(
+                "Message history length ({}) exceeds context window size ({}). Truncating.",
+                messages.len(),
+                context_window_size
+            );
+            let mut truncated_messages = vec![messages[0].clone()];
+            let recent_messages = messages.iter().skip(messages.len() - context_window_size);
+            truncated_messages.extend(recent_messages.cloned());
+            truncated_messages
+        } else {
+            messages.to_vec()
+        };
+
+        let mut request_args = CreateChatCompletionRequestArgs::default();
+        request_args.model(self.generation.model.clone()).messages(final_messages);
+        if let Some(temperature) = self.generation.temperature {
+            request_args.temperature(temperature);
+        }
+        let request = request_args
+            .build()
+            .map_err(|e| AgentError::new(ErrorKind::InvalidRequest, e.to_string()))?;
+
+        let response = self
+            .llm_client
+            .chat(&request, |error, attempt, delay| {
+                self.window
+                    .emit(
+                        "agent_event",
+                        AgentEvent {
+                            status: AgentStatus::Retrying {
+                                attempt,
+                                delay_ms: delay.as_millis() as u64,
+                                error: error.clone(),
+                            },
+                        },
+                    )
+                    .ok();
+            })
+            .await?;
+        if let Some(usage) = &response.usage {
+            self.usage.prompt_tokens += u64::from(usage.prompt_tokens);
+            self.usage.completion_tokens += u64::from(usage.completion_tokens);
+        }
+
+        Ok(response
+            .choices
+            .first()
+            .and_then(|choice| choice.message.content.clone())
+            .unwrap_or_else(|| "No response received".to_string()))
+    }
+
+    /// Lets the model call tools until it answers, a limit of the run is reached or, if given,
+    /// `max_iterations` replies have been used.
+    async fn tool_loop(
+        &mut self,
+        messages: &mut Vec<ChatCompletionRequestMessage>,
+        max_iterations: Option<u32>,
+    ) -> Result<LoopOutcome, AgentError> {
+        let available_tools = self.available_tools;
+        let mut context_window_size = self.context_messages();
+
+        let mut i = 0;
+        loop {
+            if let Some(max) = max_iterations.filter(|max| i >= *max) {
+                return Ok(LoopOutcome::OutOfSteps(format!("the limit of {} replies for this step", max)));
+            }
+            if let Some(reason) = self.limit_reached() {
+                return Ok(LoopOutcome::LimitReached(reason));
+            }
+            info!(iteration = self.usage.iterations + 1, "Agent loop iteration");
+
+            let assistant_message = match self.chat(messages, context_window_size).await {
+                Ok(assistant_message) => assistant_message,
+                // Resending the same request is pointless, but a smaller window of recent
+                // messages may fit. Does not count as an iteration.
+                Err(e) if e.kind == ErrorKind::ContextOverflow
+                    && context_window_size > MIN_CONTEXT_WINDOW_SIZE
+                    && messages.len() > MIN_CONTEXT_WINDOW_SIZE =>
+                {
+                    context_window_size = (context_window_size.min(messages.len()) / 2).max(MIN_CONTEXT_WINDOW_SIZE);
+                    warn!(context_window_size, "Context overflow, retrying with a smaller window: {}", e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            i += 1;
+            self.usage.iterations += 1;
 
             // --- 改进：使用严格的工具调用解析 ---
             match parse_strict_tool_call(&assistant_message) {
                 Ok(tool_call) => {
                     // --- 如果解析成功，表示是工具调用 ---
                     info!(tool_name = %tool_call.tool_name, "LLM requested a tool call (strict format matched)");
-                    window
+                    self.agent_state.apply_notes(&tool_call.notes);
+                    self.window
                         .emit(
                             "agent_event",
                             AgentEvent {
@@ -245,41 +653,103 @@ impl Agent {
                     let tool_info = available_tools
                         .iter()
                         .find(|t| t.tool_name == tool_call.tool_name)
-                        .ok_or_else(|| format!("Tool '{}' not found.", tool_call.tool_name))?;
-                    let mcp_client = mcp_clients_clone
-                        .get(&tool_info.server_name)
-                        .ok_or_else(|| format!("MCP client for server '{}' not found or not running.", tool_info.server_name))?;
+                        .ok_or_else(|| AgentError::new(ErrorKind::Tool, format!("Tool '{}' not found.", tool_call.tool_name)))?;
+                    // Built-in tools run in process; every other tool belongs to an MCP server.
+                    let mcp_client = if tool_info.server_name == tools::SERVER_NAME {
+                        None
+                    } else {
+                        Some(self.mcp_clients
+                            .get(&tool_info.server_name)
+                            .ok_or_else(|| AgentError::new(ErrorKind::Tool, format!("MCP client for server '{}' not found or not running.", tool_info.server_name)))?)
+                    };
 
                     info!(tool_name = %tool_call.tool_name, args = ?tool_call.arguments, "Executing tool");
-                    let arguments_object: Option<JsonObject> = match tool_call.arguments {
-                        serde_json::Value::Object(map) => Some(map),
-                        serde_json::Value::Null => None,
-                        _ => {
-                            warn!("Tool arguments for '{}' are not a JSON object or null. Arguments: {}", tool_call.tool_name, tool_call.arguments);
-                            None
-                        }
+                    let call_info = ToolCallInfo {
+                        tool_name: tool_call.tool_name.clone(),
+                        arguments: tool_call.arguments.clone(),
+                        status: None,
+                        duration_ms: None,
                     };
-                    let tool_name_cow: Cow<'static, str> = Cow::Owned(tool_call.tool_name.clone());
-                    
-                    let param = CallToolRequestParam {
-                        name: tool_name_cow,
-                        arguments: arguments_object,
-                    };
-
-                    let tool_result = mcp_client
-                        .as_ref()
-                        .call_tool(param)
-                        .await;
-
-                    let result_str = match tool_result {
-                        Ok(call_result) => {
-                            serde_json::to_string(&call_result).unwrap_or_else(|e| format!("Failed to serialize tool result: {}", e))
+                    let request_step = ChatMessage::new(Role::Assistant, assistant_message.clone())
+                        .with_tool_call(call_info.clone());
+                    self.window.emit("agent_step", &request_step).ok();
+                    self.steps.push(request_step);
+
+                    let started = Instant::now();
+                    let repeats_failure = self.agent_state.has_failed(&tool_call.tool_name, &tool_call.arguments);
+                    // Tool failures are reported back to the model rather than aborting the run,
+                    // so it can pick another approach.
+                    let (result_str, status) = match mcp_client {
+                        _ if repeats_failure => {
+                            warn!(tool_name = %tool_call.tool_name, "Refusing to repeat a failed tool call");
+                            (
+                                "Refused: this exact call already failed earlier in this run. Change the arguments or try another approach.".to_string(),
+                                ToolCallStatus::Error,
+                            )
+                        }
+                        None => {
+                            let context = tools::ToolContext { state: self.state, session_id: self.session_id };
+                            // Built-in tools read files and the search index synchronously; keep
+                            // them from stalling the other tasks on this worker thread.
+                            let result = tokio::task::block_in_place(|| {
+                                self.state.builtin_tools.call(&tool_call.tool_name, &tool_call.arguments, &context)
+                            });
+                            match result {
+                                Ok(result_str) => (result_str, ToolCallStatus::Success),
+                                Err(e) => {
+                                    warn!(tool_name = %tool_call.tool_name, "Tool execution failed: {}", e);
+                                    (format!("Tool execution failed: {}", e), ToolCallStatus::Error)
+                                }
+                            }
                         }
-                        Err(service_error) => {
-                            format!("Tool execution failed: {:?}", service_error)
+                        Some(mcp_client) => {
+                            let arguments_object: Option<JsonObject> = match tool_call.arguments {
+                                serde_json::Value::Object(map) => Some(map),
+                                serde_json::Value::Null => None,
+                                _ => {
+                                    warn!("Tool arguments for '{}' are not a JSON object or null. Arguments: {}", tool_call.tool_name, tool_call.arguments);
+                                    None
+                                }
+                            };
+                            let tool_name_cow: Cow<'static, str> = Cow::Owned(tool_call.tool_name.clone());
+
+                            let param = CallToolRequestParam {
+                                name: tool_name_cow,
+                                arguments: arguments_object,
+                            };
+
+                            match call_tool_with_retry(mcp_client.as_ref(), param).await {
+                                Ok(call_result) => {
+                                    let status = if call_result.is_error == Some(true) {
+                                        ToolCallStatus::Error
+                                    } else {
+                                        ToolCallStatus::Success
+                                    };
+                                    let result_str = serde_json::to_string(&call_result).unwrap_or_else(|e| format!("Failed to serialize tool result: {}", e));
+                                    (result_str, status)
+                                }
+                                Err(service_error) => {
+                                    let error = AgentError::from_service_error(&tool_call.tool_name, &service_error);
+                                    warn!(tool_name = %tool_call.tool_name, kind = ?error.kind, "Tool execution failed: {}", error.message);
+                                    (format!("Tool execution failed: {}", error), ToolCallStatus::Error)
+                                }
+                            }
                         }
                     };
-                    info!(tool_name = %tool_call.tool_name, result = %result_str, "Tool execution finished");
+                    let duration_ms = started.elapsed().as_millis() as u64;
+                    info!(tool_name = %tool_call.tool_name, result = %result_str, duration_ms, "Tool execution finished");
+                    if !repeats_failure {
+                        self.usage.tool_calls += 1;
+                        self.agent_state.record_tool_call(&call_info.tool_name, &call_info.arguments, status, &result_str, crate::now_ts());
+                    }
+
+                    let result_step = ChatMessage::new(Role::Tool, result_str.clone()).with_tool_call(ToolCallInfo {
+                        status: Some(status),
+                        duration_ms: Some(duration_ms),
+                        ..call_info
+                    });
+                    self.window.emit("agent_step", &result_step).ok();
+                    self.steps.push(result_step);
 
                     messages.push(
                         ChatCompletionRequestAssistantMessageArgs::default()
@@ -321,11 +791,203 @@ impl Agent {
                     // If it was a successful non-tool call, we proceed to return the message.
                     // The logic to return the final answer remains unchanged.
                     info!("LLM provided a final answer or an unparseable non-tool-call response.");
-                    return Ok(assistant_message); // Return the message as-is (could be final answer or garbled text)
+                    return Ok(LoopOutcome::Answer(assistant_message)); // Return the message as-is (could be final answer or garbled text)
+                }
+            }
+        }
+    }
+
+    /// Plan-and-execute mode: asks for a plan, works through its steps with the tool loop and
+    /// replans when a step fails, then answers from what the steps found. The plan lives in
+    /// `AppState::plans` while the run lasts and is shown again after every change.
+    async fn plan_and_execute(
+        &mut self,
+        mut messages: Vec<ChatCompletionRequestMessage>,
+    ) -> Result<String, AgentError> {
+        messages.push(user_message(plan::planning_prompt()));
+        let reply = self.chat(&messages, self.context_messages()).await?;
+        let steps = match plan::parse_steps(&reply) {
+            Ok(steps) => steps,
+            Err(e) => {
+                warn!("No usable plan, running the task directly: {}", e);
+                messages.pop();
+                let outcome = self.tool_loop(&mut messages, None).await?;
+                return self.answer_or_best_effort(&mut messages, outcome).await;
+            }
+        };
+        messages.push(assistant_message(reply));
+        self.state
+            .plans
+            .lock()
+            .map_err(|e| format!("Failed to lock plans: {}", e))?
+            .insert(self.session_id.to_string(), Plan::new(steps));
+        self.update_plan(|_| ())?;
+
+        // Set when a budget of the run stops the plan early.
+        let mut limit = None;
+        // The user may edit the pending steps meanwhile, so the next one is looked up each time.
+        loop {
+            if let Some(reason) = self.limit_reached() {
+                limit = Some(reason);
+                break;
+            }
+            let Some((index, prompt)) =
+                self.update_plan(|plan| plan.start_next().map(|i| (i, plan::step_prompt(plan, i))))?
+            else {
+                break;
+            };
+            info!(step = index + 1, "Running plan step");
+            messages.push(user_message(prompt));
+            let (reply, failure) = match self.tool_loop(&mut messages, Some(plan::MAX_STEP_ITERATIONS)).await? {
+                LoopOutcome::Answer(reply) => {
+                    let failure = plan::step_failure(&reply).map(str::to_string);
+                    messages.push(assistant_message(reply.clone()));
+                    (reply, failure)
+                }
+                LoopOutcome::OutOfSteps(_) => (String::new(), Some("The step needed too many tool calls.".to_string())),
+                LoopOutcome::LimitReached(reason) => {
+                    self.update_plan(|plan| plan.finish(index, StepStatus::Failed, &format!("Stopped at {}.", reason)))?;
+                    limit = Some(reason);
+                    break;
+                }
+            };
+            let Some(reason) = failure else {
+                self.update_plan(|plan| plan.finish(index, StepStatus::Done, &reply))?;
+                continue;
+            };
+
+            warn!(step = index + 1, "Plan step failed: {}", reason);
+            let replanning = self.update_plan(|plan| {
+                plan.finish(index, StepStatus::Failed, &reason);
+                (plan.revisions < plan::MAX_REPLANS).then(|| plan::replanning_prompt(plan))
+            })?;
+            let Some(prompt) = replanning else {
+                break;
+            };
+            messages.push(user_message(prompt));
+            let reply = self.chat(&messages, self.context_messages()).await?;
+            match plan::parse_steps(&reply) {
+                Ok(steps) => {
+                    messages.push(assistant_message(reply));
+                    self.update_plan(|plan| {
+                        plan.replace_pending(steps);
+                        plan.revisions += 1;
+                    })?;
+                }
+                Err(e) => {
+                    warn!("No usable revised plan: {}", e);
+                    messages.pop();
+                    break;
                 }
             }
         }
 
-        Err("Agent exceeded maximum iterations.".to_string())
+        // Steps still pending here were given up on after a failure or a limit.
+        let plan = self.update_plan(|plan| {
+            plan.status = if plan.steps.iter().all(|s| s.status == StepStatus::Done) {
+                PlanStatus::Done
+            } else {
+                PlanStatus::Failed
+            };
+            plan.clone()
+        })?;
+        let mut prompt = plan::final_prompt(&plan);
+        if let Some(reason) = &limit {
+            warn!(%reason, "Stopping the plan, asking for a best-effort answer");
+            prompt = format!("{} {}", limit_notice(reason), prompt);
+        }
+        messages.push(user_message(prompt));
+        self.chat(&messages, self.context_messages()).await
+    }
+
+    /// Applies `change` to the plan of this run and sends the result to the UI.
+    fn update_plan<T>(&self, change: impl FnOnce(&mut Plan) -> T) -> Result<T, String> {
+        let mut plans = self.state.plans.lock().map_err(|e| format!("Failed to lock plans: {}", e))?;
+        let plan = plans
+            .get_mut(self.session_id)
+            .ok_or_else(|| "The plan of this run is missing".to_string())?;
+        let result = change(plan);
+        self.window
+            .emit(
+                "agent_plan",
+                PlanEvent {
+                    session_id: self.session_id.to_string(),
+                    plan: plan.clone(),
+                },
+            )
+            .ok();
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_notes_do_not_break_a_tool_call() {
+        let call = parse_strict_tool_call(
+            r#"{"tool_name": "read_file", "arguments": {"path": "/a"}, "goals": ["Read a"], "facts": ["not", "an", "object"]}"#,
+        )
+        .unwrap();
+        assert_eq!(call.tool_name, "read_file");
+        assert_eq!(call.arguments["path"], "/a");
+        assert_eq!(call.notes.goals, vec!["Read a"]);
+        assert!(call.notes.facts.is_empty());
+        assert!(parse_strict_tool_call(r#"{"tool_name": 1, "arguments": {}}"#).is_err());
+    }
+
+    #[test]
+    fn request_limits_override_profile_limits() {
+        let global = RunLimits { max_steps: Some(20), max_cost: Some(1.0), ..RunLimits::default() };
+        let profile = RunLimits { max_steps: Some(50), input_price: Some(3.0), ..RunLimits::default() };
+        let request = RunLimits { max_steps: Some(5), ..RunLimits::default() };
+        let resolved = global.overridden_by(&profile).overridden_by(&request);
+        assert_eq!(
+            resolved,
+            RunLimits { max_steps: Some(5), max_cost: Some(1.0), input_price: Some(3.0), ..RunLimits::default() }
+        );
+    }
+
+    #[test]
+    fn invalid_limits_are_refused_or_adjusted() {
+        assert!(RunLimits { max_steps: Some(0), ..RunLimits::default() }.validated().is_err());
+        assert!(RunLimits { max_cost: Some(-1.0), input_price: Some(1.0), ..RunLimits::default() }.validated().is_err());
+        assert!(RunLimits { output_price: Some(f64::NAN), ..RunLimits::default() }.validated().is_err());
+
+        let limits = RunLimits { context_messages: Some(1), max_cost: Some(1.0), ..RunLimits::default() }
+            .validated()
+            .unwrap();
+        assert_eq!(limits.context_messages, Some(MIN_CONTEXT_WINDOW_SIZE));
+        assert_eq!(limits.max_cost, None);
+        let zero_tools = RunLimits { max_tool_calls: Some(0), ..RunLimits::default() };
+        assert_eq!(zero_tools.clone().validated(), Ok(zero_tools));
+    }
+
+    #[test]
+    fn limits_report_the_budget_used_up() {
+        let mut usage = Usage::new();
+        let limits = RunLimits {
+            max_tool_calls: Some(3),
+            max_tokens: Some(10_000),
+            max_cost: Some(0.05),
+            input_price: Some(10.0),
+            ..RunLimits::default()
+        };
+        assert_eq!(limits.reached(&usage), None);
+
+        usage.prompt_tokens = 5_000;
+        assert_eq!(limits.reached(&usage).as_deref(), Some("the cost limit of $0.05"));
+        usage.completion_tokens = 5_000;
+        assert_eq!(limits.reached(&usage).as_deref(), Some("the limit of 10000 tokens"));
+        usage.tool_calls = 3;
+        assert_eq!(limits.reached(&usage).as_deref(), Some("the limit of 3 tool calls"));
+        usage.iterations = DEFAULT_MAX_STEPS;
+        assert_eq!(limits.reached(&usage).as_deref(), Some("the limit of 20 steps"));
+
+        usage = Usage::new();
+        usage.started -= Duration::from_secs(61);
+        let timed = RunLimits { max_seconds: Some(60), ..RunLimits::default() };
+        assert_eq!(timed.reached(&usage).as_deref(), Some("the time limit of 60 seconds"));
     }
 }
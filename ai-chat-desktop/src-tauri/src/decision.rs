@@ -0,0 +1,338 @@
+//! The agent's working state for a chat, so that each step and each follow-up builds on what is
+//! already known instead of re-deriving it (see 增量决策.md).
+//!
+//! The model declares its goals and the facts it has established in optional fields of its tool
+//! calls. The agent loop records every tool call and refuses exact repeats of calls that failed
+//! earlier in the same run. The state is saved with the session and summarized in the system
+//! prompt of the next run on the same branch; answering on another branch starts afresh.
+
+use crate::history::ToolCallStatus;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+/// Added to the tool call instruction when tools are available.
+pub const STATE_INSTRUCTION: &str = r#"A tool call may also carry notes on your progress, which are kept for the rest of this chat: 'goals' (list of strings: what you are working towards), 'completed_goals' (list of strings: goals you have finished) and 'facts' (object mapping a short key to something you have established; null removes it). Example: {"tool_name": "read_file", "arguments": {"path": "/app/config.toml"}, "goals": ["Find why the server fails to start"], "facts": {"config_path": "/app/config.toml"}}. A call that failed is refused if repeated with the same arguments: change them or try something else."#;
+
+const MAX_TOOL_HISTORY: usize = 50;
+const MAX_FAILED_ATTEMPTS: usize = 100;
+const MAX_GOALS: usize = 20;
+const MAX_FACTS: usize = 50;
+// Tool results are kept as a short summary; the full result is in the session's messages.
+const SUMMARY_CHARS: usize = 200;
+// How much of the history goes into the system prompt.
+const PROMPT_TOOL_CALLS: usize = 10;
+const PROMPT_COMPLETED_GOALS: usize = 5;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct AgentState {
+    /// What the model has established, by key.
+    #[serde(default)]
+    pub known_facts: BTreeMap<String, String>,
+    /// The most recent tool calls, oldest first.
+    #[serde(default)]
+    pub tool_history: Vec<ToolExecution>,
+    #[serde(default)]
+    pub goals: Vec<Goal>,
+    /// Calls that failed in the current run, as `attempt_key`s, oldest first.
+    #[serde(default)]
+    pub failed_attempts: Vec<String>,
+    /// The prompt answered by the run that last updated the state; the state belongs to the
+    /// branches through it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leaf: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ToolExecution {
+    pub tool_name: String,
+    pub arguments: Value,
+    pub status: ToolCallStatus,
+    /// The start of the result.
+    pub summary: String,
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Goal {
+    pub description: String,
+    pub status: GoalStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GoalStatus {
+    Open,
+    Done,
+}
+
+/// The progress notes a tool call may carry, see `STATE_INSTRUCTION`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StateNotes {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub goals: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub completed_goals: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub facts: BTreeMap<String, Value>,
+}
+
+impl StateNotes {
+    /// Reads the notes from the other fields of a tool call. A malformed note is dropped with a
+    /// warning instead of failing the call it came with.
+    pub fn lenient(fields: &Map<String, Value>) -> Self {
+        fn read<T: serde::de::DeserializeOwned + Default>(fields: &Map<String, Value>, name: &str) -> T {
+            let Some(value) = fields.get(name) else {
+                return T::default();
+            };
+            serde_json::from_value(value.clone()).unwrap_or_else(|e| {
+                warn!(field = name, %value, "Ignoring malformed note in tool call: {}", e);
+                T::default()
+            })
+        }
+        StateNotes {
+            goals: read(fields, "goals"),
+            completed_goals: read(fields, "completed_goals"),
+            facts: read(fields, "facts"),
+        }
+    }
+}
+
+/// Identifies a call by tool and arguments, independent of the order of object keys.
+pub fn attempt_key(tool_name: &str, arguments: &Value) -> String {
+    format!("{}:{}", tool_name, canonical_json(arguments))
+}
+
+fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let entries: Vec<String> = entries
+                .into_iter()
+                .map(|(k, v)| format!("{}:{}", Value::String(k.clone()), canonical_json(v)))
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+        Value::Array(items) => format!("[{}]", items.iter().map(canonical_json).collect::<Vec<_>>().join(",")),
+        _ => value.to_string(),
+    }
+}
+
+fn same_goal(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+impl AgentState {
+    pub fn is_empty(&self) -> bool {
+        *self == AgentState::default()
+    }
+
+    /// Prepares the state for a run answering on `branch`, the message ids from the root to the
+    /// prompt. A state built on another branch is dropped. Failed calls are forgotten, as what
+    /// failed in an earlier run (a missing file, a server that was down) may work now.
+    pub fn begin_run<'a>(&mut self, mut branch: impl Iterator<Item = &'a str>) {
+        if let Some(leaf) = &self.leaf {
+            if !branch.any(|id| id == leaf) {
+                *self = AgentState::default();
+            }
+        }
+        self.failed_attempts.clear();
+    }
+
+    /// Whether this exact call already failed.
+    pub fn has_failed(&self, tool_name: &str, arguments: &Value) -> bool {
+        let key = attempt_key(tool_name, arguments);
+        self.failed_attempts.contains(&key)
+    }
+
+    pub fn record_tool_call(&mut self, tool_name: &str, arguments: &Value, status: ToolCallStatus, result: &str, now: u64) {
+        self.tool_history.push(ToolExecution {
+            tool_name: tool_name.to_string(),
+            arguments: arguments.clone(),
+            status,
+            summary: result.chars().take(SUMMARY_CHARS).collect(),
+            timestamp: now,
+        });
+        if self.tool_history.len() > MAX_TOOL_HISTORY {
+            self.tool_history.remove(0);
+        }
+        if status == ToolCallStatus::Error && !self.has_failed(tool_name, arguments) {
+            self.failed_attempts.push(attempt_key(tool_name, arguments));
+            if self.failed_attempts.len() > MAX_FAILED_ATTEMPTS {
+                self.failed_attempts.remove(0);
+            }
+        }
+    }
+
+    /// Merges the notes from a tool call: new goals are added, finished ones marked done and
+    /// facts overwritten by key.
+    pub fn apply_notes(&mut self, notes: &StateNotes) {
+        for description in notes.goals.iter().filter(|d| !d.trim().is_empty()) {
+            match self.goals.iter_mut().find(|g| same_goal(&g.description, description)) {
+                Some(goal) => goal.status = GoalStatus::Open,
+                None => self.goals.push(Goal {
+                    description: description.trim().to_string(),
+                    status: GoalStatus::Open,
+                }),
+            }
+        }
+        for description in &notes.completed_goals {
+            if let Some(goal) = self.goals.iter_mut().find(|g| same_goal(&g.description, description)) {
+                goal.status = GoalStatus::Done;
+            }
+        }
+        // Finished goals make room first.
+        while self.goals.len() > MAX_GOALS {
+            let oldest = self.goals.iter().position(|g| g.status == GoalStatus::Done).unwrap_or(0);
+            self.goals.remove(oldest);
+        }
+
+        for (key, value) in &notes.facts {
+            let value = match value {
+                Value::Null => None,
+                Value::String(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+                other => Some(other.to_string()),
+            };
+            match value {
+                None => {
+                    self.known_facts.remove(key);
+                }
+                Some(value) if self.known_facts.contains_key(key) || self.known_facts.len() < MAX_FACTS => {
+                    self.known_facts.insert(key.clone(), value);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// The state as a system prompt section, or `None` if there is nothing to carry over.
+    pub fn prompt_section(&self) -> Option<String> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut section = "Your working state in this chat, carried over from earlier turns:".to_string();
+        let open: Vec<&Goal> = self.goals.iter().filter(|g| g.status == GoalStatus::Open).collect();
+        if !open.is_empty() {
+            section.push_str("\nOpen goals:");
+            for goal in open {
+                section.push_str(&format!("\n- {}", goal.description));
+            }
+        }
+        let done: Vec<&Goal> = self.goals.iter().filter(|g| g.status == GoalStatus::Done).collect();
+        if !done.is_empty() {
+            section.push_str("\nCompleted goals:");
+            for goal in done.iter().skip(done.len().saturating_sub(PROMPT_COMPLETED_GOALS)) {
+                section.push_str(&format!("\n- {}", goal.description));
+            }
+        }
+        if !self.known_facts.is_empty() {
+            section.push_str("\nKnown facts:");
+            for (key, value) in &self.known_facts {
+                section.push_str(&format!("\n- {}: {}", key, value));
+            }
+        }
+        if !self.tool_history.is_empty() {
+            section.push_str("\nRecent tool calls:");
+            let start = self.tool_history.len().saturating_sub(PROMPT_TOOL_CALLS);
+            for call in &self.tool_history[start..] {
+                let status = match call.status {
+                    ToolCallStatus::Success => "ok",
+                    ToolCallStatus::Error => "failed",
+                };
+                section.push_str(&format!(
+                    "\n- {} {} ({}): {}",
+                    call.tool_name,
+                    call.arguments,
+                    status,
+                    call.summary.replace('\n', " ")
+                ));
+            }
+        }
+        if !self.failed_attempts.is_empty() {
+            section.push_str(&format!(
+                "\n{} failed call(s) will be refused if repeated with the same arguments.",
+                self.failed_attempts.len()
+            ));
+        }
+        Some(section)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn failed_calls_are_recognized_regardless_of_key_order() {
+        let mut state = AgentState::default();
+        let args = json!({"path": "/tmp/a", "options": {"x": 1, "y": [1, 2]}});
+        state.record_tool_call("read_file", &args, ToolCallStatus::Success, "ok", 1);
+        assert!(!state.has_failed("read_file", &args));
+
+        state.record_tool_call("read_file", &args, ToolCallStatus::Error, "No such file", 2);
+        state.record_tool_call("read_file", &args, ToolCallStatus::Error, "No such file", 3);
+        assert_eq!(state.failed_attempts.len(), 1);
+        let reordered: Value = serde_json::from_str(r#"{"options": {"y": [1, 2], "x": 1}, "path": "/tmp/a"}"#).unwrap();
+        assert!(state.has_failed("read_file", &reordered));
+        assert!(!state.has_failed("read_file", &json!({"path": "/tmp/b"})));
+        assert!(!state.has_failed("list_directory", &args));
+        assert_eq!(state.tool_history.len(), 3);
+    }
+
+    #[test]
+    fn malformed_notes_are_dropped_one_by_one() {
+        let fields = json!({"goals": ["Find the bug"], "facts": "port is 8080", "completed_goals": [1]});
+        let notes = StateNotes::lenient(fields.as_object().unwrap());
+        assert_eq!(notes.goals, vec!["Find the bug"]);
+        assert!(notes.completed_goals.is_empty());
+        assert!(notes.facts.is_empty());
+    }
+
+    #[test]
+    fn runs_forget_failures_and_states_of_other_branches() {
+        let mut state = AgentState::default();
+        state.record_tool_call("read_file", &json!({"path": "/a"}), ToolCallStatus::Error, "No such file", 1);
+        state.known_facts.insert("port".to_string(), "8080".to_string());
+        state.leaf = Some("prompt".to_string());
+
+        state.begin_run(["prompt", "reply", "follow-up"].into_iter());
+        assert!(state.failed_attempts.is_empty());
+        assert_eq!(state.known_facts.len(), 1);
+
+        state.begin_run(["edited prompt"].into_iter());
+        assert!(state.is_empty());
+    }
+
+    #[test]
+    fn notes_track_goals_and_facts() {
+        let mut state = AgentState::default();
+        let notes: StateNotes = serde_json::from_value(json!({
+            "goals": ["Find the bug", "Write a fix"],
+            "facts": {"port": 8080, "host": "localhost"}
+        }))
+        .unwrap();
+        state.apply_notes(&notes);
+        state.apply_notes(&StateNotes {
+            goals: vec!["find the bug ".to_string()],
+            completed_goals: vec!["Find the bug".to_string()],
+            facts: BTreeMap::from([("host".to_string(), Value::Null)]),
+        });
+        assert_eq!(
+            state.goals,
+            vec![
+                Goal { description: "Find the bug".to_string(), status: GoalStatus::Done },
+                Goal { description: "Write a fix".to_string(), status: GoalStatus::Open },
+            ]
+        );
+        assert_eq!(state.known_facts, BTreeMap::from([("port".to_string(), "8080".to_string())]));
+
+        let section = state.prompt_section().unwrap();
+        assert!(section.contains("Open goals:\n- Write a fix"));
+        assert!(section.contains("Completed goals:\n- Find the bug"));
+        assert!(section.contains("- port: 8080"));
+        assert_eq!(AgentState::default().prompt_section(), None);
+    }
+}
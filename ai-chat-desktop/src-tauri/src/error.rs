@@ -0,0 +1,154 @@
+//! Typed errors for agent runs, shared by the LLM transport, tool calls and Tauri commands.
+
+use rmcp::service::ServiceError;
+use serde::Serialize;
+use std::fmt;
+
+/// Broad category of a failure. The frontend switches on this to decide how to present it.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    /// The provider throttled us (HTTP 429).
+    RateLimit,
+    /// Timeouts, dropped connections and 5xx responses.
+    Network,
+    /// Missing or rejected credentials, or an exhausted quota.
+    Auth,
+    /// The provider rejected the request itself (bad model name, malformed payload, ...).
+    InvalidRequest,
+    /// The prompt is larger than the model's context window.
+    ContextOverflow,
+    /// An MCP tool call failed.
+    Tool,
+    /// Anything else: lock poisoning, serialization bugs, missing state.
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// HTTP status code, when the error came from the provider.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Delay requested by the provider through `Retry-After`, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl AgentError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            status: None,
+            retry_after_ms: None,
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Internal, message)
+    }
+
+    /// Whether repeating the exact same request may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.kind, ErrorKind::RateLimit | ErrorKind::Network)
+    }
+
+    /// Classifies a non-success HTTP response from an OpenAI-compatible endpoint.
+    pub fn from_http(status: u16, body: &str, retry_after_ms: Option<u64>) -> Self {
+        // OpenAI-style bodies look like {"error": {"message", "type", "code"}}; other providers
+        // return plain text, so fall back to the raw body.
+        let parsed: Option<serde_json::Value> = serde_json::from_str(body).ok();
+        let error_obj = parsed.as_ref().and_then(|v| v.get("error"));
+        let message = error_obj
+            .and_then(|e| e.get("message").or(Some(e)))
+            .and_then(|m| m.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| body.trim().to_string());
+        let code = error_obj
+            .and_then(|e| e.get("code").or_else(|| e.get("type")))
+            .and_then(|c| c.as_str())
+            .unwrap_or_default();
+
+        let lower = message.to_lowercase();
+        let kind = if code == "context_length_exceeded"
+            || lower.contains("maximum context length")
+            || lower.contains("context window")
+            || lower.contains("too many tokens")
+        {
+            ErrorKind::ContextOverflow
+        } else if code == "insufficient_quota" {
+            // A 429 that no amount of waiting will fix.
+            ErrorKind::Auth
+        } else {
+            match status {
+                401 | 403 => ErrorKind::Auth,
+                429 => ErrorKind::RateLimit,
+                408 | 409 | 425 => ErrorKind::Network,
+                500..=599 => ErrorKind::Network,
+                _ => ErrorKind::InvalidRequest,
+            }
+        };
+
+        let message = if message.is_empty() {
+            format!("Provider returned HTTP {}", status)
+        } else {
+            message
+        };
+
+        Self {
+            kind,
+            message,
+            status: Some(status),
+            retry_after_ms,
+        }
+    }
+
+    /// Classifies a transport-level failure that produced no HTTP response.
+    pub fn from_reqwest(error: reqwest::Error) -> Self {
+        let kind = if error.is_timeout() || error.is_connect() || error.is_request() || error.is_body() {
+            ErrorKind::Network
+        } else if error.is_decode() {
+            ErrorKind::Internal
+        } else {
+            ErrorKind::Network
+        };
+        Self {
+            kind,
+            message: error.to_string(),
+            status: error.status().map(|s| s.as_u16()),
+            retry_after_ms: None,
+        }
+    }
+
+    /// Classifies a failed MCP tool call.
+    pub fn from_service_error(tool_name: &str, error: &ServiceError) -> Self {
+        let kind = match error {
+            ServiceError::TransportSend(_) | ServiceError::TransportClosed | ServiceError::Timeout { .. } => {
+                ErrorKind::Network
+            }
+            _ => ErrorKind::Tool,
+        };
+        Self::new(kind, format!("Tool '{}' failed: {}", tool_name, error))
+    }
+}
+
+impl fmt::Display for AgentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            Some(status) => write!(f, "{:?} error (HTTP {}): {}", self.kind, status, self.message),
+            None => write!(f, "{:?} error: {}", self.kind, self.message),
+        }
+    }
+}
+
+impl std::error::Error for AgentError {}
+
+/// Lets existing `Result<_, String>` helpers be used with `?` inside functions returning `AgentError`.
+impl From<String> for AgentError {
+    fn from(message: String) -> Self {
+        Self::internal(message)
+    }
+}
@@ -0,0 +1,397 @@
+//! Renders sessions for use outside the app: Markdown with front matter, a standalone HTML page,
+//! or a versioned JSON bundle that keeps every branch.
+//!
+//! Markdown and HTML show the active branch the way the chat view does, with each tool call and
+//! its result folded into a `<details>` block.
+use crate::history::{ChatMessage, ChatSession, Role, ToolCallInfo, ToolCallStatus};
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the bundle layout changes incompatibly.
+pub const BUNDLE_VERSION: u32 = 1;
+const BUNDLE_FORMAT: &str = "trustagent.session";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Html => "html",
+            ExportFormat::Json => "json",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "Markdown",
+            ExportFormat::Html => "HTML",
+            ExportFormat::Json => "JSON bundle",
+        }
+    }
+}
+
+/// The session with every branch. Messages cannot carry files yet, so there are no attachments
+/// to include; adding them later means bumping `BUNDLE_VERSION`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionBundle {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: u64,
+    pub session: ChatSession,
+}
+
+pub fn render(session: &ChatSession, format: ExportFormat, exported_at: u64) -> Result<String, String> {
+    match format {
+        ExportFormat::Markdown => Ok(to_markdown(session)),
+        ExportFormat::Html => Ok(to_html(session)),
+        ExportFormat::Json => {
+            let bundle = SessionBundle {
+                format: BUNDLE_FORMAT.to_string(),
+                version: BUNDLE_VERSION,
+                exported_at,
+                session: session.clone(),
+            };
+            serde_json::to_string_pretty(&bundle).map_err(|e| format!("Failed to serialize session: {}", e))
+        }
+    }
+}
+
+/// A file name for the session that is safe on every platform, e.g. `Fix-login-bug-3f2a9c1d.md`.
+pub fn file_name(session: &ChatSession, format: ExportFormat) -> String {
+    let title: String = session
+        .title
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect();
+    let title = title.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-");
+    let title: String = title.chars().take(60).collect();
+    let short_id: String = session.id.chars().take(8).collect();
+    if title.is_empty() {
+        format!("chat-{}.{}", short_id, format.extension())
+    } else {
+        format!("{}-{}.{}", title, short_id, format.extension())
+    }
+}
+
+/// One entry of the transcript: a dialogue message, or a tool call with its result if it has one.
+enum Entry<'a> {
+    Message(&'a ChatMessage),
+    Tool {
+        call: &'a ToolCallInfo,
+        result: Option<&'a ChatMessage>,
+    },
+}
+
+fn transcript(session: &ChatSession) -> Vec<Entry<'_>> {
+    let branch = session.active_branch();
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < branch.len() {
+        let message = branch[i];
+        match &message.tool_call {
+            // A request followed by its result is shown once, with the result's status.
+            Some(_) if message.role == Role::Assistant => {
+                match branch.get(i + 1).filter(|next| next.role == Role::Tool) {
+                    Some(result) => {
+                        entries.push(Entry::Tool {
+                            call: result.tool_call.as_ref().expect("tool result has a tool call"),
+                            result: Some(result),
+                        });
+                        i += 1;
+                    }
+                    None => entries.push(Entry::Tool {
+                        call: message.tool_call.as_ref().expect("step has a tool call"),
+                        result: None,
+                    }),
+                }
+            }
+            Some(call) => entries.push(Entry::Tool {
+                call,
+                result: Some(message),
+            }),
+            None => entries.push(Entry::Message(message)),
+        }
+        i += 1;
+    }
+    entries
+}
+
+fn role_label(role: Role) -> &'static str {
+    match role {
+        Role::System => "System",
+        Role::User => "User",
+        Role::Assistant => "Assistant",
+        Role::Tool => "Tool",
+    }
+}
+
+/// E.g. "success · 120 ms".
+fn tool_summary(call: &ToolCallInfo, result: Option<&ChatMessage>) -> String {
+    let status = match (result, call.status) {
+        (None, _) => "no result",
+        (Some(_), Some(ToolCallStatus::Error)) => "error",
+        (Some(_), _) => "success",
+    };
+    match call.duration_ms {
+        Some(ms) => format!("{} · {} ms", status, ms),
+        None => status.to_string(),
+    }
+}
+
+fn arguments_json(call: &ToolCallInfo) -> String {
+    serde_json::to_string_pretty(&call.arguments).unwrap_or_else(|_| call.arguments.to_string())
+}
+
+// --- Markdown ---
+
+fn to_markdown(session: &ChatSession) -> String {
+    let mut out = String::new();
+    // JSON strings are valid YAML scalars, which saves escaping titles by hand.
+    out.push_str("---\n");
+    out.push_str(&format!("title: {}\n", json_string(&session.title)));
+    out.push_str(&format!("id: {}\n", session.id));
+    out.push_str(&format!("created: {}\n", format_utc(session.created_at)));
+    out.push_str(&format!("updated: {}\n", format_utc(session.updated_at)));
+    if !session.tags.is_empty() {
+        out.push_str(&format!("tags: {}\n", serde_json::to_string(&session.tags).unwrap_or_default()));
+    }
+    out.push_str("---\n\n");
+    out.push_str(&format!("# {}\n", session.title));
+
+    for entry in transcript(session) {
+        out.push('\n');
+        match entry {
+            Entry::Message(message) => {
+                out.push_str(&format!(
+                    "## {} · {}\n\n{}\n",
+                    role_label(message.role),
+                    format_utc(message.timestamp),
+                    message.content.trim_end()
+                ));
+            }
+            Entry::Tool { call, result } => {
+                out.push_str(&format!(
+                    "<details>\n<summary>Tool <code>{}</code> · {}</summary>\n\n",
+                    escape_html(&call.tool_name),
+                    tool_summary(call, result)
+                ));
+                out.push_str(&format!("Arguments:\n\n{}\n", fenced(&arguments_json(call), "json")));
+                if let Some(result) = result {
+                    out.push_str(&format!("\nResult:\n\n{}\n", fenced(&result.content, "")));
+                }
+                out.push_str("\n</details>\n");
+            }
+        }
+    }
+    out
+}
+
+/// Wraps text in a code fence longer than any backtick run inside it.
+fn fenced(text: &str, lang: &str) -> String {
+    let longest_run = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{}{}\n{}\n{}", fence, lang, text.trim_end(), fence)
+}
+
+fn json_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap_or_default()
+}
+
+// --- HTML ---
+
+const HTML_STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:860px;margin:2rem auto;padding:0 1rem;color:#1f2937}\
+.meta{color:#6b7280;font-size:.85rem}\
+.msg{border-radius:8px;padding:.75rem 1rem;margin:.75rem 0}\
+.user{background:#dbeafe}.assistant{background:#f3f4f6}.system{background:#fef3c7}\
+.role{font-weight:600;font-size:.8rem;color:#4b5563;margin-bottom:.25rem}\
+.content{white-space:pre-wrap;word-wrap:break-word}\
+details{border:1px solid #d1d5db;border-radius:8px;margin:.75rem 0;padding:.5rem 1rem;font-size:.85rem}\
+summary{cursor:pointer;font-family:monospace}\
+pre{white-space:pre-wrap;word-wrap:break-word;background:#f9fafb;padding:.5rem;border-radius:4px}";
+
+fn to_html(session: &ChatSession) -> String {
+    let mut body = String::new();
+    for entry in transcript(session) {
+        match entry {
+            Entry::Message(message) => {
+                let class = match message.role {
+                    Role::User => "user",
+                    Role::System => "system",
+                    _ => "assistant",
+                };
+                body.push_str(&format!(
+                    "<div class=\"msg {}\"><div class=\"role\">{} · {}</div><div class=\"content\">{}</div></div>\n",
+                    class,
+                    role_label(message.role),
+                    format_utc(message.timestamp),
+                    escape_html(message.content.trim_end())
+                ));
+            }
+            Entry::Tool { call, result } => {
+                body.push_str(&format!(
+                    "<details><summary>{} · {}</summary><div>Arguments</div><pre>{}</pre>",
+                    escape_html(&call.tool_name),
+                    tool_summary(call, result),
+                    escape_html(&arguments_json(call))
+                ));
+                if let Some(result) = result {
+                    body.push_str(&format!("<div>Result</div><pre>{}</pre>", escape_html(&result.content)));
+                }
+                body.push_str("</details>\n");
+            }
+        }
+    }
+
+    let tags = if session.tags.is_empty() {
+        String::new()
+    } else {
+        format!(" · {}", escape_html(&session.tags.join(", ")))
+    };
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{style}</style>\n</head>\n<body>\n\
+<h1>{title}</h1>\n<p class=\"meta\">Created {created} · Updated {updated}{tags}</p>\n{body}</body>\n</html>\n",
+        title = escape_html(&session.title),
+        style = HTML_STYLE,
+        created = format_utc(session.created_at),
+        updated = format_utc(session.updated_at),
+        tags = tags,
+        body = body,
+    )
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM UTC`.
+pub fn format_utc(ts: u64) -> String {
+    let (year, month, day) = civil_from_days((ts / 86_400) as i64);
+    let secs = ts % 86_400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, secs / 3600, (secs % 3600) / 60)
+}
+
+/// Year, month and day of a count of days since 1970-01-01, see
+/// http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A prompt, a tool call with its result, a reply, and an abandoned reply on another branch.
+    fn sample_session() -> ChatSession {
+        let mut session = ChatSession::new("3f2a9c1d-0000".into(), "Fix <b> & \"quotes\"".into());
+        session.created_at = 1_735_689_600;
+        session.updated_at = 1_735_689_600;
+        session.tags = vec!["rust".into()];
+        let prompt = session.append(ChatMessage::new(Role::User, "Why does this break?"));
+        session.append_after(Some(prompt.clone()), ChatMessage::new(Role::Assistant, "abandoned reply"));
+        session.active_leaf = Some(prompt);
+        let call = ToolCallInfo {
+            tool_name: "read_file".into(),
+            arguments: serde_json::json!({ "path": "src/main.rs" }),
+            status: None,
+            duration_ms: None,
+        };
+        session.append(ChatMessage::new(Role::Assistant, "").with_tool_call(call.clone()));
+        let result = ToolCallInfo {
+            status: Some(ToolCallStatus::Success),
+            duration_ms: Some(120),
+            ..call
+        };
+        session.append(ChatMessage::new(Role::Tool, "````\nfn main() {}\n````").with_tool_call(result));
+        session.append(ChatMessage::new(Role::Assistant, "Use <script> & stay calm"));
+        for message in &mut session.messages {
+            message.timestamp = 1_735_689_600;
+        }
+        session
+    }
+
+    #[test]
+    fn renders_markdown_with_front_matter_and_folded_tool_calls() {
+        let markdown = render(&sample_session(), ExportFormat::Markdown, 0).unwrap();
+        assert!(markdown.starts_with(
+            "---\ntitle: \"Fix <b> & \\\"quotes\\\"\"\nid: 3f2a9c1d-0000\ncreated: 2025-01-01 00:00 UTC\n"
+        ));
+        assert!(markdown.contains("tags: [\"rust\"]\n---\n"));
+        // The request and its result are paired into a single block.
+        assert_eq!(markdown.matches("<details>").count(), 1);
+        assert!(markdown.contains("<summary>Tool <code>read_file</code> · success · 120 ms</summary>"));
+        assert!(markdown.contains("```json\n{\n  \"path\": \"src/main.rs\"\n}\n```"));
+        // The result holds a run of four backticks, so its fence needs five.
+        assert!(markdown.contains("Result:\n\n`````\n````\nfn main() {}\n````\n`````"));
+        assert!(markdown.contains("## Assistant · 2025-01-01 00:00 UTC\n\nUse <script> & stay calm\n"));
+        assert!(!markdown.contains("abandoned reply"));
+    }
+
+    #[test]
+    fn renders_html_escaped() {
+        let html = render(&sample_session(), ExportFormat::Html, 0).unwrap();
+        assert!(html.contains("<title>Fix &lt;b&gt; &amp; &quot;quotes&quot;</title>"));
+        assert!(html.contains("Updated 2025-01-01 00:00 UTC · rust</p>"));
+        assert_eq!(html.matches("<details>").count(), 1);
+        assert!(html.contains("<summary>read_file · success · 120 ms</summary>"));
+        assert!(html.contains("&quot;path&quot;: &quot;src/main.rs&quot;"));
+        assert!(html.contains("<div class=\"content\">Use &lt;script&gt; &amp; stay calm</div>"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("abandoned reply"));
+    }
+
+    #[test]
+    fn renders_json_bundles_with_every_branch() {
+        let session = sample_session();
+        let json = render(&session, ExportFormat::Json, 42).unwrap();
+        let bundle: SessionBundle = serde_json::from_str(&json).unwrap();
+        assert_eq!(bundle.format, BUNDLE_FORMAT);
+        assert_eq!(bundle.version, BUNDLE_VERSION);
+        assert_eq!(bundle.exported_at, 42);
+        assert_eq!(bundle.session.messages.len(), session.messages.len());
+        assert_eq!(bundle.session.active_leaf, session.active_leaf);
+        assert!(bundle.session.messages.iter().any(|m| m.content == "abandoned reply"));
+        let result = &bundle.session.messages[3];
+        assert_eq!(result.tool_call.as_ref().unwrap().status, Some(ToolCallStatus::Success));
+    }
+
+    #[test]
+    fn formats_timestamps_as_utc() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+        // 2000-02-29 23:59:59, a leap day in a century year.
+        assert_eq!(format_utc(951_868_799), "2000-02-29 23:59 UTC");
+        assert_eq!(format_utc(951_868_800 + 3_723), "2000-03-01 01:02 UTC");
+        assert_eq!(format_utc(1_735_689_600), "2025-01-01 00:00 UTC");
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
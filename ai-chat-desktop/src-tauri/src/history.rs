@@ -1,35 +1,383 @@
-
 //! Manages the chat history sessions.
+//!
+//! A session's messages form a tree: each message points at its parent, and editing an earlier
+//! prompt adds a sibling instead of rewriting history. `active_leaf` selects the branch that is
+//! shown and sent to the model. `messages` holds every node in insertion order.
+use crate::decision::AgentState;
+use crate::plan::Plan;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Sessions written before messages had ids and parents are version 0.
+pub const SESSION_SCHEMA_VERSION: u32 = 1;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
 pub enum Role {
     #[serde(rename = "system")]
     System,
     #[serde(rename = "user")]
     User,
-    #[serde(rename = "assistant")]
+    // Early frontend builds persisted replies as "bot".
+    #[serde(rename = "assistant", alias = "bot")]
     Assistant,
+    #[serde(rename = "tool")]
+    Tool,
+}
+
+/// The tool invocation a message belongs to. Set on the assistant message that requested the
+/// call and on the `Tool` message carrying its result.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ToolCallInfo {
+    pub tool_name: String,
+    #[serde(default)]
+    pub arguments: serde_json::Value,
+    /// Outcome of the call; only set on the `Tool` message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<ToolCallStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolCallStatus {
+    Success,
+    Error,
+}
+
+/// The settings that produced an assistant reply, so alternative answers can be told apart.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GenerationInfo {
+    pub profile: String,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatMessage {
+    #[serde(default = "new_message_id")]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
     pub role: Role,
     pub content: String,
     #[serde(default = "current_timestamp")]
     pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call: Option<ToolCallInfo>,
+    /// Only set on final assistant replies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub generation: Option<GenerationInfo>,
+    /// The plan a reply was worked out with, in plan-and-execute mode.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<Plan>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            id: new_message_id(),
+            parent_id: None,
+            role,
+            content: content.into(),
+            timestamp: current_timestamp(),
+            tool_call: None,
+            generation: None,
+            plan: None,
+        }
+    }
+
+    pub fn with_tool_call(mut self, tool_call: ToolCallInfo) -> Self {
+        self.tool_call = Some(tool_call);
+        self
+    }
+
+    pub fn with_generation(mut self, generation: GenerationInfo) -> Self {
+        self.generation = Some(generation);
+        self
+    }
+
+    pub fn with_plan(mut self, plan: Option<Plan>) -> Self {
+        self.plan = plan;
+        self
+    }
+
+    /// Whether this message is an intermediate agent step rather than part of the visible dialogue.
+    pub fn is_step(&self) -> bool {
+        self.tool_call.is_some()
+    }
+}
+
+/// A message's place in the tree, without its contents. Enough to lay out a branch, see
+/// `branch_layout`.
+#[derive(Debug, Clone)]
+pub struct MessageNode {
+    pub id: String,
+    pub parent_id: Option<String>,
+    pub role: Role,
+    pub is_step: bool,
+}
+
+impl MessageNode {
+    pub fn of(message: &ChatMessage) -> Self {
+        Self {
+            id: message.id.clone(),
+            parent_id: message.parent_id.clone(),
+            role: message.role,
+            is_step: message.is_step(),
+        }
+    }
+}
+
+/// Where a message on a branch stands among the alternatives at that point.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SiblingInfo {
+    /// The message whose siblings are counted; pass it to `switch_sibling`. For a reply that
+    /// followed tool steps this is the first step, since alternatives fork right after the prompt.
+    pub sibling_anchor: String,
+    pub sibling_index: usize,
+    pub sibling_count: usize,
+}
+
+/// The branch ending at `leaf`, oldest first, as indices into `nodes` with each message's
+/// siblings. Builds a single parent-to-children map, so it takes linear time.
+pub fn branch_layout(nodes: &[MessageNode], leaf: Option<&str>) -> Vec<(usize, SiblingInfo)> {
+    let index_of: HashMap<&str, usize> = nodes.iter().enumerate().map(|(i, n)| (n.id.as_str(), i)).collect();
+    let mut children: HashMap<Option<&str>, Vec<usize>> = HashMap::new();
+    for (i, node) in nodes.iter().enumerate() {
+        children.entry(node.parent_id.as_deref()).or_default().push(i);
+    }
+
+    let mut path = Vec::new();
+    let mut cursor = leaf;
+    while let Some(id) = cursor {
+        let Some(&i) = index_of.get(id) else { break };
+        path.push(i);
+        cursor = nodes[i].parent_id.as_deref();
+        // Guard against cycles in hand-edited files.
+        if path.len() > nodes.len() {
+            break;
+        }
+    }
+    path.reverse();
+
+    let mut turn_start = None;
+    let mut layout = Vec::with_capacity(path.len());
+    for (position, &i) in path.iter().enumerate() {
+        if position > 0 && nodes[path[position - 1]].role == Role::User {
+            turn_start = Some(i);
+        }
+        let anchor = match turn_start {
+            Some(start) if nodes[i].role == Role::Assistant && !nodes[i].is_step => start,
+            _ => i,
+        };
+        let siblings = children
+            .get(&nodes[anchor].parent_id.as_deref())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        layout.push((
+            i,
+            SiblingInfo {
+                sibling_anchor: nodes[anchor].id.clone(),
+                sibling_index: siblings.iter().position(|&s| s == anchor).unwrap_or(0),
+                sibling_count: siblings.len(),
+            },
+        ));
+    }
+    layout
+}
+
+/// Where a session's title came from, which decides whether it may be replaced automatically.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleSource {
+    /// "New Chat", before the first message.
+    Placeholder,
+    /// Cut from the first prompt; replaced by a generated title when one is available.
+    Heuristic,
+    /// Written by the model after the first exchange.
+    Generated,
+    /// Set by the user or taken from an import. Never replaced automatically. Sessions saved
+    /// before titles had a source count as manual, since there is no telling who wrote them.
+    #[default]
+    Manual,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ChatSession {
     pub id: String,
     pub title: String,
+    #[serde(default)]
+    pub title_source: TitleSource,
     #[serde(default = "current_timestamp")]
     pub created_at: u64,
     #[serde(default = "current_timestamp")]
     pub updated_at: u64,
+    #[serde(default)]
     pub messages: Vec<ChatMessage>,
+    /// Normalized with `normalize_tag`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Project the session is filed under in the sidebar; `None` for ungrouped sessions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<String>,
+    /// Pinned sessions are listed above all others.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// When the session was moved to the trash; `None` for live sessions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
+    /// Last message of the branch currently shown; `None` for an empty branch.
+    #[serde(default)]
+    pub active_leaf: Option<String>,
+    /// What the agent knows and is working on in this chat, carried from one run to the next.
+    #[serde(default, skip_serializing_if = "AgentState::is_empty")]
+    pub agent_state: AgentState,
+    #[serde(default)]
+    pub schema_version: u32,
+}
+
+impl ChatSession {
+    pub fn new(id: String, title: String) -> Self {
+        let now = current_timestamp();
+        Self {
+            id,
+            title,
+            title_source: TitleSource::Placeholder,
+            created_at: now,
+            updated_at: now,
+            messages: Vec::new(),
+            tags: Vec::new(),
+            folder: None,
+            pinned: false,
+            deleted_at: None,
+            active_leaf: None,
+            agent_state: AgentState::default(),
+            schema_version: SESSION_SCHEMA_VERSION,
+        }
+    }
+
+    /// Whether the session is an untouched new chat, which can be dropped without asking.
+    pub fn is_pristine(&self) -> bool {
+        self.messages.is_empty() && self.tags.is_empty() && self.folder.is_none() && !self.pinned
+    }
+
+    /// Upgrades a session from an older schema in place. Returns true if anything changed, in
+    /// which case the caller should persist it so message ids stay stable.
+    pub fn migrate(&mut self) -> bool {
+        if self.schema_version >= SESSION_SCHEMA_VERSION {
+            return false;
+        }
+        // Version 0 sessions are a flat list: chain the messages into a single branch.
+        let mut parent: Option<String> = None;
+        for message in &mut self.messages {
+            message.parent_id = parent.clone();
+            parent = Some(message.id.clone());
+        }
+        self.active_leaf = parent;
+        self.schema_version = SESSION_SCHEMA_VERSION;
+        true
+    }
+
+    pub fn get_message(&self, id: &str) -> Option<&ChatMessage> {
+        self.messages.iter().find(|m| m.id == id)
+    }
+
+    /// Adds `message` as a child of `parent_id` and makes it the active leaf.
+    pub fn append_after(&mut self, parent_id: Option<String>, mut message: ChatMessage) -> String {
+        let id = message.id.clone();
+        message.parent_id = parent_id;
+        self.messages.push(message);
+        self.active_leaf = Some(id.clone());
+        id
+    }
+
+    /// Adds `message` at the end of the active branch.
+    pub fn append(&mut self, message: ChatMessage) -> String {
+        self.append_after(self.active_leaf.clone(), message)
+    }
+
+    /// A new session `id` holding a copy of the branch ending at `leaf`. The copies get new ids,
+    /// since search and embeddings look messages up by id alone.
+    pub fn fork(&self, leaf: Option<&str>, id: String, title: String) -> ChatSession {
+        let mut fork = ChatSession::new(id, title);
+        fork.tags = self.tags.clone();
+        let mut new_ids: HashMap<&str, String> = HashMap::new();
+        for message in self.path_to(leaf) {
+            let mut copy = message.clone();
+            copy.id = new_message_id();
+            copy.parent_id = message.parent_id.as_deref().and_then(|p| new_ids.get(p).cloned());
+            new_ids.insert(message.id.as_str(), copy.id.clone());
+            fork.messages.push(copy);
+        }
+        fork.active_leaf = leaf.and_then(|l| new_ids.get(l).cloned());
+        fork
+    }
+
+    /// Messages from the root down to `leaf`, inclusive.
+    pub fn path_to(&self, leaf: Option<&str>) -> Vec<&ChatMessage> {
+        let by_id: HashMap<&str, &ChatMessage> = self.messages.iter().map(|m| (m.id.as_str(), m)).collect();
+        let mut path = Vec::new();
+        let mut cursor = leaf;
+        while let Some(id) = cursor {
+            let Some(message) = by_id.get(id) else { break };
+            path.push(*message);
+            cursor = message.parent_id.as_deref();
+            // Guard against cycles in hand-edited files.
+            if path.len() > self.messages.len() {
+                break;
+            }
+        }
+        path.reverse();
+        path
+    }
+
+    /// The messages on the active branch, oldest first.
+    pub fn active_branch(&self) -> Vec<&ChatMessage> {
+        self.path_to(self.active_leaf.as_deref())
+    }
+
+    pub fn nodes(&self) -> Vec<MessageNode> {
+        self.messages.iter().map(MessageNode::of).collect()
+    }
+
+    /// Messages sharing `message_id`'s parent (including itself), in creation order.
+    pub fn siblings_of(&self, message_id: &str) -> Vec<&ChatMessage> {
+        let Some(message) = self.get_message(message_id) else {
+            return Vec::new();
+        };
+        self.children_of(message.parent_id.as_deref())
+    }
+
+    pub fn children_of(&self, parent_id: Option<&str>) -> Vec<&ChatMessage> {
+        self.messages
+            .iter()
+            .filter(|m| m.parent_id.as_deref() == parent_id)
+            .collect()
+    }
+
+    /// Follows the most recently created child from `message_id` down to a leaf.
+    pub fn latest_leaf_under(&self, message_id: &str) -> String {
+        let mut current = message_id.to_string();
+        while let Some(child) = self.children_of(Some(&current)).last() {
+            current = child.id.clone();
+        }
+        current
+    }
+}
+
+/// Lowercases a tag and joins its words with `-`, so it can be searched as `tag:<tag>`.
+/// Returns `None` for a blank tag.
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.split_whitespace().collect::<Vec<_>>().join("-").to_lowercase();
+    (!tag.is_empty()).then_some(tag)
+}
+
+fn new_message_id() -> String {
+    Uuid::new_v4().to_string()
 }
 
 fn current_timestamp() -> u64 {
@@ -38,3 +386,27 @@ fn current_timestamp() -> u64 {
         .expect("Time went backwards")
         .as_secs()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forks_copy_the_branch_under_new_ids() {
+        let mut session = ChatSession::new("source".to_string(), "Chat".to_string());
+        session.tags = vec!["infra".to_string()];
+        let prompt = session.append(ChatMessage::new(Role::User, "Question"));
+        let reply = session.append(ChatMessage::new(Role::Assistant, "Answer"));
+        session.append_after(Some(prompt.clone()), ChatMessage::new(Role::Assistant, "Other answer"));
+        session.append(ChatMessage::new(Role::User, "Follow-up"));
+
+        let fork = session.fork(Some(&reply), "fork".to_string(), "Chat (fork)".to_string());
+        let contents: Vec<&str> = fork.active_branch().iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["Question", "Answer"]);
+        assert_eq!(fork.messages.len(), 2);
+        assert!(fork.messages.iter().all(|m| session.get_message(&m.id).is_none()));
+        assert_eq!(fork.messages[1].parent_id.as_deref(), Some(fork.messages[0].id.as_str()));
+        assert_eq!(fork.active_leaf.as_deref(), Some(fork.messages[1].id.as_str()));
+        assert_eq!(fork.tags, session.tags);
+    }
+}
@@ -0,0 +1,460 @@
+//! Converts chat histories exported from other tools into sessions.
+//!
+//! Supported inputs are ChatGPT's `conversations.json` and a generic JSONL file with one message
+//! per line. Imported sessions get ids derived from the source conversation, so importing the
+//! same export twice can be detected and skipped.
+use crate::history::{ChatMessage, ChatSession, Role, TitleSource};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    /// `conversations.json` from a ChatGPT data export.
+    ChatGpt,
+    /// One `{"role", "content", "timestamp"}` object per line. Lines with a `conversation` field
+    /// are grouped by it; otherwise the whole file is one conversation.
+    Jsonl,
+}
+
+impl ImportFormat {
+    /// Guesses the format from the file name.
+    pub fn detect(path: &std::path::Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("jsonl") => ImportFormat::Jsonl,
+            _ => ImportFormat::ChatGpt,
+        }
+    }
+}
+
+/// One conversation found in the input, converted or not.
+pub struct ParsedConversation {
+    /// Title, or a description of where in the file it came from if it has none.
+    pub label: String,
+    pub result: Result<ChatSession, String>,
+}
+
+/// Parses `content` into sessions. Fails only if the file as a whole is unreadable; problems with
+/// individual conversations are reported in their `result`.
+pub fn parse(content: &str, format: ImportFormat, default_title: &str) -> Result<Vec<ParsedConversation>, String> {
+    match format {
+        ImportFormat::ChatGpt => parse_chatgpt(content),
+        ImportFormat::Jsonl => Ok(parse_jsonl(content, default_title)),
+    }
+}
+
+/// A stable session id for a conversation from an external source.
+fn imported_session_id(source: &str, key: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_URL, format!("trustagent-import:{}:{}", source, key).as_bytes()).to_string()
+}
+
+fn parse_role(role: &str) -> Option<Role> {
+    match role {
+        "system" => Some(Role::System),
+        "user" | "human" => Some(Role::User),
+        "assistant" | "bot" | "ai" => Some(Role::Assistant),
+        "tool" | "function" => Some(Role::Tool),
+        _ => None,
+    }
+}
+
+/// Accepts seconds (possibly fractional) or milliseconds since the epoch, as a number or a
+/// string, or an ISO 8601 date and time such as `2024-03-01T12:30:00Z`.
+fn parse_timestamp(value: &Value) -> Option<u64> {
+    let ts = match value {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(s) => match s.trim().parse::<f64>() {
+            Ok(ts) => ts,
+            Err(_) => return parse_iso8601(s.trim()),
+        },
+        _ => return None,
+    };
+    if !ts.is_finite() || ts <= 0.0 {
+        return None;
+    }
+    // Anything past the year 33658 in seconds is really milliseconds.
+    Some(if ts > 1e12 { (ts / 1000.0) as u64 } else { ts as u64 })
+}
+
+/// Parses `YYYY-MM-DD`, optionally followed by `T` or a space, `HH:MM[:SS[.fraction]]` and a `Z`
+/// or `+HH:MM` offset. Times without an offset are taken as UTC.
+fn parse_iso8601(s: &str) -> Option<u64> {
+    let (date, rest) = (s.get(..10)?, s.get(10..)?);
+    let mut fields = date.split('-');
+    let year: i64 = fields.next()?.parse().ok()?;
+    let month: u32 = fields.next()?.parse().ok()?;
+    let day: u32 = fields.next()?.parse().ok()?;
+    if fields.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let mut seconds = days_from_civil(year, month, day) * 86_400;
+
+    let rest = rest.strip_prefix(['T', 't', ' ']).unwrap_or(rest);
+    if !rest.is_empty() {
+        let (time, offset) = match rest.find(['Z', 'z', '+', '-']) {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let mut fields = time.split(':');
+        let hour: i64 = fields.next()?.parse().ok()?;
+        let minute: i64 = fields.next()?.parse().ok()?;
+        let second: f64 = fields.next().map_or(Ok(0.0), str::parse).ok()?;
+        if fields.next().is_some() || !(0..24).contains(&hour) || !(0..60).contains(&minute) || !(0.0..61.0).contains(&second) {
+            return None;
+        }
+        seconds += hour * 3600 + minute * 60 + second as i64;
+        if !matches!(offset, "" | "Z" | "z") {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let digits: String = offset[1..].chars().filter(|c| *c != ':').collect();
+            if digits.len() != 4 {
+                return None;
+            }
+            let offset_hours: i64 = digits[..2].parse().ok()?;
+            let offset_minutes: i64 = digits[2..].parse().ok()?;
+            seconds -= sign * (offset_hours * 3600 + offset_minutes * 60);
+        }
+    }
+    u64::try_from(seconds).ok().filter(|s| *s > 0)
+}
+
+/// Days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// --- ChatGPT ---
+
+fn parse_chatgpt(content: &str) -> Result<Vec<ParsedConversation>, String> {
+    let conversations: Vec<Value> =
+        serde_json::from_str(content).map_err(|e| format!("Not a ChatGPT conversations.json export: {}", e))?;
+    Ok(conversations
+        .iter()
+        .enumerate()
+        .map(|(i, conversation)| {
+            let label = conversation
+                .get("title")
+                .and_then(Value::as_str)
+                .filter(|t| !t.trim().is_empty())
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Conversation #{}", i + 1));
+            ParsedConversation {
+                label,
+                result: convert_chatgpt(conversation),
+            }
+        })
+        .collect())
+}
+
+/// Text of a ChatGPT message. Non-text parts such as images are left out.
+fn chatgpt_text(message: &Value) -> String {
+    let content = &message["content"];
+    if let Some(parts) = content.get("parts").and_then(Value::as_array) {
+        return parts
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join("\n");
+    }
+    content.get("text").and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+/// The closest node from `id` upwards that is kept. Bounded by the node count to survive
+/// malformed parent links.
+fn nearest_kept<'a>(mapping: &'a Map<String, Value>, kept: &HashSet<&str>, mut id: Option<&'a str>) -> Option<&'a str> {
+    for _ in 0..=mapping.len() {
+        let current = id?;
+        if kept.contains(current) {
+            return Some(current);
+        }
+        id = mapping.get(current).and_then(|n| n["parent"].as_str());
+    }
+    None
+}
+
+/// ChatGPT stores a conversation as a tree of nodes keyed by id, the same shape as our sessions.
+/// Nodes without visible text (the hidden root, empty system prompts, ...) are dropped and their
+/// children attached to the nearest kept ancestor.
+fn convert_chatgpt(conversation: &Value) -> Result<ChatSession, String> {
+    let key = conversation
+        .get("conversation_id")
+        .or_else(|| conversation.get("id"))
+        .and_then(Value::as_str)
+        .ok_or("Missing conversation id")?;
+    let mapping = conversation
+        .get("mapping")
+        .and_then(Value::as_object)
+        .ok_or("Missing message mapping")?;
+
+    let mut session = ChatSession::new(
+        imported_session_id("chatgpt", key),
+        conversation
+            .get("title")
+            .and_then(Value::as_str)
+            .filter(|t| !t.trim().is_empty())
+            .unwrap_or("Imported chat")
+            .to_string(),
+    );
+    session.title_source = TitleSource::Manual;
+
+    let kept: HashSet<&str> = mapping
+        .iter()
+        .filter(|(_, node)| {
+            let message = &node["message"];
+            let hidden = message["metadata"]["is_visually_hidden_from_conversation"].as_bool() == Some(true);
+            !message.is_null()
+                && !hidden
+                && message["author"]["role"].as_str().and_then(parse_role).is_some()
+                && !chatgpt_text(message).trim().is_empty()
+        })
+        .map(|(id, _)| id.as_str())
+        .collect();
+    // Walk from the roots so parents are inserted before their children. Nodes whose parent links
+    // form a cycle cannot be reached from a root; each cycle is then walked from one of its nodes,
+    // which is cut off from its parent.
+    let mut queue: Vec<&str> = mapping
+        .iter()
+        .filter(|(_, node)| node["parent"].as_str().map_or(true, |p| !mapping.contains_key(p)))
+        .map(|(id, _)| id.as_str())
+        .collect();
+    let mut unreached = mapping.keys().map(String::as_str);
+    let mut visited = HashSet::new();
+    let mut inserted = HashSet::new();
+    while let Some(id) = queue.pop().or_else(|| unreached.find(|id| !visited.contains(id))) {
+        if !visited.insert(id) {
+            continue;
+        }
+        // Children may name nodes that are missing from the mapping.
+        let Some(node) = mapping.get(id) else { continue };
+        if kept.contains(id) {
+            let message = &node["message"];
+            let role = message["author"]["role"].as_str().and_then(parse_role).expect("kept nodes have a role");
+            let mut chat_message = ChatMessage::new(role, chatgpt_text(message));
+            chat_message.id = id.to_string();
+            if let Some(ts) = parse_timestamp(&message["create_time"]) {
+                chat_message.timestamp = ts;
+            }
+            let parent = nearest_kept(mapping, &kept, node["parent"].as_str()).filter(|p| inserted.contains(p));
+            session.append_after(parent.map(str::to_string), chat_message);
+            inserted.insert(id);
+        }
+        if let Some(children) = node["children"].as_array() {
+            // Reversed so the first child is visited first.
+            queue.extend(children.iter().rev().filter_map(Value::as_str));
+        }
+    }
+    if session.messages.is_empty() {
+        return Err("No messages".to_string());
+    }
+
+    session.active_leaf = nearest_kept(mapping, &kept, conversation["current_node"].as_str())
+        .map(str::to_string)
+        .or_else(|| session.messages.last().map(|m| m.id.clone()));
+    let first_ts = session.messages.iter().map(|m| m.timestamp).min().unwrap_or(session.created_at);
+    let last_ts = session.messages.iter().map(|m| m.timestamp).max().unwrap_or(session.updated_at);
+    session.created_at = parse_timestamp(&conversation["create_time"]).unwrap_or(first_ts);
+    session.updated_at = parse_timestamp(&conversation["update_time"]).unwrap_or(last_ts);
+    Ok(session)
+}
+
+// --- JSONL ---
+
+#[derive(Debug, Deserialize)]
+struct JsonlLine {
+    role: String,
+    content: String,
+    #[serde(default)]
+    timestamp: Value,
+    #[serde(default)]
+    conversation: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+}
+
+/// The lines of one conversation with their line numbers.
+type LineGroup = Vec<(usize, Result<JsonlLine, String>)>;
+
+fn parse_jsonl(content: &str, default_title: &str) -> Vec<ParsedConversation> {
+    // Conversations in order of first appearance.
+    let mut order: Vec<Option<String>> = Vec::new();
+    let mut groups: HashMap<Option<String>, LineGroup> = HashMap::new();
+    let mut results = Vec::new();
+
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed = serde_json::from_str::<JsonlLine>(line).map_err(|e| e.to_string());
+        let group = match &parsed {
+            Ok(line) => line.conversation.clone(),
+            Err(_) => serde_json::from_str::<Value>(line)
+                .ok()
+                .and_then(|v| v["conversation"].as_str().map(str::to_string)),
+        };
+        if !groups.contains_key(&group) {
+            order.push(group.clone());
+        }
+        groups.entry(group).or_default().push((i + 1, parsed));
+    }
+
+    for group in order {
+        let lines = groups.remove(&group).unwrap_or_default();
+        let label = lines
+            .iter()
+            .find_map(|(_, l)| l.as_ref().ok().and_then(|l| l.title.clone()))
+            .or_else(|| group.clone())
+            .unwrap_or_else(|| default_title.to_string());
+        results.push(ParsedConversation {
+            result: convert_jsonl(group.as_deref(), &label, lines),
+            label,
+        });
+    }
+    results
+}
+
+fn convert_jsonl(
+    group: Option<&str>,
+    title: &str,
+    lines: LineGroup,
+) -> Result<ChatSession, String> {
+    let mut messages = Vec::with_capacity(lines.len());
+    // Without an explicit conversation id, the first message identifies the conversation. Its
+    // timestamp as given, not the import time a missing one defaults to.
+    let mut key = group.map(str::to_string);
+    for (line_no, line) in lines {
+        let line = line.map_err(|e| format!("Line {}: {}", line_no, e))?;
+        let role = parse_role(&line.role).ok_or_else(|| format!("Line {}: unknown role '{}'", line_no, line.role))?;
+        let timestamp = parse_timestamp(&line.timestamp);
+        if key.is_none() {
+            key = Some(format!("{:?}:{}:{}", role, timestamp.unwrap_or(0), line.content));
+        }
+        let mut message = ChatMessage::new(role, line.content);
+        if let Some(ts) = timestamp {
+            message.timestamp = ts;
+        }
+        messages.push(message);
+    }
+    if messages.is_empty() {
+        return Err("No messages".to_string());
+    }
+    let key = key.unwrap_or_default();
+    let mut session = ChatSession::new(imported_session_id("jsonl", &key), title.to_string());
+    session.title_source = TitleSource::Manual;
+    session.created_at = messages.iter().map(|m| m.timestamp).min().unwrap_or(session.created_at);
+    session.updated_at = messages.iter().map(|m| m.timestamp).max().unwrap_or(session.updated_at);
+    for message in messages {
+        session.append(message);
+    }
+    Ok(session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn node(id: &str, parent: Option<&str>, children: &[&str], role: &str, content: Value) -> (String, Value) {
+        let message = if role.is_empty() {
+            Value::Null
+        } else {
+            json!({"author": {"role": role}, "content": content, "create_time": 1_700_000_000.5})
+        };
+        (id.to_string(), json!({"id": id, "parent": parent, "children": children, "message": message}))
+    }
+
+    #[test]
+    fn walks_chatgpt_trees() {
+        let branched: Map<String, Value> = [
+            node("root", None, &["a"], "", Value::Null),
+            node("a", Some("root"), &["b", "c"], "user", json!({"parts": ["Hi"]})),
+            node("b", Some("a"), &[], "assistant", json!({"parts": [null, "Hello", {"image": "x"}]})),
+            node("c", Some("a"), &["gone"], "assistant", json!({"parts": ["Hey"]})),
+            node("d", Some("a"), &[], "assistant", json!({"parts": [null]})),
+        ]
+        .into_iter()
+        .collect();
+        let cycle: Map<String, Value> = [
+            node("x", Some("y"), &["y"], "user", json!({"parts": ["Loop"]})),
+            node("y", Some("x"), &["x"], "assistant", json!({"parts": ["Back"]})),
+        ]
+        .into_iter()
+        .collect();
+        let export = json!([
+            {"conversation_id": "one", "title": "Branched", "mapping": branched, "current_node": "c"},
+            {"conversation_id": "two", "mapping": cycle},
+            {"conversation_id": "three", "title": "Broken"},
+        ]);
+
+        let parsed = parse(&export.to_string(), ImportFormat::ChatGpt, "export").unwrap();
+        assert_eq!(parsed.len(), 3);
+
+        let session = parsed[0].result.as_ref().unwrap();
+        assert_eq!(session.messages.len(), 3);
+        assert_eq!(session.get_message("b").unwrap().content, "Hello");
+        assert_eq!(session.children_of(Some("a")).len(), 2);
+        assert_eq!(session.active_leaf.as_deref(), Some("c"));
+        assert_eq!(session.active_branch().len(), 2);
+        assert_eq!(session.created_at, 1_700_000_000);
+
+        let session = parsed[1].result.as_ref().unwrap();
+        assert_eq!(parsed[1].label, "Conversation #2");
+        assert_eq!(session.messages.len(), 2);
+        assert_eq!(session.children_of(None).len(), 1);
+        assert_eq!(session.active_branch().len(), 2);
+
+        assert_eq!(parsed[2].label, "Broken");
+        assert!(parsed[2].result.is_err());
+    }
+
+    #[test]
+    fn groups_jsonl_lines_by_conversation() {
+        let content = [
+            r#"{"conversation": "a", "title": "First", "role": "user", "content": "Hi", "timestamp": "2024-03-01T12:30:00Z"}"#,
+            r#"{"conversation": "b", "role": "user", "content": "Other", "timestamp": 1709296200000}"#,
+            "",
+            r#"{"conversation": "a", "role": "assistant", "content": "Hello", "timestamp": "1709296260"}"#,
+            r#"{"conversation": "b", "role": "wizard", "content": "?"}"#,
+        ]
+        .join("\n");
+        let parsed = parse(&content, ImportFormat::Jsonl, "file").unwrap();
+        assert_eq!(parsed.len(), 2);
+
+        assert_eq!(parsed[0].label, "First");
+        let session = parsed[0].result.as_ref().unwrap();
+        let timestamps: Vec<u64> = session.active_branch().iter().map(|m| m.timestamp).collect();
+        assert_eq!(timestamps, vec![1_709_296_200, 1_709_296_260]);
+        assert_eq!((session.created_at, session.updated_at), (1_709_296_200, 1_709_296_260));
+
+        assert_eq!(parsed[1].label, "b");
+        assert_eq!(parsed[1].result.as_ref().unwrap_err(), "Line 5: unknown role 'wizard'");
+
+        // Without conversation ids the whole file is one chat, and re-importing finds the same id.
+        let single = r#"{"role": "user", "content": "Hi"}"#;
+        let session = parse(single, ImportFormat::Jsonl, "file").unwrap().remove(0).result.unwrap();
+        assert_eq!(session.title, "file");
+        assert_eq!(session.id, imported_session_id("jsonl", "User:0:Hi"));
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp(&json!(1_709_296_200)), Some(1_709_296_200));
+        assert_eq!(parse_timestamp(&json!(1_709_296_200_500u64)), Some(1_709_296_200));
+        assert_eq!(parse_timestamp(&json!("1709296200.5")), Some(1_709_296_200));
+        assert_eq!(parse_timestamp(&json!("2024-03-01T12:30:00Z")), Some(1_709_296_200));
+        assert_eq!(parse_timestamp(&json!("2024-03-01 12:30:00.250")), Some(1_709_296_200));
+        assert_eq!(parse_timestamp(&json!("2024-03-01T14:30:00+02:00")), Some(1_709_296_200));
+        assert_eq!(parse_timestamp(&json!("2024-03-01T07:30-0500")), Some(1_709_296_200));
+        assert_eq!(parse_timestamp(&json!("2024-03-01")), Some(1_709_251_200));
+        assert_eq!(parse_timestamp(&json!("2024-13-01")), None);
+        assert_eq!(parse_timestamp(&json!("yesterday")), None);
+        assert_eq!(parse_timestamp(&json!("NaN")), None);
+        assert_eq!(parse_timestamp(&Value::Null), None);
+    }
+}
@@ -0,0 +1,557 @@
+//! Local document knowledge base, so the agent can answer from project docs without an MCP server.
+//!
+//! Files in the folders listed in `knowledge.folders` are split into chunks of whole lines and
+//! indexed in their own Tantivy index, with the same analyzer as chat search. The folders are
+//! watched for changes, and a background thread then reindexes only the files that changed. As
+//! watchers can miss events (network drives, too many watched files), the folders are also
+//! rescanned every `poll_secs`. The agent searches the index through the built-in
+//! `search_knowledge` tool, whose results name the file and line range of every passage so that
+//! answers can cite them.
+
+use crate::search::{open_index, MixedTokenizer, SearchConfig, TOKENIZER};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, UNIX_EPOCH};
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::*;
+use tantivy::{doc, Index, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+use tracing::{info, warn};
+
+pub const TOOL_NAME: &str = "search_knowledge";
+pub const TOOL_DESCRIPTION: &str = "Searches the user's local documents and code. Arguments: \
+{\"query\": string, \"limit\": number (optional, default 5)}. Returns passages with their file and line \
+range. When you use a passage, cite it as path:start-end.";
+
+const INDEX_DIR: &str = ".knowledge";
+/// Bump when files are chunked or indexed differently, to reindex everything once.
+const INDEX_VERSION: u32 = 2;
+const MEMORY_ARENA_NUM_BYTES: usize = 50_000_000; // 50MB
+const CHUNK_LINES: usize = 40;
+// Lines shared by consecutive chunks, so a passage cut at a chunk boundary is still found whole.
+const CHUNK_OVERLAP: usize = 5;
+const MAX_CHUNK_BYTES: usize = 4_000;
+// Cap on the text of each passage given to the model.
+const MAX_PASSAGE_CHARS: usize = 2_000;
+// Changes are picked up once the folders have been quiet this long, so a save that touches
+// several files, or one file several times, is indexed once.
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+// Larger files are generated or data, not documentation.
+const MAX_FILE_BYTES: u64 = 10_000_000;
+const DEFAULT_RESULTS: usize = 5;
+const MAX_RESULTS: usize = 20;
+// Directories that hold dependencies or build output rather than the user's documents.
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target", "dist", "build", "__pycache__"];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KnowledgeConfig {
+    /// Folders to index, recursively. The knowledge base is off while this is empty.
+    #[serde(default)]
+    pub folders: Vec<PathBuf>,
+    /// Extensions of the files read as text. PDFs are indexed by their text as well when `pdf`
+    /// is listed.
+    #[serde(default = "default_extensions")]
+    pub extensions: Vec<String>,
+    /// How often the folders are rescanned in case the watcher missed a change, in seconds.
+    #[serde(default = "default_poll_secs")]
+    pub poll_secs: u64,
+}
+
+fn default_extensions() -> Vec<String> {
+    [
+        "md", "markdown", "txt", "rst", "adoc", "pdf", "rs", "py", "js", "jsx", "ts", "tsx", "go", "java", "kt", "c",
+        "h", "cpp", "hpp", "cs", "rb", "php", "swift", "sh", "sql", "toml", "yaml", "yml", "json",
+    ]
+    .iter()
+    .map(|e| e.to_string())
+    .collect()
+}
+
+fn default_poll_secs() -> u64 {
+    600
+}
+
+impl Default for KnowledgeConfig {
+    fn default() -> Self {
+        Self {
+            folders: Vec::new(),
+            extensions: default_extensions(),
+            poll_secs: default_poll_secs(),
+        }
+    }
+}
+
+/// A chunk of a file that matched a query.
+#[derive(Debug, Clone, Serialize)]
+pub struct Passage {
+    pub path: String,
+    /// 1-based and inclusive.
+    pub start_line: u64,
+    pub end_line: u64,
+    pub score: f32,
+    pub text: String,
+}
+
+/// What the index holds, saved as the payload of each commit so it is in step with the index.
+#[derive(Serialize, Deserialize, Default)]
+struct IndexedFiles {
+    version: u32,
+    #[serde(default)]
+    analyzer: SearchConfig,
+    /// Path to the modification time and size of the indexed copy.
+    files: HashMap<String, (u64, u64)>,
+}
+
+/// Searches the knowledge index. The folders are scanned by a background thread, which stops
+/// when this is dropped.
+pub struct Knowledge {
+    index: Index,
+    schema: Schema,
+    // Dropping these stops the scanner.
+    _scanner: mpsc::Sender<()>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl Knowledge {
+    /// Opens the index in the app's data directory and starts watching `config.folders`.
+    pub fn start(config: KnowledgeConfig, analyzer: &SearchConfig, data_dir: &Path) -> Result<Self, String> {
+        let index_path = data_dir.join(INDEX_DIR);
+        std::fs::create_dir_all(&index_path).map_err(|e| e.to_string())?;
+        let index = open_index(&index_path, Self::build_schema())?;
+        Self::with_index(index, config, analyzer)
+    }
+
+    fn build_schema() -> Schema {
+        let text_options = TextOptions::default()
+            .set_indexing_options(
+                TextFieldIndexing::default()
+                    .set_tokenizer(TOKENIZER)
+                    .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+            )
+            .set_stored();
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("path", STRING | STORED);
+        schema_builder.add_u64_field("start_line", STORED);
+        schema_builder.add_u64_field("end_line", STORED);
+        schema_builder.add_text_field("content", text_options);
+        schema_builder.build()
+    }
+
+    fn with_index(index: Index, config: KnowledgeConfig, analyzer: &SearchConfig) -> Result<Self, String> {
+        index.tokenizers().register(TOKENIZER, MixedTokenizer::new(analyzer));
+        let schema = index.schema();
+        let writer = index
+            .writer(MEMORY_ARENA_NUM_BYTES)
+            .map_err(|e| format!("Failed to create knowledge index writer: {}", e))?;
+        let indexed = index
+            .load_metas()
+            .ok()
+            .and_then(|metas| metas.payload)
+            .and_then(|payload| serde_json::from_str::<IndexedFiles>(&payload).ok())
+            .filter(|indexed| indexed.version == INDEX_VERSION && indexed.analyzer == *analyzer)
+            .unwrap_or_default();
+        let (scanner_handle, receiver) = mpsc::channel();
+        let watcher = watch(&config.folders, scanner_handle.clone());
+        let mut scanner = Scanner {
+            writer,
+            schema: schema.clone(),
+            config,
+            analyzer: analyzer.clone(),
+            indexed,
+        };
+        std::thread::Builder::new()
+            .name("knowledge-indexer".to_string())
+            .spawn(move || scanner.run(receiver))
+            .map_err(|e| format!("Failed to start knowledge indexer: {}", e))?;
+        Ok(Knowledge {
+            index,
+            schema,
+            _scanner: scanner_handle,
+            _watcher: watcher,
+        })
+    }
+
+    /// The best matching passages, best first.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Passage>, String> {
+        let path_field = self.schema.get_field("path").unwrap();
+        let start_field = self.schema.get_field("start_line").unwrap();
+        let end_field = self.schema.get_field("end_line").unwrap();
+        let content_field = self.schema.get_field("content").unwrap();
+
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(|e| e.to_string())?;
+        let searcher = reader.searcher();
+        // Queries come from the model, which does not know the query syntax: take what parses.
+        let (query, _) = QueryParser::for_index(&self.index, vec![content_field]).parse_query_lenient(query);
+        let top_docs = searcher
+            .search(&query, &TopDocs::with_limit(limit))
+            .map_err(|e| e.to_string())?;
+
+        let mut passages = Vec::with_capacity(top_docs.len());
+        for (score, doc_address) in top_docs {
+            let doc = searcher.doc::<TantivyDocument>(doc_address).map_err(|e| e.to_string())?;
+            let text_of = |field| doc.get_first(field).and_then(|v| v.as_str()).unwrap_or_default().to_string();
+            let line_of = |field| doc.get_first(field).and_then(|v| v.as_u64()).unwrap_or(0);
+            passages.push(Passage {
+                path: text_of(path_field),
+                start_line: line_of(start_field),
+                end_line: line_of(end_field),
+                score,
+                text: text_of(content_field),
+            });
+        }
+        Ok(passages)
+    }
+
+    /// Runs the `search_knowledge` tool and formats the passages for the model.
+    pub fn call_tool(&self, arguments: &serde_json::Value) -> Result<String, String> {
+        let query = arguments
+            .get("query")
+            .and_then(serde_json::Value::as_str)
+            .filter(|q| !q.trim().is_empty())
+            .ok_or("Missing 'query' argument")?;
+        let limit = arguments
+            .get("limit")
+            .and_then(serde_json::Value::as_u64)
+            .map_or(DEFAULT_RESULTS, |l| (l as usize).clamp(1, MAX_RESULTS));
+        let passages = self.search(query, limit)?;
+        if passages.is_empty() {
+            return Ok(format!("No passages in the local documents match '{}'.", query));
+        }
+        let mut result = String::from("Passages from the local documents. Cite the ones you use as path:start-end.\n");
+        for passage in passages {
+            let mut text: String = passage.text.chars().take(MAX_PASSAGE_CHARS).collect();
+            if text.len() < passage.text.len() {
+                text.push_str(" [...]");
+            }
+            result.push_str(&format!(
+                "\n--- {}:{}-{}\n{}\n",
+                passage.path, passage.start_line, passage.end_line, text
+            ));
+        }
+        Ok(result)
+    }
+}
+
+/// Watches `folders` and pings `changed` when something in them changes. Returns `None` when no
+/// watcher could be started, leaving the periodic rescan to find changes.
+fn watch(folders: &[PathBuf], changed: mpsc::Sender<()>) -> Option<RecommendedWatcher> {
+    if folders.is_empty() {
+        return None;
+    }
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+        // Reads, including the scanner's own, change nothing.
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+        _ => {
+            let _ = changed.send(());
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            warn!("Failed to watch the knowledge folders, relying on rescans: {}", e);
+            return None;
+        }
+    };
+    for folder in folders {
+        if let Err(e) = watcher.watch(folder, RecursiveMode::Recursive) {
+            warn!(folder = %folder.display(), "Failed to watch knowledge folder, relying on rescans: {}", e);
+        }
+    }
+    Some(watcher)
+}
+
+/// Keeps the index in step with the files on disk.
+struct Scanner {
+    writer: IndexWriter,
+    schema: Schema,
+    config: KnowledgeConfig,
+    analyzer: SearchConfig,
+    indexed: IndexedFiles,
+}
+
+impl Scanner {
+    /// Scans whenever the watcher reports a change, and every `poll_secs` regardless. Returns once
+    /// `Knowledge` is dropped, which disconnects `changed`.
+    fn run(&mut self, changed: mpsc::Receiver<()>) {
+        let poll = Duration::from_secs(self.config.poll_secs.max(1));
+        loop {
+            if let Err(e) = self.scan() {
+                warn!("Failed to update the knowledge index: {}", e);
+                self.writer.rollback().ok();
+            }
+            match changed.recv_timeout(poll) {
+                Ok(()) => loop {
+                    match changed.recv_timeout(SETTLE_DELAY) {
+                        Ok(()) => continue,
+                        Err(mpsc::RecvTimeoutError::Timeout) => break,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                    }
+                },
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Reindexes the files that changed since the last scan and drops the ones that are gone.
+    fn scan(&mut self) -> Result<(), String> {
+        let mut pending = IndexedFiles {
+            version: INDEX_VERSION,
+            analyzer: self.analyzer.clone(),
+            files: HashMap::new(),
+        };
+        if self.indexed.version != INDEX_VERSION {
+            info!("Knowledge index is missing, outdated or built with other settings, rebuilding it.");
+            self.writer.delete_all_documents().map_err(|e| e.to_string())?;
+        } else {
+            pending.files = self.indexed.files.clone();
+        }
+        let rebuilt = self.indexed.version != INDEX_VERSION;
+
+        let files = collect_files(&self.config);
+        let path_field = self.schema.get_field("path").unwrap();
+        let mut changed = 0;
+        for (path, stamp) in &files {
+            if pending.files.get(path) == Some(stamp) {
+                continue;
+            }
+            changed += 1;
+            self.writer.delete_term(Term::from_field_text(path_field, path));
+            // A file that cannot be read is recorded anyway, so it is retried only once it changes.
+            match read_text(Path::new(path)) {
+                Ok(text) => {
+                    for (start_line, end_line, chunk) in chunks(&text) {
+                        self.writer
+                            .add_document(doc!(
+                                path_field => path.as_str(),
+                                self.schema.get_field("start_line").unwrap() => start_line as u64,
+                                self.schema.get_field("end_line").unwrap() => end_line as u64,
+                                self.schema.get_field("content").unwrap() => chunk,
+                            ))
+                            .map_err(|e| e.to_string())?;
+                    }
+                }
+                Err(e) => warn!(%path, "Skipping file in the knowledge base: {}", e),
+            }
+            pending.files.insert(path.clone(), *stamp);
+        }
+        let removed: Vec<String> = pending.files.keys().filter(|p| !files.contains_key(*p)).cloned().collect();
+        for path in &removed {
+            self.writer.delete_term(Term::from_field_text(path_field, path));
+            pending.files.remove(path);
+        }
+
+        if changed == 0 && removed.is_empty() && !rebuilt {
+            return Ok(());
+        }
+        let payload = serde_json::to_string(&pending).map_err(|e| e.to_string())?;
+        let mut commit = self.writer.prepare_commit().map_err(|e| e.to_string())?;
+        commit.set_payload(&payload);
+        commit.commit().map_err(|e| e.to_string())?;
+        info!(files = pending.files.len(), changed, removed = removed.len(), "Updated the knowledge index");
+        self.indexed = pending;
+        Ok(())
+    }
+}
+
+/// The files to index in `config.folders`, with their modification time and size. Hidden files
+/// and directories are left out.
+fn collect_files(config: &KnowledgeConfig) -> HashMap<String, (u64, u64)> {
+    let extensions: HashSet<String> = config.extensions.iter().map(|e| e.to_lowercase()).collect();
+    let mut files = HashMap::new();
+    let mut dirs: Vec<PathBuf> = config.folders.clone();
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(dir = %dir.display(), "Failed to read knowledge folder: {}", e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // `file_type` does not follow symlinks, which keeps link cycles out.
+            let Ok(file_type) = entry.file_type() else { continue };
+            if name.starts_with('.') {
+                continue;
+            }
+            if file_type.is_dir() {
+                if !SKIPPED_DIRS.contains(&name.as_str()) {
+                    dirs.push(entry.path());
+                }
+                continue;
+            }
+            let path = entry.path();
+            let wanted = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| extensions.contains(&e.to_lowercase()));
+            let Ok(metadata) = entry.metadata() else { continue };
+            if !file_type.is_file() || !wanted || metadata.len() > MAX_FILE_BYTES {
+                continue;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            files.insert(path.to_string_lossy().to_string(), (modified, metadata.len()));
+        }
+    }
+    files
+}
+
+fn read_text(path: &Path) -> Result<String, String> {
+    let is_pdf = path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("pdf"));
+    if is_pdf {
+        // The PDF parser panics on some malformed files; treat that like any other read error.
+        return std::panic::catch_unwind(|| pdf_extract::extract_text(path))
+            .map_err(|_| "The PDF could not be parsed".to_string())?
+            .map_err(|e| e.to_string());
+    }
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Splits `text` into overlapping chunks of whole lines: `(first line, last line, text)`, with
+/// 1-based line numbers. A line longer than `MAX_CHUNK_BYTES`, as in minified files, is split
+/// into chunks of its own that all cite that line. Blank chunks are left out.
+fn chunks(text: &str) -> Vec<(usize, usize, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        if lines[start].len() > MAX_CHUNK_BYTES {
+            for piece in split_line(lines[start], MAX_CHUNK_BYTES) {
+                if !piece.trim().is_empty() {
+                    chunks.push((start + 1, start + 1, piece.to_string()));
+                }
+            }
+            start += 1;
+            continue;
+        }
+        let mut end = start + 1;
+        let mut bytes = lines[start].len();
+        while end < lines.len() && end - start < CHUNK_LINES && bytes + lines[end].len() < MAX_CHUNK_BYTES {
+            bytes += lines[end].len() + 1;
+            end += 1;
+        }
+        let chunk = lines[start..end].join("\n");
+        if !chunk.trim().is_empty() {
+            chunks.push((start + 1, end, chunk));
+        }
+        if end == lines.len() {
+            break;
+        }
+        // Overlapping into a chunk that stopped at a long line would only repeat its tail.
+        let next_is_long = lines[end].len() > MAX_CHUNK_BYTES;
+        start = if end - start > CHUNK_OVERLAP && !next_is_long { end - CHUNK_OVERLAP } else { end };
+    }
+    chunks
+}
+
+/// Splits `line` into pieces of at most `max` bytes, at whitespace where there is some in the
+/// second half of a piece.
+fn split_line(line: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = line;
+    while rest.len() > max {
+        let mut cut = max;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if let Some(space) = rest[..cut].rfind(char::is_whitespace).filter(|&i| i > max / 2) {
+            cut = space;
+        }
+        pieces.push(&rest[..cut]);
+        rest = &rest[cut..];
+    }
+    pieces.push(rest);
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_overlap_and_number_lines_from_one() {
+        let text: String = (1..=100).map(|i| format!("line {}\n", i)).collect();
+        let ranges: Vec<(usize, usize)> = chunks(&text).into_iter().map(|(s, e, _)| (s, e)).collect();
+        assert_eq!(ranges, vec![(1, 40), (36, 75), (71, 100)]);
+        let (_, _, first) = &chunks(&text)[0];
+        assert!(first.starts_with("line 1\n") && first.ends_with("line 40"));
+        assert!(chunks("\n\n  \n").is_empty());
+    }
+
+    #[test]
+    fn long_lines_are_split_into_chunks_of_their_own() {
+        let long = "word ".repeat(2_000);
+        let text = format!("first\n{}\n数据库{}\nlast", long, "连".repeat(2_000));
+        let chunks = chunks(&text);
+        assert!(chunks.iter().all(|(_, _, chunk)| chunk.len() <= MAX_CHUNK_BYTES));
+        let ranges: Vec<(usize, usize)> = chunks.iter().map(|(s, e, _)| (*s, *e)).collect();
+        assert_eq!(ranges, vec![(1, 1), (2, 2), (2, 2), (2, 2), (3, 3), (3, 3), (4, 4)]);
+        let rejoined: String = chunks[1..4].iter().map(|(_, _, chunk)| chunk.as_str()).collect();
+        assert!(rejoined == long);
+        assert!(chunks[2].2.starts_with(' '));
+    }
+
+    #[test]
+    fn scans_folders_and_cites_line_ranges() {
+        let dir = std::env::temp_dir().join(format!("knowledge-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::create_dir_all(dir.join("node_modules")).unwrap();
+        let guide = dir.join("docs").join("deploy.md");
+        std::fs::write(&guide, "# Deploy\n\nRun the migrations before restarting.\n部署前先备份数据库。\n").unwrap();
+        std::fs::write(dir.join("node_modules").join("dep.md"), "migrations").unwrap();
+        std::fs::write(dir.join("notes.bin"), "migrations").unwrap();
+
+        let config = KnowledgeConfig {
+            folders: vec![dir.clone()],
+            ..KnowledgeConfig::default()
+        };
+        let index = Index::create_in_ram(Knowledge::build_schema());
+        index.tokenizers().register(TOKENIZER, MixedTokenizer::new(&SearchConfig::default()));
+        let mut scanner = Scanner {
+            writer: index.writer(MEMORY_ARENA_NUM_BYTES).unwrap(),
+            schema: index.schema(),
+            config,
+            analyzer: SearchConfig::default(),
+            indexed: IndexedFiles::default(),
+        };
+        scanner.scan().unwrap();
+        assert_eq!(scanner.indexed.files.len(), 1);
+
+        let (scanner_handle, _) = mpsc::channel();
+        let knowledge = Knowledge {
+            index: index.clone(),
+            schema: index.schema(),
+            _scanner: scanner_handle,
+            _watcher: None,
+        };
+        let passages = knowledge.search("migration", 5).unwrap();
+        assert_eq!(passages.len(), 1);
+        assert_eq!(passages[0].path, guide.to_string_lossy());
+        assert_eq!((passages[0].start_line, passages[0].end_line), (1, 4));
+        assert_eq!(knowledge.search("备份", 5).unwrap().len(), 1);
+
+        let result = knowledge.call_tool(&serde_json::json!({ "query": "restarting" })).unwrap();
+        assert!(result.contains(&format!("{}:1-4", guide.display())));
+        assert!(knowledge.call_tool(&serde_json::json!({})).is_err());
+
+        std::fs::remove_file(&guide).unwrap();
+        scanner.scan().unwrap();
+        assert!(scanner.indexed.files.is_empty());
+        assert!(knowledge.search("migration", 5).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
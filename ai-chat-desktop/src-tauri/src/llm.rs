@@ -0,0 +1,172 @@
+//! Chat completion and embeddings transport with error classification and retries.
+//!
+//! Requests are built with the `async-openai` types but sent through `reqwest` directly, so that
+//! status codes and `Retry-After` headers are visible when deciding whether to retry.
+
+use crate::error::{AgentError, ErrorKind};
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionResponse};
+use rand::Rng;
+use reqwest::header::HeaderMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
+
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_DELAY_MS: u64 = 1_000;
+const MAX_DELAY_MS: u64 = 30_000;
+// A provider asking us to wait longer than this is effectively telling us to give up.
+const MAX_RETRY_AFTER_MS: u64 = 60_000;
+const REQUEST_TIMEOUT_SECS: u64 = 120;
+
+pub struct LlmClient {
+    http: reqwest::Client,
+    api_key: String,
+    base_url: String,
+}
+
+impl LlmClient {
+    pub fn new(api_key: String, base_url: String) -> Result<Self, AgentError> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()
+            .map_err(|e| AgentError::internal(format!("Failed to build HTTP client: {}", e)))?;
+        Ok(Self {
+            http,
+            api_key,
+            base_url,
+        })
+    }
+
+    /// Sends a chat completion request, retrying rate limits and transient network failures.
+    ///
+    /// `on_retry` is called before each backoff sleep with the error, the attempt that failed
+    /// (starting at 1) and the delay about to be waited.
+    pub async fn chat<F>(
+        &self,
+        request: &CreateChatCompletionRequest,
+        on_retry: F,
+    ) -> Result<CreateChatCompletionResponse, AgentError>
+    where
+        F: FnMut(&AgentError, u32, Duration),
+    {
+        self.post("chat/completions", request, on_retry).await
+    }
+
+    /// Embeds each of `input` with `model`; returns the vectors in the same order.
+    pub async fn embed(&self, model: &str, input: &[String]) -> Result<Vec<Vec<f32>>, AgentError> {
+        let request = EmbeddingRequest { model, input };
+        let mut response: EmbeddingResponse = self.post("embeddings", &request, |_, _, _| {}).await?;
+        if response.data.len() != input.len() {
+            return Err(AgentError::internal(format!(
+                "Expected {} embeddings, got {}",
+                input.len(),
+                response.data.len()
+            )));
+        }
+        response.data.sort_by_key(|d| d.index);
+        Ok(response.data.into_iter().map(|d| d.embedding).collect())
+    }
+
+    async fn post<Req, Resp, F>(&self, path: &str, request: &Req, mut on_retry: F) -> Result<Resp, AgentError>
+    where
+        Req: Serialize + ?Sized,
+        Resp: DeserializeOwned,
+        F: FnMut(&AgentError, u32, Duration),
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match self.send(path, request).await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+
+            if !error.is_retryable() || attempt >= MAX_ATTEMPTS {
+                return Err(error);
+            }
+            let delay = match retry_delay(&error, attempt) {
+                Some(delay) => delay,
+                None => return Err(error),
+            };
+
+            warn!(attempt, delay_ms = delay.as_millis() as u64, error = %error, path, "Request failed, retrying");
+            on_retry(&error, attempt, delay);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send<Req, Resp>(&self, path: &str, request: &Req) -> Result<Resp, AgentError>
+    where
+        Req: Serialize + ?Sized,
+        Resp: DeserializeOwned,
+    {
+        let url = format!("{}/{}", self.base_url.trim_end_matches('/'), path);
+        let response = self
+            .http
+            .post(url)
+            .bearer_auth(&self.api_key)
+            .json(request)
+            .send()
+            .await
+            .map_err(AgentError::from_reqwest)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retry_after_ms = parse_retry_after(response.headers());
+            let body = response.text().await.unwrap_or_default();
+            return Err(AgentError::from_http(status.as_u16(), &body, retry_after_ms));
+        }
+
+        let bytes = response.bytes().await.map_err(AgentError::from_reqwest)?;
+        serde_json::from_slice(&bytes).map_err(|e| {
+            AgentError::new(
+                ErrorKind::Internal,
+                format!("Failed to parse {} response: {}", path, e),
+            )
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+/// Picks how long to wait before the next attempt, or `None` if the wait would be unreasonable.
+///
+/// A provider-supplied `Retry-After` wins; otherwise exponential backoff with jitter.
+fn retry_delay(error: &AgentError, attempt: u32) -> Option<Duration> {
+    if let Some(ms) = error.retry_after_ms {
+        return (ms <= MAX_RETRY_AFTER_MS).then(|| Duration::from_millis(ms));
+    }
+    let ceiling = BASE_DELAY_MS
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_DELAY_MS);
+    let jittered = rand::thread_rng().gen_range(ceiling / 2..=ceiling);
+    Some(Duration::from_millis(jittered))
+}
+
+/// Reads `retry-after-ms` (sent by OpenAI and Azure) or the standard `Retry-After` in seconds.
+fn parse_retry_after(headers: &HeaderMap) -> Option<u64> {
+    let header_str = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+
+    if let Some(ms) = header_str("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return Some(ms.max(0.0) as u64);
+    }
+    header_str("retry-after")
+        .and_then(|v| v.parse::<f64>().ok())
+        .map(|secs| (secs.max(0.0) * 1000.0) as u64)
+}
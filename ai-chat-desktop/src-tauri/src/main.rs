@@ -1,7 +1,20 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod agent;
+mod decision;
+mod error;
+mod export;
+mod history;
+mod importer;
+mod knowledge;
+mod llm;
+mod memory;
+mod plan;
 mod search;
+mod semantic;
+mod storage;
+mod title;
+mod tools;
 mod window;
 
 use serde::{Deserialize, Serialize};
@@ -9,10 +22,15 @@ use std::collections::HashMap;
 use std::fs::{self, File};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tauri::{App, Manager, State, WebviewWindow, Emitter};
+use tauri::{App, AppHandle, Manager, State, WebviewWindow, Emitter};
+use tauri_plugin_dialog::{DialogExt, FilePath};
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::error::{AgentError, ErrorKind};
+use crate::history::{ChatMessage, ChatSession, GenerationInfo, Role, TitleSource};
+use crate::storage::cached_session;
+
 
 // --- Configuration Structures ---
 
@@ -21,6 +39,80 @@ struct AppConfig {
     openai: OpenAIParams,
     #[serde(rename = "mcpServers")]
     mcp_servers: HashMap<String, McpServerProcessConfig>,
+    #[serde(default)]
+    storage: storage::StorageConfig,
+    /// How messages are split into words for search.
+    #[serde(default)]
+    search: search::SearchConfig,
+    /// Semantic search; off by default.
+    #[serde(default)]
+    embeddings: semantic::EmbeddingConfig,
+    /// Local documents the agent can search with `search_knowledge`.
+    #[serde(default)]
+    knowledge: knowledge::KnowledgeConfig,
+    /// Settings of the built-in tools.
+    #[serde(default)]
+    tools: tools::ToolsConfig,
+    /// Facts the agent remembers across chats.
+    #[serde(default)]
+    memory: memory::MemoryConfig,
+    /// Budgets for each run, see `agent::RunLimits`.
+    #[serde(default)]
+    limits: agent::RunLimits,
+    /// Named overrides of the model settings, selectable per request.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    profiles: HashMap<String, ModelProfile>,
+}
+
+/// The profile used when a request does not name one: the `openai` settings as they are.
+const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+struct ModelProfile {
+    /// Falls back to `openai.model`.
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    temperature: Option<f32>,
+    /// Overrides the global `limits`.
+    #[serde(default)]
+    limits: agent::RunLimits,
+}
+
+impl AppConfig {
+    /// Resolves the model settings for a run under the named profile.
+    fn generation_for(&self, profile: Option<&str>) -> Result<GenerationInfo, String> {
+        let name = profile.unwrap_or(DEFAULT_PROFILE);
+        let overrides = match self.profiles.get(name) {
+            Some(p) => p.clone(),
+            None if name == DEFAULT_PROFILE => ModelProfile::default(),
+            None => return Err(format!("Unknown profile '{}'", name)),
+        };
+        Ok(GenerationInfo {
+            profile: name.to_string(),
+            model: overrides.model.unwrap_or_else(|| self.openai.model.clone()),
+            temperature: overrides.temperature,
+        })
+    }
+
+    /// Resolves how to run a request under the named profile. Limits given with the request
+    /// override the profile's, which override the global ones.
+    fn run_options(
+        &self,
+        profile: Option<&str>,
+        plan: bool,
+        limits: Option<&agent::RunLimits>,
+    ) -> Result<agent::RunOptions, String> {
+        let generation = self.generation_for(profile)?;
+        let mut resolved = self.limits.clone();
+        if let Some(p) = self.profiles.get(&generation.profile) {
+            resolved = resolved.overridden_by(&p.limits);
+        }
+        if let Some(limits) = limits {
+            resolved = resolved.overridden_by(limits);
+        }
+        Ok(agent::RunOptions { generation, plan, limits: resolved.validated()? })
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -46,36 +138,6 @@ impl Default for OpenAIParams {
     }
 }
 
-// --- Chat Structures ---
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ChatMessage {
-    role: String,
-    content: String,
-    timestamp: u64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ChatSession {
-    id: String,
-    title: String,
-    messages: Vec<ChatMessage>,
-    created_at: u64,
-    updated_at: u64,
-}
-
-impl ChatSession {
-    fn new(id: String, title: String) -> Self {
-        let now = now_ts();
-        Self {
-            id,
-            title,
-            messages: Vec::new(),
-            created_at: now,
-            updated_at: now,
-        }
-    }
-}
-
 fn now_ts() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -93,12 +155,19 @@ struct McpServerInfo {
 
 struct AppState {
     config: Mutex<AppConfig>,
-    sessions: Mutex<HashMap<String, ChatSession>>,
+    sessions: Mutex<HashMap<String, ChatSession>>, // Sessions opened during this run; `store` is the source of truth
+    store: Box<dyn storage::SessionStore>,
     current_session_id: Mutex<Option<String>>,
     tool_states: Mutex<HashMap<String, bool>>, // Key: "{server_name}/{tool_name}"
     mcp_tools: Mutex<HashMap<String, Vec<String>>>, // 工具名列表
         mcp_clients: Mutex<HashMap<String, Arc<rmcp::service::RunningService<rmcp::service::RoleClient, ()>>>>, // Active MCP clients
-    searcher: Mutex<search::Searcher>,
+    builtin_tools: tools::ToolRegistry, // In-process tools, listed as server `builtin`
+    searcher: search::Searcher, // Indexes in the background; no lock needed
+    embedder: Option<semantic::Embedder>, // Set if `embeddings.enabled`
+    knowledge: Option<knowledge::Knowledge>, // Set if `knowledge.folders` is not empty
+    memory: Option<memory::Memory>, // Set if `memory.enabled`
+    plans: Mutex<HashMap<String, plan::Plan>>, // Plans of running tasks, by session; editable until they finish
+    load_issues: Mutex<Vec<storage::LoadIssue>>, // Session files that failed to load at startup
 }
 
 // --- Filesystem and Config Logic ---
@@ -132,58 +201,27 @@ fn get_app_log_dir() -> PathBuf {
     log_dir
 }
 
-fn save_session_to_file(session: &ChatSession) -> Result<(), String> {
-    let dir = get_app_data_dir().join(".chats");
-    if !dir.exists() {
-        fs::create_dir_all(&dir).ok();
-    }
-    let path = dir.join(format!("{}.json", session.id));
-    let content = serde_json::to_string_pretty(session).map_err(|e| e.to_string())?;
-    fs::write(path, content).map_err(|e| e.to_string())?;
-    Ok(())
-}
-
-/// Updates the search index for a session
+/// Updates the search index for a session. Sessions in the trash are kept out of the index.
 fn update_session_index(state: &AppState, session: &ChatSession) -> Result<(), String> {
-    let searcher = state.searcher.lock().map_err(|e| format!("Failed to lock searcher: {}", e))?;
-    searcher.add_or_update_session(session)?;
+    if session.deleted_at.is_some() {
+        return remove_session_from_index(state, &session.id);
+    }
+    state.searcher.add_or_update_session(session)?;
+    if let Some(embedder) = &state.embedder {
+        embedder.update_session(session)?;
+    }
     Ok(())
 }
 
 /// Removes a session from the search index
 fn remove_session_from_index(state: &AppState, session_id: &str) -> Result<(), String> {
-    let searcher = state.searcher.lock().map_err(|e| format!("Failed to lock searcher: {}", e))?;
-    searcher.remove_session(session_id)?;
-    Ok(())
-}
-
-fn delete_session_file(session_id: &str) -> Result<(), String> {
-    let dir = get_app_data_dir().join(".chats");
-    let path = dir.join(format!("{}.json", session_id));
-    if path.exists() {
-        fs::remove_file(path).map_err(|e| e.to_string())?;
+    state.searcher.remove_session(session_id)?;
+    if let Some(embedder) = &state.embedder {
+        embedder.remove_session(session_id)?;
     }
     Ok(())
 }
 
-fn load_sessions_from_files() -> HashMap<String, ChatSession> {
-    let dir = get_app_data_dir().join(".chats");
-    if !dir.exists() {
-        fs::create_dir_all(&dir).ok();
-    }
-    let mut map = HashMap::new();
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            if let Ok(content) = fs::read_to_string(entry.path()) {
-                if let Ok(session) = serde_json::from_str::<ChatSession>(&content) {
-                    map.insert(session.id.clone(), session);
-                }
-            }
-        }
-    }
-    map
-}
-
 fn load_or_initialize_config() -> AppConfig {
     let config_path = get_app_config_path();
     if config_path.exists() {
@@ -210,24 +248,6 @@ fn load_or_initialize_config() -> AppConfig {
     }
 }
 
-fn generate_session_title(messages: &[ChatMessage]) -> String {
-    messages
-        .iter()
-        .find(|m| m.role == "user")
-        .map(|m| {
-            let mut t = m.content.trim().to_string();
-            // Ensure we truncate at a character boundary to avoid panic
-            if t.len() > 20 {
-                // Find a character boundary at or before the 20th byte
-                let max_index = t.char_indices().take(20).last().map_or(0, |(i, _)| i);
-                t.truncate(max_index);
-                t.push_str("...");
-            }
-            t
-        })
-        .unwrap_or_else(|| "New Chat".to_string())
-}
-
 // --- MCP Logic ---
 
 async fn _start_mcp_server_logic(
@@ -530,13 +550,15 @@ fn get_mcp_servers(state: State<'_, Arc<AppState>>) -> Result<Vec<McpServerInfo>
     let servers_info = config
         .mcp_servers
         .keys()
+        .cloned()
+        .chain(std::iter::once(tools::SERVER_NAME.to_string()))
         .map(|name| McpServerInfo {
-            name: name.clone(),
-            status: if state.mcp_tools.lock().map_err(|e| format!("Failed to lock mcp_tools: {}", e)).unwrap().contains_key(name) {
+            status: if state.mcp_tools.lock().map_err(|e| format!("Failed to lock mcp_tools: {}", e)).unwrap().contains_key(&name) {
                 "running".to_string()
             } else {
                 "stopped".to_string()
             },
+            name,
         })
         .collect();
     Ok(servers_info)
@@ -558,6 +580,9 @@ async fn stop_mcp_server(
     window: WebviewWindow,
 ) -> Result<(), String> {
     info!(server_name = %server_name, "Stopping MCP server");
+    if server_name == tools::SERVER_NAME {
+        return Err("Built-in tools cannot be stopped; switch them off in the Tools menu instead.".to_string());
+    }
     state.mcp_tools.lock().map_err(|e| format!("Failed to lock mcp_tools: {}", e))?.remove(&server_name);
     // Here you would also kill the process if you were managing it.
     // For now, we just remove it from the list of active tool providers.
@@ -591,113 +616,300 @@ fn get_all_discovered_tools(state: State<'_, Arc<AppState>>) -> Result<Vec<Strin
 }
 
 // --- Agent Commands ---
+
+/// Applies the sliding window to a branch, prefixed with a summary of what falls outside it.
+fn context_history(branch: &[ChatMessage]) -> Vec<ChatMessage> {
+    let messages_len = branch.len();
+    let windowed_history = window::select_context_messages(branch, None);
+
+    // Optionally get a summary of older messages if we have more than our window
+    if messages_len > windowed_history.len() {
+        // Only summarize messages that aren't in our window
+        let older_messages = &branch[..messages_len - windowed_history.len()];
+        if let Some(summary) = window::summarize_old_messages(older_messages) {
+            let summary_msg = ChatMessage::new(
+                Role::System,
+                format!("Previous conversation summary: {}", summary),
+            );
+            let mut result = vec![summary_msg];
+            result.extend(windowed_history);
+            return result;
+        }
+    }
+    windowed_history
+}
+
+/// Collects the discovered tools, filtered by the active_tools list from the frontend.
+fn collect_available_tools(state: &AppState, active_tools: &[String]) -> Result<Vec<agent::Tool>, String> {
+    let mcp_tools_guard = state.mcp_tools.lock().map_err(|e| format!("Failed to lock mcp_tools: {}", e))?;
+    Ok(mcp_tools_guard
+        .iter()
+        .flat_map(|(server_name, tools)| {
+            tools.iter().map(move |tool_name| agent::Tool {
+                server_name: server_name.clone(),
+                tool_name: tool_name.clone(),
+                description: state
+                    .builtin_tools
+                    .description(tool_name)
+                    .filter(|_| server_name == tools::SERVER_NAME)
+                    .unwrap_or_else(|| format!("A tool named '{}' from server '{}'", tool_name, server_name)),
+            })
+        })
+        .filter(|tool| active_tools.contains(&tool.tool_name))
+        .collect())
+}
+
+/// Runs the agent on `history` and stores its steps and reply under the prompt `user_message_id`.
+/// A prompt that already has a reply gets the new one as an alternative.
+async fn answer_prompt(
+    state: &Arc<AppState>,
+    window: &WebviewWindow,
+    session_id: &str,
+    user_message_id: String,
+    history: Vec<ChatMessage>,
+    active_tools: &[String],
+    options: agent::RunOptions,
+) -> Result<String, AgentError> {
+    let available_tools = collect_available_tools(state, active_tools)?;
+    info!("Agent will run with {} active tools: {:?}", available_tools.len(), available_tools.iter().map(|t| &t.tool_name).collect::<Vec<_>>());
+
+    let mut agent_state = {
+        let sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+        let session = sessions.get(session_id).ok_or_else(|| "Session not found".to_string())?;
+        let mut agent_state = session.agent_state.clone();
+        agent_state.begin_run(session.path_to(Some(&user_message_id)).into_iter().map(|m| m.id.as_str()));
+        agent_state
+    };
+
+    let agent = agent::Agent::new();
+    let mut steps = Vec::new();
+    let result: Result<String, AgentError> = agent
+        .run_task(session_id, &history, available_tools, state.clone(), window, &options, &mut steps, &mut agent_state)
+        .await;
+    let plan = state
+        .plans
+        .lock()
+        .map_err(|e| format!("Failed to lock plans: {}", e))?
+        .remove(session_id)
+        .map(|mut plan| {
+            if plan.status == plan::PlanStatus::Running {
+                plan.status = plan::PlanStatus::Failed;
+            }
+            plan
+        });
+
+    let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    let session = sessions.get_mut(session_id)
+        .ok_or_else(|| "Session not found after agent run".to_string())?;
+
+    // Keep the tool calls that led to the answer so they are visible and reused as context later.
+    // Chain them under the prompt explicitly, in case the user switched branches meanwhile.
+    agent_state.leaf = Some(user_message_id.clone());
+    let mut parent_id = user_message_id;
+    for step in steps {
+        parent_id = session.append_after(Some(parent_id), step);
+    }
+    let reply = match &result {
+        Ok(assistant_content) => ChatMessage::new(Role::Assistant, assistant_content.clone()),
+        // Optionally add an error message to the chat history
+        Err(e) => ChatMessage::new(Role::Assistant, format!("An error occurred: {}", e)),
+    };
+    let model = options.generation.model.clone();
+    session.append_after(Some(parent_id), reply.with_generation(options.generation).with_plan(plan));
+    session.agent_state = agent_state;
+
+    // Name the session after its first exchange: the heuristic title right away, and a better
+    // one from the model once it arrives.
+    let first_exchange = session.title_source == TitleSource::Placeholder;
+    if first_exchange {
+        session.title = title::heuristic_title(&session.messages);
+        session.title_source = TitleSource::Heuristic;
+    }
+
+    session.updated_at = now_ts();
+    state.store.save(session).map_err(|e| format!("Failed to save session: {}", e))?;
+
+    // Update the search index with the modified session
+    update_session_index(state, session)?;
+
+    if first_exchange && result.is_ok() {
+        let branch: Vec<ChatMessage> = session.active_branch().into_iter().cloned().collect();
+        tauri::async_runtime::spawn(refine_session_title(
+            state.clone(),
+            window.clone(),
+            session_id.to_string(),
+            branch,
+            model,
+        ));
+    }
+
+    result
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct SessionTitleChanged {
+    id: String,
+    title: String,
+}
+
+/// Replaces a heuristic title with one written by the model, then emits `session_title_changed`.
+/// On failure the heuristic title stays.
+async fn refine_session_title(
+    state: Arc<AppState>,
+    window: WebviewWindow,
+    session_id: String,
+    messages: Vec<ChatMessage>,
+    model: String,
+) {
+    let openai = match state.config.lock() {
+        Ok(config) => config.openai.clone(),
+        Err(e) => {
+            error!("Failed to lock config: {}", e);
+            return;
+        }
+    };
+    let generated = match llm::LlmClient::new(openai.api_key, openai.base_url) {
+        Ok(client) => title::generate_title(&client, &model, &messages).await,
+        Err(e) => Err(e),
+    };
+    let new_title = match generated {
+        Ok(new_title) => new_title,
+        Err(e) => {
+            warn!(%session_id, "Title generation failed, keeping the heuristic title: {}", e);
+            return;
+        }
+    };
+
+    let apply = || -> Result<bool, String> {
+        let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+        let Some(session) = cached_session(state.store.as_ref(), &mut sessions, &session_id)? else {
+            return Ok(false);
+        };
+        // The user may have renamed the session while the model was busy.
+        if session.title_source != TitleSource::Heuristic {
+            return Ok(false);
+        }
+        session.title = new_title.clone();
+        session.title_source = TitleSource::Generated;
+        state.store.save(session).map_err(|e| format!("Failed to save session: {}", e))?;
+        update_session_index(&state, session)?;
+        Ok(true)
+    };
+    match apply() {
+        Ok(true) => {
+            info!(%session_id, title = %new_title, "Generated session title");
+            window
+                .emit(
+                    "session_title_changed",
+                    SessionTitleChanged {
+                        id: session_id,
+                        title: new_title,
+                    },
+                )
+                .ok();
+        }
+        Ok(false) => {}
+        Err(e) => error!(%session_id, "Failed to save generated title: {}", e),
+    }
+}
+
 #[tauri::command]
 async fn run_agent_task(
     message: String,
-    active_tools: Vec<String>, // Remove _ prefix
+    active_tools: Vec<String>,
+    profile: Option<String>,
+    plan: Option<bool>,
+    limits: Option<agent::RunLimits>,
     state: State<'_, Arc<AppState>>,
     window: WebviewWindow,
-) -> Result<String, String> {
+) -> Result<String, AgentError> {
     info!(%message, "Running agent task with history");
 
+    let options = state.config.lock().map_err(|e| format!("Failed to lock config: {}", e))?
+        .run_options(profile.as_deref(), plan.unwrap_or(false), limits.as_ref())
+        .map_err(|e| AgentError::new(ErrorKind::InvalidRequest, e))?;
+
     // 1. Get current session and add the new user message
     let session_id = state.current_session_id.lock().unwrap().clone()
         .ok_or_else(|| "No active session".to_string())?;
 
-    let history_clone = {
+    let (history, user_message_id) = {
         let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
         let session = sessions.get_mut(&session_id)
             .ok_or_else(|| "Current session not found in state".to_string())?;
 
-        let user_message = ChatMessage {
-            role: "user".to_string(),
-            content: message.clone(),
-            timestamp: now_ts(),
-        };
-        session.messages.push(user_message);
+        let user_message_id = session.append(ChatMessage::new(Role::User, message.clone()));
 
-        // Apply sliding window and potentially summarize old messages
-        let messages_len = session.messages.len();
-        let windowed_history = window::select_context_messages(&session.messages, None);
-        
-        // Optionally get a summary of older messages if we have more than our window
-        if messages_len > windowed_history.len() {
-            // Only summarize messages that aren't in our window
-            let older_messages = &session.messages[..messages_len - windowed_history.len()];
-            if let Some(summary) = window::summarize_old_messages(older_messages) {
-                let summary_msg = ChatMessage {
-                    role: "system".to_string(),
-                    content: format!("Previous conversation summary: {}", summary),
-                    timestamp: now_ts(),
-                };
-                let mut result = vec![summary_msg];
-                result.extend(windowed_history);
-                result
-            } else {
-                windowed_history
-            }
-        } else {
-            windowed_history
-        }
+        // Only the active branch is part of the conversation the model sees.
+        let branch: Vec<ChatMessage> = session.active_branch().into_iter().cloned().collect();
+        (context_history(&branch), user_message_id)
     }; // MutexGuard is dropped here
 
-    // 2. Collect available tools from the state, filtered by the active_tools list from the frontend
-    let available_tools: Vec<agent::Tool> = {
-        let mcp_tools_guard = state.mcp_tools.lock().map_err(|e| format!("Failed to lock mcp_tools: {}", e))?;
-        mcp_tools_guard
-            .iter()
-            .flat_map(|(server_name, tools)| {
-                tools.iter().map(move |tool_name| agent::Tool {
-                    server_name: server_name.clone(),
-                    tool_name: tool_name.clone(),
-                    description: format!("A tool named '{}' from server '{}'", tool_name, server_name),
-                })
-            })
-            .filter(|tool| active_tools.contains(&tool.tool_name)) // Apply the frontend filter
-            .collect()
-    }; // Lock guard is dropped here
+    // 2. Run the agent and record its answer
+    answer_prompt(state.inner(), &window, &session_id, user_message_id, history, &active_tools, options).await
+}
 
-    info!("Agent will run with {} active tools: {:?}", available_tools.len(), available_tools.iter().map(|t| &t.tool_name).collect::<Vec<_>>());
+/// Answers a prompt again and stores the result as an alternative to the existing reply.
+/// `message_id` is the prompt, or any message of the reply to it.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn regenerate_response(
+    session_id: String,
+    message_id: String,
+    active_tools: Vec<String>,
+    profile: Option<String>,
+    plan: Option<bool>,
+    limits: Option<agent::RunLimits>,
+    state: State<'_, Arc<AppState>>,
+    window: WebviewWindow,
+) -> Result<String, AgentError> {
+    info!(%session_id, %message_id, "Regenerating response");
 
-    // 3. Create an agent instance and run the task with the whole history
-    let agent = agent::Agent::new();
-    
-    let result: Result<String, String> = agent
-        .run_task(&history_clone, available_tools, state.inner().clone(), &window)
-        .await;
+    let options = state.config.lock().map_err(|e| format!("Failed to lock config: {}", e))?
+        .run_options(profile.as_deref(), plan.unwrap_or(false), limits.as_ref())
+        .map_err(|e| AgentError::new(ErrorKind::InvalidRequest, e))?;
 
-    // 4. Save the result to the session
-    let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
-    let session = sessions.get_mut(&session_id)
-        .ok_or_else(|| "Current session not found after agent run".to_string())?;
-
-    match &result {
-        Ok(assistant_content) => {
-            let assistant_message = ChatMessage {
-                role: "assistant".to_string(),
-                content: assistant_content.clone(),
-                timestamp: now_ts(),
-            };
-            session.messages.push(assistant_message);
-        }
-        Err(e) => {
-            // Optionally add an error message to the chat history
-            let error_message = ChatMessage {
-                role: "assistant".to_string(),
-                content: format!("An error occurred: {}", e),
-                timestamp: now_ts(),
-            };
-            session.messages.push(error_message);
-        }
-    }
-    
-    session.updated_at = now_ts();
-    save_session_to_file(session).map_err(|e| format!("Failed to save session: {}", e))?;
-    
-    // Update the search index with the modified session
-    update_session_index(&state, session)?;
+    let (history, user_message_id) = {
+        let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+        let session = cached_session(state.store.as_ref(), &mut sessions, &session_id)?
+            .ok_or_else(|| "Session not found".to_string())?;
 
-    result
+        let mut path: Vec<ChatMessage> = session.path_to(Some(&message_id)).into_iter().cloned().collect();
+        let prompt_index = path
+            .iter()
+            .rposition(|m| m.role == Role::User)
+            .ok_or_else(|| "No prompt to regenerate a response for".to_string())?;
+        path.truncate(prompt_index + 1);
+        let user_message_id = path[prompt_index].id.clone();
+        (context_history(&path), user_message_id)
+    };
+
+    answer_prompt(state.inner(), &window, &session_id, user_message_id, history, &active_tools, options).await
+}
+
+/// Replaces the steps of a running plan that have not started yet, as edited by the user on
+/// `version` of the plan. Returns the updated plan.
+#[tauri::command]
+fn update_plan(
+    session_id: String,
+    version: u64,
+    steps: Vec<plan::StepInput>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<plan::Plan, String> {
+    let mut plans = state.plans.lock().map_err(|e| format!("Failed to lock plans: {}", e))?;
+    let plan = plans
+        .get_mut(&session_id)
+        .ok_or_else(|| "No plan is running in this chat".to_string())?;
+    plan.edit_pending(version, steps)?;
+    info!(%session_id, steps = plan.steps.len(), "Plan edited");
+    Ok(plan.clone())
+}
+
+/// The plan of the task running in a session, if there is one.
+#[tauri::command]
+fn get_plan(session_id: String, state: State<'_, Arc<AppState>>) -> Result<Option<plan::Plan>, String> {
+    let plans = state.plans.lock().map_err(|e| format!("Failed to lock plans: {}", e))?;
+    Ok(plans.get(&session_id).cloned())
 }
 
 // --- Tauri Session Commands ---
@@ -709,90 +921,401 @@ fn rename_session(
     state: State<'_, Arc<AppState>>,
 ) -> Result<(), String> {
     let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
-    if let Some(session) = sessions.get_mut(&id) {
-        session.title = new_title;
-        session.updated_at = now_ts();
-        save_session_to_file(session).map_err(|e| format!("Failed to save session: {}", e))?;
-        
-        // Update the search index
-        update_session_index(&state, session)?;
-    }
+    let session = cached_session(state.store.as_ref(), &mut sessions, &id)?
+        .ok_or_else(|| "Session not found".to_string())?;
+    session.title = new_title;
+    session.title_source = TitleSource::Manual;
+    session.updated_at = now_ts();
+    state.store.save(session).map_err(|e| format!("Failed to save session: {}", e))?;
+
+    // Update the search index
+    update_session_index(&state, session)?;
     Ok(())
 }
 
+/// Moves a session to the trash. It can be brought back with `restore_session` until it is
+/// purged, see `storage.trash_retention_days`.
 #[tauri::command]
 fn delete_session(id: String, state: State<'_, Arc<AppState>>) -> Result<(), String> {
     let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
-    if sessions.remove(&id).is_some() {
-        delete_session_file(&id).map_err(|e| format!("Failed to delete session file: {}", e))?;
-        
-        // Remove from search index
-        remove_session_from_index(&state, &id)?;
+    let mut current_id_guard = state.current_session_id.lock().map_err(|e| format!("Failed to lock current_session_id: {}", e))?;
+    if let Some(mut session) = match sessions.remove(&id) {
+        Some(session) => Some(session),
+        None => state.store.load(&id)?,
+    } {
+        // An untouched new chat has nothing worth restoring and may never have been saved.
+        if session.is_pristine() {
+            state.store.delete(&id).map_err(|e| format!("Failed to delete session: {}", e))?;
+        } else {
+            session.deleted_at = Some(now_ts());
+            state.store.save(&session).map_err(|e| format!("Failed to save session: {}", e))?;
+        }
+    }
+    if current_id_guard.as_deref() == Some(id.as_str()) {
+        *current_id_guard = None;
     }
+
+    // Remove from search index
+    remove_session_from_index(&state, &id)?;
     Ok(())
 }
 
+/// Takes a session out of the trash.
+#[tauri::command]
+fn restore_session(id: String, state: State<'_, Arc<AppState>>) -> Result<storage::SessionSummary, String> {
+    let session = storage::restore_session(state.store.as_ref(), &id)?;
+    update_session_index(&state, &session)?;
+    Ok(storage::SessionSummary::of(&session))
+}
+
+/// Permanently deletes the given sessions from the trash, or everything in it if `ids` is `None`.
+/// Returns how many sessions were deleted.
+#[tauri::command]
+fn empty_trash(ids: Option<Vec<String>>, state: State<'_, Arc<AppState>>) -> Result<usize, String> {
+    let trashed = state.store.list(&storage::ListQuery {
+        trashed: true,
+        limit: usize::MAX,
+        ..storage::ListQuery::default()
+    })?;
+    let mut deleted = 0;
+    for summary in trashed {
+        if ids.as_ref().map_or(true, |ids| ids.contains(&summary.id)) {
+            state.store.delete(&summary.id).map_err(|e| format!("Failed to delete session: {}", e))?;
+            deleted += 1;
+        }
+    }
+    info!(deleted, "Emptied trash");
+    Ok(deleted)
+}
+
 #[tauri::command]
 fn open_config_file() -> Result<(), String> {
     let path = get_app_config_path();
     opener::open(&path).map_err(|e| format!("Failed to open config file: {}", e))
 }
 
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionPage {
+    items: Vec<storage::SessionSummary>,
+    /// Pass back as `cursor` to fetch the next page; `None` on the last page.
+    next_cursor: Option<String>,
+}
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Lists session summaries for the sidebar, one page at a time.
+#[tauri::command]
+async fn list_sessions(
+    cursor: Option<String>,
+    limit: Option<usize>,
+    sort: Option<storage::SessionSort>,
+    pinned: Option<bool>,
+    folder: Option<String>,
+    trashed: Option<bool>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<SessionPage, String> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let after = cursor
+        .as_deref()
+        .map(serde_json::from_str::<storage::Cursor>)
+        .transpose()
+        .map_err(|e| format!("Invalid cursor: {}", e))?;
+    let is_first_page = after.is_none();
+    let query = storage::ListQuery {
+        sort: sort.unwrap_or_default(),
+        after,
+        // One extra row tells us whether there is another page.
+        limit: limit + 1,
+        pinned,
+        folder,
+        trashed: trashed.unwrap_or(false),
+    };
+
+    let mut items = state.store.list(&query)?;
+    let next_cursor = if items.len() > limit {
+        items.truncate(limit);
+        let last = items.last().expect("page is not empty");
+        let cursor = storage::Cursor {
+            key: query.sort.key(last),
+            id: last.id.clone(),
+        };
+        Some(serde_json::to_string(&cursor).map_err(|e| e.to_string())?)
+    } else {
+        None
+    };
+
+    // A new chat lives only in memory until its first message; show it on top of the first page.
+    if is_first_page {
+        let sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+        let unsaved: Vec<_> = sessions
+            .values()
+            .filter(|s| s.messages.is_empty() && !items.iter().any(|i| i.id == s.id))
+            .map(storage::SessionSummary::of)
+            .filter(|s| query.matches(s))
+            .collect();
+        items.splice(0..0, unsaved);
+    }
+
+    Ok(SessionPage { items, next_cursor })
+}
+
+/// Updates an organizational field of a session. These do not count as activity, so
+/// `updated_at` is left alone and the session keeps its place in the list.
+fn update_session_meta(
+    state: &AppState,
+    id: &str,
+    update: impl FnOnce(&mut ChatSession),
+) -> Result<storage::SessionSummary, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    let session = cached_session(state.store.as_ref(), &mut sessions, id)?
+        .ok_or_else(|| "Session not found".to_string())?;
+    update(session);
+    state.store.save(session).map_err(|e| format!("Failed to save session: {}", e))?;
+    update_session_index(state, session)?;
+    Ok(storage::SessionSummary::of(session))
+}
+
+/// Replaces a session's tags. Tags are normalized (see `history::normalize_tag`) and deduplicated.
 #[tauri::command]
-async fn get_all_sessions(state: State<'_, Arc<AppState>>) -> Result<Vec<ChatSession>, String> {
-    let sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
-    let mut list: Vec<_> = sessions.values().cloned().collect();
-    list.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
-    Ok(list)
+fn set_session_tags(
+    id: String,
+    tags: Vec<String>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<storage::SessionSummary, String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags.iter().filter_map(|t| history::normalize_tag(t)) {
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    update_session_meta(&state, &id, |session| session.tags = normalized)
 }
 
+/// Moves a session into a folder, or out of any folder if `folder` is `None` or blank.
 #[tauri::command]
-async fn search_chat_sessions(query: String, state: State<'_, Arc<AppState>>) -> Result<Vec<ChatSession>, String> {
+fn set_session_folder(
+    id: String,
+    folder: Option<String>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<storage::SessionSummary, String> {
+    let folder = folder.map(|f| f.trim().to_string()).filter(|f| !f.is_empty());
+    update_session_meta(&state, &id, |session| session.folder = folder)
+}
+
+#[tauri::command]
+fn set_session_pinned(
+    id: String,
+    pinned: bool,
+    state: State<'_, Arc<AppState>>,
+) -> Result<storage::SessionSummary, String> {
+    update_session_meta(&state, &id, |session| session.pinned = pinned)
+}
+
+#[tauri::command]
+fn list_folders(state: State<'_, Arc<AppState>>) -> Result<Vec<String>, String> {
+    state.store.folders()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MessageRange {
+    /// Index of the first message; `None` means "the last `limit` messages".
+    start: Option<usize>,
+    limit: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MessagePage {
+    messages: Vec<BranchMessage>,
+    /// Index of `messages[0]` within the active branch.
+    start: usize,
+    total: usize,
+}
+
+/// Loads a slice of a session's active branch, so long chats can be rendered incrementally.
+#[tauri::command]
+async fn get_session_messages(
+    id: String,
+    range: Option<MessageRange>,
+    state: State<'_, Arc<AppState>>,
+) -> Result<MessagePage, String> {
+    let range = range.unwrap_or(MessageRange {
+        start: None,
+        limit: usize::MAX,
+    });
+
+    // The cached copy is authoritative for sessions opened in this run. Others are read a page
+    // at a time without being cached.
+    let page = {
+        let sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+        sessions
+            .get(&id)
+            .map(|session| storage::branch_page(session, None, range.start, range.limit))
+    };
+    let page = match page {
+        Some(page) => page,
+        None => state
+            .store
+            .load_messages(&id, None, range.start, range.limit)?
+            .ok_or_else(|| "Session not found".to_string())?,
+    };
+    Ok(MessagePage {
+        messages: page.messages.into_iter().map(BranchMessage::from).collect(),
+        start: page.start,
+        total: page.total,
+    })
+}
+
+/// A message on the active branch, with its position among the alternatives at that point.
+#[derive(Debug, Serialize)]
+struct BranchMessage {
+    #[serde(flatten)]
+    message: ChatMessage,
+    #[serde(flatten)]
+    siblings: history::SiblingInfo,
+}
+
+impl From<(ChatMessage, history::SiblingInfo)> for BranchMessage {
+    fn from((message, siblings): (ChatMessage, history::SiblingInfo)) -> Self {
+        Self { message, siblings }
+    }
+}
+
+/// A session as the chat view shows it: only the messages on the active branch.
+#[derive(Debug, Serialize)]
+struct SessionView {
+    id: String,
+    title: String,
+    created_at: u64,
+    updated_at: u64,
+    tags: Vec<String>,
+    active_leaf: Option<String>,
+    messages: Vec<BranchMessage>,
+}
+
+impl SessionView {
+    fn of(session: &ChatSession) -> Self {
+        Self {
+            id: session.id.clone(),
+            title: session.title.clone(),
+            created_at: session.created_at,
+            updated_at: session.updated_at,
+            tags: session.tags.clone(),
+            active_leaf: session.active_leaf.clone(),
+            messages: branch_messages(session),
+        }
+    }
+}
+
+fn branch_messages(session: &ChatSession) -> Vec<BranchMessage> {
+    storage::branch_page(session, None, None, usize::MAX)
+        .messages
+        .into_iter()
+        .map(BranchMessage::from)
+        .collect()
+}
+
+/// Returns the session files that were unreadable at startup, including ones restored from backup.
+#[tauri::command]
+fn get_session_load_issues(state: State<'_, Arc<AppState>>) -> Result<Vec<storage::LoadIssue>, String> {
+    let issues = state.load_issues.lock().map_err(|e| format!("Failed to lock load_issues: {}", e))?;
+    Ok(issues.clone())
+}
+
+/// Returns everything the agent remembers, most recently updated first.
+#[tauri::command]
+fn list_memories(state: State<'_, Arc<AppState>>) -> Result<Vec<memory::MemoryEntry>, String> {
+    match &state.memory {
+        Some(memory) => memory.list(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Deletes a memory by id.
+#[tauri::command]
+fn delete_memory(id: String, state: State<'_, Arc<AppState>>) -> Result<(), String> {
+    let memory = state.memory.as_ref().ok_or("Memory is unavailable")?;
+    match memory.delete(&id)? {
+        Some(entry) => {
+            info!(key = %entry.key, "Deleted memory");
+            Ok(())
+        }
+        None => Err(format!("Memory '{}' not found", id)),
+    }
+}
+
+/// A search hit with the title of its session, for the result list.
+#[derive(Debug, Serialize)]
+struct SearchHitView {
+    #[serde(flatten)]
+    hit: search::SearchHit,
+    title: String,
+}
+
+/// Returns the matching messages, best first, each with a highlighted snippet. Open a hit with
+/// `select_session` and, if the message is not on the active branch, `switch_branch`.
+#[tauri::command]
+async fn search_chat_sessions(query: String, state: State<'_, Arc<AppState>>) -> Result<Vec<SearchHitView>, String> {
     info!(search_query = %query, "Searching chat sessions");
-    let searcher = state.searcher.lock().map_err(|e| format!("Failed to lock searcher: {}", e))?;
-    let results = searcher.search(&query)?;
+    let mut hits = state.searcher.search(&query)?;
+    if let Some(embedder) = &state.embedder {
+        hits = embedder.hybrid_search(&state.searcher, &query, hits).await;
+    }
 
-    let sessions_map = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
-    let mut found_sessions = Vec::new();
+    let mut sessions_map = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    let mut found = Vec::new();
 
-    for result in results {
-        if let Some(session) = sessions_map.get(&result.session_id) {
-            found_sessions.push(session.clone());
+    for hit in hits {
+        match cached_session(state.store.as_ref(), &mut sessions_map, &hit.session_id) {
+            Ok(Some(session)) => {
+                let title = session.title.clone();
+                found.push(SearchHitView { hit, title });
+            }
+            Ok(None) => {}
+            // E.g. moved to the trash after the hit was indexed.
+            Err(e) => warn!(session_id = %hit.session_id, "Skipping search hit: {}", e),
         }
     }
-    Ok(found_sessions)
+    Ok(found)
+}
+
+/// Finalizes the session being switched away from. An untouched new chat is discarded; anything
+/// else is kept, so switching chats never loses work.
+fn leave_session(state: &AppState, sessions: &mut HashMap<String, ChatSession>, old_id: &str) -> Result<(), String> {
+    let Some(session) = sessions.get_mut(old_id) else {
+        return Ok(());
+    };
+    if session.is_pristine() {
+        sessions.remove(old_id);
+        state.store.delete(old_id).map_err(|e| format!("Failed to delete session: {}", e))?;
+        return remove_session_from_index(state, old_id);
+    }
+    // Normally titled after the first exchange already; this covers runs that never finished one.
+    if session.title_source == TitleSource::Placeholder && !session.messages.is_empty() {
+        session.title = title::heuristic_title(&session.messages);
+        session.title_source = TitleSource::Heuristic;
+        session.updated_at = now_ts();
+    }
+    state.store.save(session).map_err(|e| format!("Failed to save session: {}", e))?;
+
+    // Update the search index
+    update_session_index(state, session)
 }
 
 #[tauri::command]
-async fn finalize_and_new_chat(state: State<'_, Arc<AppState>>) -> Result<ChatSession, String> {
+async fn finalize_and_new_chat(state: State<'_, Arc<AppState>>) -> Result<SessionView, String> {
     let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
     let mut current_id_guard = state.current_session_id.lock().map_err(|e| format!("Failed to lock current_session_id: {}", e))?;
 
     if let Some(old_id) = current_id_guard.clone() {
-        let is_empty = sessions
-            .get(&old_id)
-            .map_or(true, |s| s.messages.is_empty());
-
-        if is_empty {
-            sessions.remove(&old_id);
-            delete_session_file(&old_id).map_err(|e| format!("Failed to delete session file: {}", e))?;
-            
-            // Remove from search index
-            remove_session_from_index(&state, &old_id)?;
-        } else {
-            if let Some(session) = sessions.get_mut(&old_id) {
-                session.title = generate_session_title(&session.messages);
-                session.updated_at = now_ts();
-                save_session_to_file(session).map_err(|e| format!("Failed to save session: {}", e))?;
-                
-                // Update the search index
-                update_session_index(&state, session)?;
-            }
-        }
+        leave_session(&state, &mut sessions, &old_id)?;
     }
 
     let new_id = Uuid::new_v4().to_string();
-    let new_session = ChatSession::new(new_id.clone(), "New Chat".to_string());
+    let new_session = ChatSession::new(new_id.clone(), title::PLACEHOLDER_TITLE.to_string());
     sessions.insert(new_id.clone(), new_session.clone());
     *current_id_guard = Some(new_id.clone());
 
@@ -800,49 +1323,311 @@ async fn finalize_and_new_chat(state: State<'_, Arc<AppState>>) -> Result<ChatSe
     // It will be saved when a message is added.
     // Also no need to index an empty session.
 
-    Ok(new_session)
+    Ok(SessionView::of(&new_session))
 }
 
 #[tauri::command]
 async fn select_session(
     id_to_select: String,
     state: State<'_, Arc<AppState>>,
-) -> Result<ChatSession, String> {
+) -> Result<SessionView, String> {
     let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
     let mut current_id_guard = state.current_session_id.lock().map_err(|e| format!("Failed to lock current_session_id: {}", e))?;
 
     if let Some(old_id) = current_id_guard.clone() {
         if old_id != id_to_select {
-            let is_empty = sessions
-                .get(&old_id)
-                .map_or(true, |s| s.messages.is_empty());
-
-            if is_empty {
-                sessions.remove(&old_id);
-                
-                // Remove from search index
-                remove_session_from_index(&state, &old_id)?;
-            } else {
-                if let Some(old_session) = sessions.get_mut(&old_id) {
-                    old_session.title = generate_session_title(&old_session.messages);
-                    old_session.updated_at = now_ts();
-                    save_session_to_file(old_session).map_err(|e| format!("Failed to save session: {}", e))?;
-                    
-                    // Update the search index
-                    update_session_index(&state, old_session)?;
-                }
-            }
+            leave_session(&state, &mut sessions, &old_id)?;
         }
     }
 
-    if let Some(new_session) = sessions.get(&id_to_select) {
+    if let Some(new_session) = cached_session(state.store.as_ref(), &mut sessions, &id_to_select)? {
+        let view = SessionView::of(new_session);
         *current_id_guard = Some(id_to_select);
-        Ok(new_session.clone())
+        Ok(view)
     } else {
         Err("Session to select not found".to_string())
     }
 }
 
+/// Starts a new branch after `message_id` (or at the very beginning if `None`); the next prompt
+/// is added there. With `new_session`, the path up to `message_id` is copied into a new session
+/// instead, which becomes the current one.
+#[tauri::command]
+async fn fork_from_message(
+    session_id: String,
+    message_id: Option<String>,
+    new_session: bool,
+    state: State<'_, Arc<AppState>>,
+) -> Result<SessionView, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    let session = cached_session(state.store.as_ref(), &mut sessions, &session_id)?
+        .ok_or_else(|| "Session not found".to_string())?;
+    if let Some(id) = &message_id {
+        if session.get_message(id).is_none() {
+            return Err("Message not found".to_string());
+        }
+    }
+
+    if !new_session {
+        session.active_leaf = message_id;
+        state.store.save(session).map_err(|e| format!("Failed to save session: {}", e))?;
+        return Ok(SessionView::of(session));
+    }
+
+    let title = format!("{} (fork)", session.title);
+    let mut fork = session.fork(message_id.as_deref(), Uuid::new_v4().to_string(), title);
+    fork.title_source = TitleSource::Manual;
+    state.store.save(&fork).map_err(|e| format!("Failed to save session: {}", e))?;
+    update_session_index(&state, &fork)?;
+
+    let view = SessionView::of(&fork);
+    *state.current_session_id.lock().map_err(|e| format!("Failed to lock current_session_id: {}", e))? =
+        Some(fork.id.clone());
+    sessions.insert(fork.id.clone(), fork);
+    Ok(view)
+}
+
+/// Shows the branch through `message_id`, continuing down to its most recent leaf.
+#[tauri::command]
+async fn switch_branch(
+    session_id: String,
+    message_id: String,
+    state: State<'_, Arc<AppState>>,
+) -> Result<SessionView, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    let session = cached_session(state.store.as_ref(), &mut sessions, &session_id)?
+        .ok_or_else(|| "Session not found".to_string())?;
+    activate_branch(&state, session, &message_id)
+}
+
+/// Moves from `message_id` to the sibling `step` places away (wrapping around), e.g. -1 for the
+/// previous alternative answer.
+#[tauri::command]
+async fn switch_sibling(
+    session_id: String,
+    message_id: String,
+    step: i32,
+    state: State<'_, Arc<AppState>>,
+) -> Result<SessionView, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    let session = cached_session(state.store.as_ref(), &mut sessions, &session_id)?
+        .ok_or_else(|| "Session not found".to_string())?;
+    let siblings = session.siblings_of(&message_id);
+    let position = siblings
+        .iter()
+        .position(|m| m.id == message_id)
+        .ok_or_else(|| "Message not found".to_string())?;
+    let target = (position as i64 + step as i64).rem_euclid(siblings.len() as i64) as usize;
+    let target_id = siblings[target].id.clone();
+    activate_branch(&state, session, &target_id)
+}
+
+fn activate_branch(state: &AppState, session: &mut ChatSession, message_id: &str) -> Result<SessionView, String> {
+    if session.get_message(message_id).is_none() {
+        return Err("Message not found".to_string());
+    }
+    session.active_leaf = Some(session.latest_leaf_under(message_id));
+    state.store.save(session).map_err(|e| format!("Failed to save session: {}", e))?;
+    Ok(SessionView::of(session))
+}
+
+// --- Export Commands ---
+
+/// Waits for a file dialog without blocking a runtime worker. `show` opens the dialog with the
+/// callback it is given.
+async fn await_dialog(show: impl FnOnce(Box<dyn FnOnce(Option<FilePath>) + Send>)) -> Result<Option<PathBuf>, String> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    show(Box::new(move |picked| {
+        tx.send(picked).ok();
+    }));
+    match rx.await.map_err(|_| "The file dialog closed without an answer".to_string())? {
+        Some(picked) => picked.into_path().map(Some).map_err(|e| format!("Invalid path: {}", e)),
+        None => Ok(None),
+    }
+}
+
+/// Loads a session for export, preferring the cached copy which may be newer than the store.
+fn session_for_export(state: &AppState, id: &str) -> Result<ChatSession, String> {
+    let mut sessions = state.sessions.lock().map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    cached_session(state.store.as_ref(), &mut sessions, id)?
+        .map(|s| s.clone())
+        .ok_or_else(|| format!("Session {} not found", id))
+}
+
+/// Writes one session to `path`, or to a file picked in a save dialog if `path` is `None`.
+/// Returns the written path, or `None` if the dialog was cancelled.
+#[tauri::command]
+async fn export_session(
+    id: String,
+    format: export::ExportFormat,
+    path: Option<String>,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Option<String>, String> {
+    let session = session_for_export(&state, &id)?;
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let picked = await_dialog(|done| {
+                app.dialog()
+                    .file()
+                    .set_file_name(export::file_name(&session, format))
+                    .add_filter(format.label(), &[format.extension()])
+                    .save_file(done)
+            })
+            .await?;
+            match picked {
+                Some(picked) => picked,
+                None => return Ok(None),
+            }
+        }
+    };
+
+    let content = export::render(&session, format, now_ts())?;
+    fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    info!(session_id = %id, path = %path.display(), "Exported session");
+    Ok(Some(path.display().to_string()))
+}
+
+/// Writes each session to its own file in `dir`, or in a folder picked in a dialog if `dir` is
+/// `None`. Returns the written paths; empty if the dialog was cancelled.
+#[tauri::command]
+async fn export_sessions(
+    ids: Vec<String>,
+    format: export::ExportFormat,
+    dir: Option<String>,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Vec<String>, String> {
+    let dir = match dir {
+        Some(dir) => PathBuf::from(dir),
+        None => match await_dialog(|done| app.dialog().file().pick_folder(done)).await? {
+            Some(picked) => picked,
+            None => return Ok(Vec::new()),
+        },
+    };
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let exported_at = now_ts();
+    let mut written = Vec::with_capacity(ids.len());
+    for id in &ids {
+        let session = session_for_export(&state, id)?;
+        let path = dir.join(export::file_name(&session, format));
+        let content = export::render(&session, format, exported_at)?;
+        fs::write(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        written.push(path.display().to_string());
+    }
+    info!(count = written.len(), dir = %dir.display(), "Exported sessions");
+    Ok(written)
+}
+
+// --- Import Commands ---
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum ImportStatus {
+    Imported,
+    /// Imported before; the existing session was left as it is.
+    Duplicate,
+    Failed,
+}
+
+/// The outcome for one conversation of an import.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportResult {
+    title: String,
+    status: ImportStatus,
+    session_id: Option<String>,
+    message_count: usize,
+    error: Option<String>,
+}
+
+/// Imports the conversations in `path`, or in a file picked in a dialog if `path` is `None`.
+/// The format is guessed from the extension unless given. Returns `None` if the dialog was
+/// cancelled.
+#[tauri::command]
+async fn import_conversations(
+    path: Option<String>,
+    format: Option<importer::ImportFormat>,
+    app: AppHandle,
+    state: State<'_, Arc<AppState>>,
+) -> Result<Option<Vec<ImportResult>>, String> {
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let picked = await_dialog(|done| {
+                app.dialog()
+                    .file()
+                    .add_filter("Chat exports", &["json", "jsonl"])
+                    .pick_file(done)
+            })
+            .await?;
+            match picked {
+                Some(picked) => picked,
+                None => return Ok(None),
+            }
+        }
+    };
+    let format = format.unwrap_or_else(|| importer::ImportFormat::detect(&path));
+    let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let default_title = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Imported chat")
+        .to_string();
+    let conversations = importer::parse(&content, format, &default_title)?;
+
+    let mut results = Vec::with_capacity(conversations.len());
+    for conversation in conversations {
+        let session = match conversation.result {
+            Ok(session) => session,
+            Err(e) => {
+                results.push(ImportResult {
+                    title: conversation.label,
+                    status: ImportStatus::Failed,
+                    session_id: None,
+                    message_count: 0,
+                    error: Some(e),
+                });
+                continue;
+            }
+        };
+
+        let mut result = ImportResult {
+            title: session.title.clone(),
+            status: ImportStatus::Imported,
+            session_id: Some(session.id.clone()),
+            message_count: session.messages.len(),
+            error: None,
+        };
+        let saved = match state.store.load(&session.id) {
+            Ok(Some(_)) => {
+                result.status = ImportStatus::Duplicate;
+                Ok(())
+            }
+            Ok(None) => state
+                .store
+                .save(&session)
+                .and_then(|_| update_session_index(&state, &session)),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            result.status = ImportStatus::Failed;
+            result.error = Some(e);
+        }
+        results.push(result);
+    }
+
+    info!(
+        path = %path.display(),
+        imported = results.iter().filter(|r| matches!(r.status, ImportStatus::Imported)).count(),
+        total = results.len(),
+        "Imported conversations"
+    );
+    Ok(Some(results))
+}
+
 /// Reads the last `n` lines from a file.
 /// This is useful for reading the tail of a log file.
 fn read_last_lines_of_file(path: &std::path::Path, n: usize) -> Result<String, std::io::Error> {
@@ -913,26 +1698,73 @@ fn setup_app(app: &mut App) -> Result<(), Box<dyn std::error::Error>> {
 fn main() {
     setup_logging();
     let config = load_or_initialize_config();
-    let sessions = load_sessions_from_files();
-    let searcher = search::Searcher::new().expect("Failed to create searcher");
-
-    // Rebuild index on startup
-    let sessions_vec: Vec<ChatSession> = sessions.values().cloned().collect();
-    if let Err(e) = searcher.rebuild_index(sessions_vec) {
-        error!("Failed to rebuild index on startup: {}", e);
+    let (store, load_issues) = storage::open(&config.storage).expect("Failed to open session store");
+    match storage::purge_expired_trash(store.as_ref(), config.storage.trash_retention_days, now_ts()) {
+        Ok(0) => {}
+        Ok(purged) => info!(purged, "Purged expired sessions from the trash"),
+        Err(e) => error!("Failed to purge the trash: {}", e),
+    }
+    let searcher = search::Searcher::new(&config.search).expect("Failed to create searcher");
+    let embedder = if config.embeddings.enabled {
+        llm::LlmClient::new(config.openai.api_key.clone(), config.openai.base_url.clone())
+            .map_err(|e| e.to_string())
+            .and_then(|client| semantic::Embedder::start(config.embeddings.clone(), client, &get_app_data_dir()))
+            .map_err(|e| error!("Semantic search is unavailable: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    let knowledge = if config.knowledge.folders.is_empty() {
+        None
+    } else {
+        knowledge::Knowledge::start(config.knowledge.clone(), &config.search, &get_app_data_dir())
+            .map_err(|e| error!("The knowledge base is unavailable: {}", e))
+            .ok()
+    };
+    let memory = if config.memory.enabled {
+        memory::Memory::open(&get_app_data_dir(), &config.search)
+            .map_err(|e| error!("Memory is unavailable: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    let builtin_tools = tools::ToolRegistry::new(&config.tools, &config.knowledge, &config.memory);
+    // Listed like an MCP server's tools, so they can be switched on and off in the Tools menu.
+    let mcp_tools = HashMap::from([(tools::SERVER_NAME.to_string(), builtin_tools.names())]);
+
+    // Catch up on sessions changed since they were last indexed; this runs in the background.
+    match store.load_all() {
+        Ok(sessions_vec) => {
+            let live: Vec<_> = sessions_vec.into_iter().filter(|s| s.deleted_at.is_none()).collect();
+            if let Some(Err(e)) = embedder.as_ref().map(|embedder| embedder.sync(live.clone())) {
+                error!("Failed to sync embeddings on startup: {}", e);
+            }
+            if let Err(e) = searcher.sync(live) {
+                error!("Failed to sync the search index on startup: {}", e);
+            }
+        }
+        Err(e) => error!("Failed to load sessions for indexing: {}", e),
     }
 
     let app_state = Arc::new(AppState {
         config: Mutex::new(config),
-        sessions: Mutex::new(sessions),
+        sessions: Mutex::new(HashMap::new()),
+        store,
         current_session_id: Mutex::new(None),
         tool_states: Mutex::new(HashMap::new()),
-        mcp_tools: Mutex::new(HashMap::new()),
+        mcp_tools: Mutex::new(mcp_tools),
         mcp_clients: Mutex::new(HashMap::new()),
-        searcher: Mutex::new(searcher),
+        builtin_tools,
+        searcher,
+        embedder,
+        knowledge,
+        memory,
+        plans: Mutex::new(HashMap::new()),
+        load_issues: Mutex::new(load_issues),
     });
 
     tauri::Builder::default()
+        .plugin(tauri_plugin_dialog::init())
         .manage(app_state)
         .setup(setup_app)
         .invoke_handler(tauri::generate_handler![
@@ -943,15 +1775,43 @@ fn main() {
             get_all_discovered_tools, // Add the new command
             // Agent
             run_agent_task,
+            regenerate_response,
+            update_plan,
+            get_plan,
             // Session
-            get_all_sessions,
+            list_sessions,
+            get_session_messages,
+            get_session_load_issues,
             search_chat_sessions,
             finalize_and_new_chat,
             select_session,
+            export_session,
+            export_sessions,
+            import_conversations,
+            fork_from_message,
+            switch_branch,
+            switch_sibling,
             open_config_file,
             rename_session,
-            delete_session
+            set_session_tags,
+            set_session_folder,
+            set_session_pinned,
+            list_folders,
+            delete_session,
+            restore_session,
+            empty_trash,
+            // Memory
+            list_memories,
+            delete_memory
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Commit index changes still waiting for their batch.
+            if let tauri::RunEvent::Exit = event {
+                if let Err(e) = app.state::<Arc<AppState>>().searcher.flush() {
+                    error!("Failed to flush the search index: {}", e);
+                }
+            }
+        });
 }
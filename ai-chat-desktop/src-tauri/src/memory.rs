@@ -0,0 +1,338 @@
+//! Long-term memory: facts the agent saves with `remember` and gets back in later sessions.
+//!
+//! Memories are kept in `<data>/memory.db`, one row per key, and searched through an in-memory
+//! Tantivy index rebuilt from the database at startup; there are few enough of them for that.
+//! The ones relevant to a prompt are added to the system prompt, and the user can review and
+//! delete them from the sidebar.
+
+use crate::search::{MixedTokenizer, SearchConfig, TOKENIZER};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Mutex;
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::*;
+use tantivy::{doc, Index, IndexWriter, ReloadPolicy, TantivyDocument, Term};
+use uuid::Uuid;
+
+const DB_FILE: &str = "memory.db";
+const MEMORY_ARENA_NUM_BYTES: usize = 15_000_000;
+// Memories are short facts, not documents.
+const MAX_CONTENT_CHARS: usize = 2_000;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS memories (
+    id             TEXT PRIMARY KEY,
+    key            TEXT NOT NULL UNIQUE,
+    content        TEXT NOT NULL,
+    source_session TEXT,
+    created_at     INTEGER NOT NULL,
+    updated_at     INTEGER NOT NULL
+);
+";
+const COLUMNS: &str = "id, key, content, source_session, created_at, updated_at";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MemoryConfig {
+    /// Off unless set: memories are added to the instructions of every chat, so the user should
+    /// choose to have them.
+    #[serde(default)]
+    pub enabled: bool,
+    /// How many memories relevant to a prompt are added to the system prompt.
+    #[serde(default = "default_max_injected")]
+    pub max_injected: usize,
+}
+
+fn default_max_injected() -> usize {
+    5
+}
+
+impl Default for MemoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_injected: default_max_injected(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct MemoryEntry {
+    pub id: String,
+    /// Normalized with `normalize_key`; remembering under an existing key replaces its content.
+    pub key: String,
+    pub content: String,
+    /// The session the agent was in when it saved this.
+    pub source_session: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl MemoryEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(MemoryEntry {
+            id: row.get(0)?,
+            key: row.get(1)?,
+            content: row.get(2)?,
+            source_session: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+        })
+    }
+}
+
+/// Lowercases a key and joins its words with `-`, so "Preferred editor" and "preferred-editor"
+/// are the same memory. Returns `None` for a blank key.
+pub fn normalize_key(key: &str) -> Option<String> {
+    let key = key.split_whitespace().collect::<Vec<_>>().join("-").to_lowercase();
+    (!key.is_empty()).then_some(key)
+}
+
+pub struct Memory {
+    conn: Mutex<Connection>,
+    index: Index,
+    writer: Mutex<IndexWriter>,
+}
+
+impl Memory {
+    pub fn open(data_dir: &Path, analyzer: &SearchConfig) -> Result<Self, String> {
+        let path = data_dir.join(DB_FILE);
+        let conn = Connection::open(&path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(|e| e.to_string())?;
+        Self::with_connection(conn, analyzer)
+    }
+
+    fn with_connection(conn: Connection, analyzer: &SearchConfig) -> Result<Self, String> {
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create schema: {}", e))?;
+        let index = Index::create_in_ram(Self::build_schema());
+        index.tokenizers().register(TOKENIZER, MixedTokenizer::new(analyzer));
+        let writer = index
+            .writer(MEMORY_ARENA_NUM_BYTES)
+            .map_err(|e| format!("Failed to create memory index writer: {}", e))?;
+        let memory = Memory {
+            conn: Mutex::new(conn),
+            index,
+            writer: Mutex::new(writer),
+        };
+        let entries = memory.list()?;
+        {
+            let mut writer = memory.lock_writer()?;
+            for entry in &entries {
+                writer.add_document(memory.document(entry)).map_err(|e| e.to_string())?;
+            }
+            writer.commit().map_err(|e| e.to_string())?;
+        }
+        Ok(memory)
+    }
+
+    fn build_schema() -> Schema {
+        let text_options = TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(TOKENIZER)
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        );
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("id", STRING | STORED);
+        schema_builder.add_text_field("key", text_options.clone());
+        schema_builder.add_text_field("content", text_options);
+        schema_builder.build()
+    }
+
+    fn field(&self, name: &str) -> Field {
+        self.index.schema().get_field(name).unwrap()
+    }
+
+    fn document(&self, entry: &MemoryEntry) -> TantivyDocument {
+        // Keys are written with dashes; index them as words.
+        doc!(
+            self.field("id") => entry.id.as_str(),
+            self.field("key") => entry.key.replace('-', " "),
+            self.field("content") => entry.content.as_str(),
+        )
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.conn.lock().map_err(|e| format!("Failed to lock memory database: {}", e))
+    }
+
+    fn lock_writer(&self) -> Result<std::sync::MutexGuard<'_, IndexWriter>, String> {
+        self.writer.lock().map_err(|e| format!("Failed to lock memory index: {}", e))
+    }
+
+    /// Saves `content` under `key`, replacing what was there.
+    pub fn remember(&self, key: &str, content: &str, source_session: Option<&str>, now: u64) -> Result<MemoryEntry, String> {
+        let key = normalize_key(key).ok_or("The key is empty")?;
+        let content = content.trim();
+        if content.is_empty() {
+            return Err("The content is empty".to_string());
+        }
+        let content: String = content.chars().take(MAX_CONTENT_CHARS).collect();
+        let entry = {
+            let conn = self.lock()?;
+            conn.execute(
+                "INSERT INTO memories (id, key, content, source_session, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?5)
+                 ON CONFLICT(key) DO UPDATE SET content = excluded.content,
+                 source_session = excluded.source_session, updated_at = excluded.updated_at",
+                params![Uuid::new_v4().to_string(), key, content, source_session, now],
+            )
+            .map_err(|e| e.to_string())?;
+            conn.query_row(
+                &format!("SELECT {} FROM memories WHERE key = ?1", COLUMNS),
+                [&key],
+                MemoryEntry::from_row,
+            )
+            .map_err(|e| e.to_string())?
+        };
+        let mut writer = self.lock_writer()?;
+        writer.delete_term(Term::from_field_text(self.field("id"), &entry.id));
+        writer.add_document(self.document(&entry)).map_err(|e| e.to_string())?;
+        writer.commit().map_err(|e| e.to_string())?;
+        Ok(entry)
+    }
+
+    /// Deletes the memory with this key or id, as the model may give either. Returns it if there
+    /// was one.
+    pub fn forget(&self, key_or_id: &str) -> Result<Option<MemoryEntry>, String> {
+        let key = normalize_key(key_or_id).unwrap_or_default();
+        self.delete_where("id = ?1 OR key = ?2", params![key_or_id, key])
+    }
+
+    /// Deletes the memory with this id only. Returns it if there was one.
+    pub fn delete(&self, id: &str) -> Result<Option<MemoryEntry>, String> {
+        self.delete_where("id = ?1", params![id])
+    }
+
+    fn delete_where(&self, condition: &str, params: impl rusqlite::Params) -> Result<Option<MemoryEntry>, String> {
+        let entry = {
+            let conn = self.lock()?;
+            let entry = conn
+                .query_row(
+                    &format!("SELECT {} FROM memories WHERE {}", COLUMNS, condition),
+                    params,
+                    MemoryEntry::from_row,
+                )
+                .optional()
+                .map_err(|e| e.to_string())?;
+            let Some(entry) = entry else { return Ok(None) };
+            conn.execute("DELETE FROM memories WHERE id = ?1", [&entry.id])
+                .map_err(|e| e.to_string())?;
+            entry
+        };
+        let mut writer = self.lock_writer()?;
+        writer.delete_term(Term::from_field_text(self.field("id"), &entry.id));
+        writer.commit().map_err(|e| e.to_string())?;
+        Ok(Some(entry))
+    }
+
+    /// All memories, most recently updated first.
+    pub fn list(&self) -> Result<Vec<MemoryEntry>, String> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM memories ORDER BY updated_at DESC, key", COLUMNS))
+            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map([], MemoryEntry::from_row).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    /// The memories that best match `query`, best first.
+    pub fn recall(&self, query: &str, limit: usize) -> Result<Vec<MemoryEntry>, String> {
+        let reader = self
+            .index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()
+            .map_err(|e| e.to_string())?;
+        let searcher = reader.searcher();
+        // Queries are prompts or come from the model: take what parses.
+        let parser = QueryParser::for_index(&self.index, vec![self.field("key"), self.field("content")]);
+        let (query, _) = parser.parse_query_lenient(query);
+        let top_docs = searcher
+            .search(&query, &TopDocs::with_limit(limit))
+            .map_err(|e| e.to_string())?;
+
+        let conn = self.lock()?;
+        let mut entries = Vec::with_capacity(top_docs.len());
+        for (_, doc_address) in top_docs {
+            let doc = searcher.doc::<TantivyDocument>(doc_address).map_err(|e| e.to_string())?;
+            let Some(id) = doc.get_first(self.field("id")).and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let entry = conn
+                .query_row(&format!("SELECT {} FROM memories WHERE id = ?1", COLUMNS), [id], MemoryEntry::from_row)
+                .optional()
+                .map_err(|e| e.to_string())?;
+            entries.extend(entry);
+        }
+        Ok(entries)
+    }
+}
+
+/// The system prompt section listing `entries`, or `None` if there are none.
+pub fn prompt_section(entries: &[MemoryEntry]) -> Option<String> {
+    if entries.is_empty() {
+        return None;
+    }
+    let lines: Vec<String> = entries.iter().map(|e| format!("- {}: {}", e.key, e.content)).collect();
+    Some(format!(
+        "Things you remembered in earlier conversations (they may be out of date):\n{}",
+        lines.join("\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory() -> Memory {
+        Memory::with_connection(Connection::open_in_memory().unwrap(), &SearchConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn remember_replaces_by_key_and_forget_deletes() {
+        let memory = memory();
+        let first = memory.remember("Preferred editor", "Vim", Some("s1"), 10).unwrap();
+        assert_eq!(first.key, "preferred-editor");
+        let second = memory.remember("preferred-editor", "Helix", Some("s2"), 20).unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!((second.created_at, second.updated_at), (10, 20));
+        assert_eq!(second.source_session.as_deref(), Some("s2"));
+        assert_eq!(memory.list().unwrap(), vec![second.clone()]);
+        assert!(memory.remember(" ", "x", None, 0).is_err());
+        assert!(memory.remember("k", " ", None, 0).is_err());
+
+        assert_eq!(memory.forget("Preferred Editor").unwrap(), Some(second));
+        assert_eq!(memory.forget("preferred-editor").unwrap(), None);
+        assert!(memory.list().unwrap().is_empty());
+        assert!(memory.recall("editor", 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn delete_matches_ids_only() {
+        let memory = memory();
+        let entry = memory.remember("editor", "Vim", None, 1).unwrap();
+        // A memory keyed like another one's id is not deleted in its place.
+        let decoy = memory.remember(&entry.id, "Decoy", None, 2).unwrap();
+        assert_eq!(memory.delete("editor").unwrap(), None);
+        assert_eq!(memory.delete(&entry.id).unwrap(), Some(entry));
+        assert_eq!(memory.list().unwrap(), vec![decoy]);
+        assert!(!MemoryConfig::default().enabled);
+    }
+
+    #[test]
+    fn recall_searches_keys_and_content() {
+        let memory = memory();
+        memory.remember("deploy target", "Production runs on Kubernetes", None, 1).unwrap();
+        memory.remember("语言", "用户喜欢用中文回答", None, 2).unwrap();
+        let keys = |query: &str| -> Vec<String> { memory.recall(query, 5).unwrap().into_iter().map(|e| e.key).collect() };
+        assert_eq!(keys("where do we deploy?"), vec!["deploy-target"]);
+        assert_eq!(keys("kubernetes"), vec!["deploy-target"]);
+        assert_eq!(keys("中文"), vec!["语言"]);
+        assert!(keys("unrelated").is_empty());
+
+        let section = prompt_section(&memory.recall("deploy", 5).unwrap()).unwrap();
+        assert!(section.ends_with("- deploy-target: Production runs on Kubernetes"));
+        assert_eq!(prompt_section(&[]), None);
+    }
+}
@@ -0,0 +1,295 @@
+//! Plan-and-execute mode: the agent first writes a plan of steps, then works through them one
+//! at a time and replans when a step fails.
+//!
+//! The plan of a running task lives in `AppState::plans`, where the user can edit the steps not
+//! started yet. Steps are matched by position, so an edit names the plan version it was made on and
+//! is refused once the agent has moved on from it. When the task ends the plan is stored on the
+//! reply, with each step's outcome.
+
+use serde::{Deserialize, Serialize};
+
+/// Steps not started yet beyond this are dropped from a plan.
+pub const MAX_PLAN_STEPS: usize = 10;
+/// Replies the model gets for one step, tool calls included.
+pub const MAX_STEP_ITERATIONS: u32 = 8;
+/// How often a plan may be revised after failed steps before the task gives up.
+pub const MAX_REPLANS: u32 = 2;
+// Step results are kept as a summary for the checklist.
+const MAX_RESULT_CHARS: usize = 500;
+
+/// How a step reply reports that the step could not be done.
+pub const STEP_FAILED_MARKER: &str = "STEP FAILED:";
+
+const PLAN_FORMAT: &str = r#"Respond with ONLY a JSON object of the form {"steps": [{"description": string, "tools": [string]}]}, where "tools" lists the tools you expect the step to use (possibly none). Keep steps concrete and few, at most 10."#;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Plan {
+    pub steps: Vec<PlanStep>,
+    pub status: PlanStatus,
+    /// How often the plan was revised after a failed step.
+    #[serde(default)]
+    pub revisions: u32,
+    /// Bumped whenever the agent changes the plan.
+    #[serde(default)]
+    pub version: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PlanStep {
+    pub description: String,
+    /// The tools the model expects to use for the step.
+    #[serde(default)]
+    pub tools: Vec<String>,
+    pub status: StepStatus,
+    /// What the step found, or why it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StepStatus {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+/// A step as the model or the user writes it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StepInput {
+    pub description: String,
+    #[serde(default)]
+    pub tools: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct PlanResponse {
+    steps: Vec<StepInput>,
+}
+
+impl Plan {
+    pub fn new(steps: Vec<StepInput>) -> Self {
+        let mut plan = Plan {
+            steps: Vec::new(),
+            status: PlanStatus::Running,
+            revisions: 0,
+            version: 0,
+        };
+        plan.replace_pending(steps);
+        plan
+    }
+
+    /// Replaces the steps not started yet with the agent's. Blank steps are dropped.
+    pub fn replace_pending(&mut self, steps: Vec<StepInput>) {
+        self.set_pending(steps);
+        self.version += 1;
+    }
+
+    /// Replaces the steps not started yet with the user's, unless the agent changed the plan
+    /// since `version`: positions may have shifted, and a step may have started meanwhile.
+    pub fn edit_pending(&mut self, version: u64, steps: Vec<StepInput>) -> Result<(), String> {
+        if version != self.version {
+            return Err("The plan changed while you were editing it; edit the current version".to_string());
+        }
+        self.set_pending(steps);
+        Ok(())
+    }
+
+    fn set_pending(&mut self, steps: Vec<StepInput>) {
+        self.steps.retain(|s| s.status != StepStatus::Pending);
+        self.steps.extend(
+            steps
+                .into_iter()
+                .filter(|s| !s.description.trim().is_empty())
+                .take(MAX_PLAN_STEPS)
+                .map(|s| PlanStep {
+                    description: s.description.trim().to_string(),
+                    tools: s.tools,
+                    status: StepStatus::Pending,
+                    result: None,
+                }),
+        );
+    }
+
+    /// Marks the first pending step as running and returns its index.
+    pub fn start_next(&mut self) -> Option<usize> {
+        let index = self.steps.iter().position(|s| s.status == StepStatus::Pending)?;
+        self.steps[index].status = StepStatus::Running;
+        self.version += 1;
+        Some(index)
+    }
+
+    pub fn finish(&mut self, index: usize, status: StepStatus, result: &str) {
+        let step = &mut self.steps[index];
+        step.status = status;
+        step.result = Some(result.trim().chars().take(MAX_RESULT_CHARS).collect());
+        self.version += 1;
+    }
+
+    /// The plan as a list for prompts, with the outcome of each finished step.
+    pub fn describe(&self) -> String {
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let status = match step.status {
+                    StepStatus::Pending => "pending",
+                    StepStatus::Running => "running",
+                    StepStatus::Done => "done",
+                    StepStatus::Failed => "failed",
+                };
+                let mut line = format!("{}. [{}] {}", i + 1, status, step.description);
+                if let Some(result) = &step.result {
+                    line.push_str(&format!(" -> {}", result.replace('\n', " ")));
+                }
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Reads the steps from a planning reply. The JSON object may be wrapped in other text.
+pub fn parse_steps(reply: &str) -> Result<Vec<StepInput>, String> {
+    let start = reply.find('{').ok_or("The reply contains no JSON object")?;
+    let end = reply.rfind('}').filter(|end| *end > start).ok_or("The reply contains no JSON object")?;
+    let response: PlanResponse =
+        serde_json::from_str(&reply[start..=end]).map_err(|e| format!("Invalid plan: {}", e))?;
+    let steps: Vec<StepInput> = response
+        .steps
+        .into_iter()
+        .filter(|s| !s.description.trim().is_empty())
+        .collect();
+    if steps.is_empty() {
+        return Err("The plan has no steps".to_string());
+    }
+    Ok(steps)
+}
+
+/// Asks for a plan for the conversation's latest request.
+pub fn planning_prompt() -> String {
+    format!(
+        "Before doing anything, plan how to fulfil my last request as a list of steps, each small enough to do with a few tool calls. {}",
+        PLAN_FORMAT
+    )
+}
+
+/// Asks for the remaining steps after `plan` hit a failed step.
+pub fn replanning_prompt(plan: &Plan) -> String {
+    format!(
+        "A step failed. This is the plan so far:\n{}\n\nWrite new steps to replace the pending ones, taking the failure into account. {}",
+        plan.describe(),
+        PLAN_FORMAT
+    )
+}
+
+/// Asks the model to carry out step `index` of `plan`.
+pub fn step_prompt(plan: &Plan, index: usize) -> String {
+    let step = &plan.steps[index];
+    let tools = if step.tools.is_empty() {
+        String::new()
+    } else {
+        format!(" (expected tools: {})", step.tools.join(", "))
+    };
+    format!(
+        "Plan:\n{}\n\nNow carry out step {}: {}{}. Do only this step. When it is done, reply with a short summary of what it found. If it cannot be done, reply with \"{}\" followed by the reason.",
+        plan.describe(),
+        index + 1,
+        step.description,
+        tools,
+        STEP_FAILED_MARKER
+    )
+}
+
+/// Asks for the final answer once the steps are done or given up on.
+pub fn final_prompt(plan: &Plan) -> String {
+    if plan.status == PlanStatus::Failed {
+        format!(
+            "The plan could not be completed:\n{}\n\nAnswer my original request as far as the finished steps allow, and say what could not be done and why. Do not call any more tools.",
+            plan.describe()
+        )
+    } else {
+        format!(
+            "The plan is finished:\n{}\n\nNow answer my original request using what the steps found. Do not call any more tools.",
+            plan.describe()
+        )
+    }
+}
+
+/// Whether a step reply reports a failure; returns the reason if so.
+pub fn step_failure(reply: &str) -> Option<&str> {
+    reply.trim().strip_prefix(STEP_FAILED_MARKER).map(str::trim)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(description: &str) -> StepInput {
+        StepInput {
+            description: description.to_string(),
+            tools: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_plans_wrapped_in_text() {
+        let steps = parse_steps(
+            "Here is the plan:\n```json\n{\"steps\": [{\"description\": \"Read the log\", \"tools\": [\"read_file\"]}, {\"description\": \" \"}, {\"description\": \"Summarize\"}]}\n```",
+        )
+        .unwrap();
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].tools, vec!["read_file"]);
+        assert!(parse_steps("No plan").is_err());
+        assert!(parse_steps("{\"steps\": []}").is_err());
+    }
+
+    #[test]
+    fn edits_only_replace_steps_not_started() {
+        let mut plan = Plan::new(vec![input("One"), input("Two"), input("Three")]);
+        assert_eq!(plan.start_next(), Some(0));
+        plan.finish(0, StepStatus::Done, "found it");
+        assert_eq!(plan.start_next(), Some(1));
+        plan.replace_pending(vec![input("Three, revised"), input(""), input("Four")]);
+        let descriptions: Vec<&str> = plan.steps.iter().map(|s| s.description.as_str()).collect();
+        assert_eq!(descriptions, vec!["One", "Two", "Three, revised", "Four"]);
+        assert_eq!(plan.steps[1].status, StepStatus::Running);
+        assert!(plan.describe().starts_with("1. [done] One -> found it\n2. [running] Two"));
+
+        // Only steps not started yet count towards the cap.
+        plan.replace_pending((0..20).map(|i| input(&i.to_string())).collect());
+        assert_eq!(plan.steps.len(), 2 + MAX_PLAN_STEPS);
+    }
+
+    #[test]
+    fn stale_edits_are_refused() {
+        let mut plan = Plan::new(vec![input("One"), input("Two")]);
+        let seen = plan.version;
+        plan.edit_pending(seen, vec![input("One, edited"), input("Two")]).unwrap();
+        assert_eq!(plan.version, seen);
+        plan.edit_pending(seen, vec![input("One, edited again"), input("Two")]).unwrap();
+
+        // The user is still looking at "One" as pending when the agent starts it.
+        plan.start_next();
+        assert!(plan.edit_pending(seen, vec![input("Two")]).is_err());
+        assert_eq!(plan.steps[0].description, "One, edited again");
+        assert_eq!(plan.steps[1].description, "Two");
+        plan.edit_pending(plan.version, vec![input("Two, edited")]).unwrap();
+        assert_eq!(plan.steps[1].description, "Two, edited");
+    }
+
+    #[test]
+    fn recognizes_failed_steps() {
+        assert_eq!(step_failure(" STEP FAILED: no access"), Some("no access"));
+        assert_eq!(step_failure("The log shows a timeout."), None);
+    }
+}
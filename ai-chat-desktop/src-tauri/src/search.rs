@@ -1,70 +1,200 @@
-//! Full-text search module using Tantivy with custom Jieba tokenizer for Chinese text.
+//! Full-text search module using Tantivy, with an analyzer that segments Chinese with Jieba and
+//! stems words in other scripts.
 
-use crate::{ChatSession, get_app_data_dir};
+use crate::get_app_data_dir;
+use crate::history::{normalize_tag, ChatMessage, ChatSession, Role};
 use serde::{Deserialize, Serialize};
 use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery, TermSetQuery};
 use tantivy::schema::*;
-use tantivy::{doc, Index, ReloadPolicy};
+use tantivy::snippet::{Snippet, SnippetGenerator};
+use tantivy::{doc, Index, ReloadPolicy, TantivyDocument, TantivyError};
 use tantivy::directory::MmapDirectory;
-use tantivy::tokenizer::{Token, TokenStream, Tokenizer};
+use tantivy::tokenizer::{
+    Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, StopWordFilter, TextAnalyzer, Token,
+    TokenStream, Tokenizer,
+};
 use jieba_rs::Jieba;
-use std::sync::Arc;
-use tracing::info;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{mpsc, Arc, OnceLock};
+use std::time::Duration;
+use tracing::{error, info};
 
 const INDEX_DIR: &str = ".index";
 const MEMORY_ARENA_NUM_BYTES: usize = 50_000_000; // 50MB
+/// Bump when documents are built or tokenized differently, to reindex everything once. Changes to
+/// `SearchConfig` are detected separately.
+const INDEX_VERSION: u32 = 2;
+/// Name the analyzer is registered under. Renaming it changes the schema, which recreates the
+/// index on the next start.
+pub(crate) const TOKENIZER: &str = "mixed";
+// Longer "words" are hashes or encoded data, not worth indexing.
+const MAX_WORD_BYTES: usize = 64;
+// Changes arriving this soon after each other are committed together.
+const COMMIT_DELAY: Duration = Duration::from_millis(500);
+const MAX_BATCH: usize = 256;
+const MAX_HITS: usize = 100;
+// Tantivy measures snippet length in bytes.
+const SNIPPET_MAX_BYTES: usize = 200;
+const EXCERPT_CHARS: usize = 80;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SearchResult {
+/// A message (or session title) that matched a search, best matches first.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchHit {
     pub session_id: String,
+    /// `None` when the match is in the session title.
+    pub message_id: Option<String>,
+    pub timestamp: u64,
     pub score: f32,
+    /// The part of the text around the best match.
+    pub snippet: String,
+    /// Matched ranges in `snippet`, in UTF-16 code units so the UI can slice the string as is.
+    pub highlights: Vec<(usize, usize)>,
 }
 
-// Custom Jieba tokenizer implementation
+/// How text is split into searchable words. The index records the settings it was built with
+/// and is rebuilt at startup when they change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SearchConfig {
+    /// Segment Chinese into words with Jieba. When off, every Chinese character is a word.
+    #[serde(default = "default_true")]
+    pub jieba: bool,
+    /// Language of the text in other scripts: its stemmer makes "Running" match "run". One of
+    /// Tantivy's stemmer languages ("English", "French", "German", ...); `null` only lowercases.
+    #[serde(default = "default_language")]
+    pub language: Option<Language>,
+    /// Leave out the language's stop words ("the", "of", ...).
+    #[serde(default = "default_true")]
+    pub stop_words: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_language() -> Option<Language> {
+    Some(Language::English)
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            jieba: true,
+            language: default_language(),
+            stop_words: true,
+        }
+    }
+}
+
+/// Chinese characters, which are segmented with Jieba rather than split at spaces.
+fn is_chinese(c: char) -> bool {
+    matches!(c, '\u{3007}' | '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}' | '\u{20000}'..='\u{2FA1F}')
+}
+
+/// Splits `text` into alternating runs of Chinese and other characters: `(byte offset, run,
+/// is Chinese)`.
+fn script_runs(text: &str) -> Vec<(usize, &str, bool)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut chinese = false;
+    for (i, c) in text.char_indices() {
+        if i > start && is_chinese(c) != chinese {
+            runs.push((start, &text[start..i], chinese));
+            start = i;
+        }
+        chinese = is_chinese(c);
+    }
+    if start < text.len() {
+        runs.push((start, &text[start..], chinese));
+    }
+    runs
+}
+
+/// Jieba's dictionary takes a while to load and tens of megabytes, so all tokenizers share one.
+fn shared_jieba() -> Arc<Jieba> {
+    static JIEBA: OnceLock<Arc<Jieba>> = OnceLock::new();
+    JIEBA.get_or_init(|| Arc::new(Jieba::new())).clone()
+}
+
+/// Analyzer for mixed Chinese and Latin text. Chinese runs are segmented with Jieba; everything
+/// else goes through `latin`, which splits at non-alphanumeric characters, lowercases, drops stop
+/// words and stems.
 #[derive(Clone)]
-pub struct JiebaTokenizer {
-    jieba: Arc<Jieba>,
+pub struct MixedTokenizer {
+    jieba: Option<Arc<Jieba>>,
+    latin: TextAnalyzer,
 }
 
-impl JiebaTokenizer {
-    pub fn new() -> Self {
-        JiebaTokenizer {
-            jieba: Arc::new(Jieba::new()),
+impl MixedTokenizer {
+    pub fn new(config: &SearchConfig) -> Self {
+        let mut latin = TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(RemoveLongFilter::limit(MAX_WORD_BYTES))
+            .filter(LowerCaser)
+            .dynamic();
+        if let Some(language) = config.language {
+            // Stop words go first: the lists hold unstemmed words.
+            if let Some(stop_words) = StopWordFilter::new(language).filter(|_| config.stop_words) {
+                latin = latin.filter_dynamic(stop_words);
+            }
+            latin = latin.filter_dynamic(Stemmer::new(language));
+        }
+        MixedTokenizer {
+            jieba: config.jieba.then(shared_jieba),
+            latin: latin.build(),
         }
     }
 }
 
-impl Tokenizer for JiebaTokenizer {
-    type TokenStream<'a> = JiebaTokenStream;
+impl Tokenizer for MixedTokenizer {
+    type TokenStream<'a> = MixedTokenStream;
 
+    /// Positions count words, not bytes, so phrase queries match adjacent words; offsets are byte
+    /// offsets into `text`. Whitespace, punctuation and stop words are dropped, so
+    /// `"Connection refused"` and `connection, refused` index the same way.
     fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
-        let tokens = self.jieba.tokenize(text, jieba_rs::TokenizeMode::Default, true);
-        let mut tantivy_tokens = Vec::new();
-        
-        for token in tokens {
-            tantivy_tokens.push(Token {
-                offset_from: token.start,
-                offset_to: token.end,
-                position: token.start,
-                text: token.word.to_string(),
-                position_length: token.end - token.start,
+        let mut tokens = Vec::new();
+        let push = |tokens: &mut Vec<Token>, offset_from: usize, offset_to: usize, text: String| {
+            tokens.push(Token {
+                offset_from,
+                offset_to,
+                position: tokens.len(),
+                text,
+                position_length: 1,
             });
+        };
+        for (start, run, chinese) in script_runs(text) {
+            if !chinese {
+                self.latin.token_stream(run).process(&mut |token: &Token| {
+                    push(&mut tokens, start + token.offset_from, start + token.offset_to, token.text.clone());
+                });
+                continue;
+            }
+            // `cut` returns consecutive slices of `run`, which gives the byte offsets.
+            let words: Vec<&str> = match &self.jieba {
+                Some(jieba) => jieba.cut(run, true),
+                None => run.char_indices().map(|(i, c)| &run[i..i + c.len_utf8()]).collect(),
+            };
+            let mut offset = start;
+            for word in words {
+                push(&mut tokens, offset, offset + word.len(), word.to_string());
+                offset += word.len();
+            }
         }
-        
-        JiebaTokenStream {
-            tokens: tantivy_tokens,
-            index: 0,
-        }
+
+        MixedTokenStream { tokens, index: 0 }
     }
 }
 
-pub struct JiebaTokenStream {
+pub struct MixedTokenStream {
     tokens: Vec<Token>,
     index: usize,
 }
 
-impl TokenStream for JiebaTokenStream {
+impl TokenStream for MixedTokenStream {
     fn advance(&mut self) -> bool {
         if self.index < self.tokens.len() {
             self.index += 1;
@@ -83,178 +213,918 @@ impl TokenStream for JiebaTokenStream {
     }
 }
 
+/// Changes for the writer thread.
+enum IndexOp {
+    Upsert(Box<ChatSession>),
+    Remove(String),
+    /// Makes the index match this complete set of sessions.
+    Sync(Vec<ChatSession>),
+    /// Commits what is pending and reports back.
+    Flush(mpsc::Sender<()>),
+}
+
+/// What the index holds, saved as the payload of each commit so it is in step with the index.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct IndexedVersions {
+    version: u32,
+    #[serde(default)]
+    analyzer: SearchConfig,
+    /// Session id to the `fingerprint` of the indexed copy.
+    sessions: HashMap<String, u64>,
+}
+
+impl IndexedVersions {
+    /// The saved state, or an empty one if the index was built differently than `config` says.
+    fn load(index: &Index, config: &SearchConfig) -> Self {
+        index
+            .load_metas()
+            .ok()
+            .and_then(|metas| metas.payload)
+            .and_then(|payload| serde_json::from_str::<Self>(&payload).ok())
+            .filter(|indexed| indexed.version == INDEX_VERSION && indexed.analyzer == *config)
+            .unwrap_or_default()
+    }
+
+    /// The sessions whose indexed copy is missing or outdated, and the ids of indexed sessions
+    /// that are no longer in `sessions`.
+    fn stale<'a>(&self, sessions: &'a [ChatSession]) -> (Vec<&'a ChatSession>, Vec<String>) {
+        let changed = sessions
+            .iter()
+            .filter(|s| self.sessions.get(&s.id) != Some(&fingerprint(s)))
+            .collect();
+        let live: HashSet<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+        let removed = self
+            .sessions
+            .keys()
+            .filter(|id| !live.contains(id.as_str()))
+            .cloned()
+            .collect();
+        (changed, removed)
+    }
+}
+
+/// Opens the index in `path`, or creates it. An index written with another schema is deleted and
+/// created again; callers rebuild its contents from their source at startup anyway.
+pub(crate) fn open_index(path: &Path, schema: Schema) -> Result<Index, String> {
+    let dir = MmapDirectory::open(path).map_err(|e| format!("Failed to open directory: {}", e))?;
+    match Index::open_or_create(dir, schema.clone()) {
+        Ok(index) => Ok(index),
+        Err(TantivyError::SchemaError(e)) => {
+            info!(path = %path.display(), "Index schema changed ({}), recreating it.", e);
+            std::fs::remove_dir_all(path).map_err(|e| e.to_string())?;
+            std::fs::create_dir_all(path).map_err(|e| e.to_string())?;
+            let dir = MmapDirectory::open(path).map_err(|e| format!("Failed to open directory: {}", e))?;
+            Index::create(dir, schema, tantivy::IndexSettings::default())
+                .map_err(|e| format!("Failed to create index: {}", e))
+        }
+        Err(e) => Err(format!("Failed to open or create index: {}", e)),
+    }
+}
+
+/// Identifies the indexed content of a session, i.e. everything `session_documents` reads.
+/// `DefaultHasher` may change between Rust releases; that only costs one full reindex.
+fn fingerprint(session: &ChatSession) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    session.title.hash(&mut hasher);
+    session.updated_at.hash(&mut hasher);
+    session.tags.hash(&mut hasher);
+    for message in &session.messages {
+        message.id.hash(&mut hasher);
+        message.parent_id.hash(&mut hasher);
+        message.content.hash(&mut hasher);
+        message.timestamp.hash(&mut hasher);
+        message.tool_call.as_ref().map(|c| &c.tool_name).hash(&mut hasher);
+        message.generation.as_ref().map(|g| &g.model).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Searches the index. Changes are handed to a single writer thread, which commits them in
+/// batches, so callers never wait for indexing.
 pub struct Searcher {
     pub index: Index,
     schema: Schema,
+    ops: mpsc::Sender<IndexOp>,
 }
 
 impl Searcher {
     /// Creates or opens a Tantivy index in the app's data directory.
-    pub fn new() -> Result<Self, String> {
+    pub fn new(config: &SearchConfig) -> Result<Self, String> {
         let index_path = get_app_data_dir().join(INDEX_DIR);
         if !index_path.exists() {
             std::fs::create_dir_all(&index_path).map_err(|e| e.to_string())?;
         }
 
-        // Define text options with jieba tokenizer
+        let index = open_index(&index_path, Self::build_schema())?;
+        Self::with_index(index, config)
+    }
+
+    fn build_schema() -> Schema {
         let text_options = TextOptions::default()
             .set_indexing_options(
                 TextFieldIndexing::default()
-                    .set_tokenizer("jieba")
+                    .set_tokenizer(TOKENIZER)
                     .set_index_option(IndexRecordOption::WithFreqsAndPositions)
             )
             .set_stored();
 
         let mut schema_builder = Schema::builder();
         schema_builder.add_text_field("session_id", STRING | STORED);
+        // Absent on the title document.
+        schema_builder.add_text_field("message_id", STRING | STORED);
+        // Filters; see `Filters`. Fast fields so they can also be used for sorting and counting.
+        schema_builder.add_u64_field("timestamp", STORED | INDEXED | FAST);
+        schema_builder.add_text_field("role", STRING | FAST);
+        // Tools called on the way to an assistant reply, lowercased.
+        schema_builder.add_text_field("tool", STRING | FAST);
+        schema_builder.add_text_field("model", STRING | FAST);
         schema_builder.add_text_field("title", text_options.clone());
         schema_builder.add_text_field("content", text_options);
-        let schema = schema_builder.build();
+        // One untokenized value per tag, on every document of the session, for `tag:` filters.
+        schema_builder.add_text_field("tag", STRING);
+        schema_builder.build()
+    }
 
-        let dir = MmapDirectory::open(&index_path)
-            .map_err(|e| format!("Failed to open directory: {}", e))?;
-        let index = Index::open_or_create(dir, schema.clone())
-            .map_err(|e| format!("Failed to open or create index: {}", e))?;
+    fn with_index(index: Index, config: &SearchConfig) -> Result<Self, String> {
+        index.tokenizers().register(TOKENIZER, MixedTokenizer::new(config));
+        let schema = index.schema();
+        let writer = index.writer(MEMORY_ARENA_NUM_BYTES)
+            .map_err(|e| format!("Failed to create index writer: {}", e))?;
+        let indexed = IndexedVersions::load(&index, config);
+        let (ops, receiver) = mpsc::channel();
+        let writer_schema = schema.clone();
+        let writer_config = config.clone();
+        std::thread::Builder::new()
+            .name("search-index-writer".to_string())
+            .spawn(move || run_writer(writer, writer_schema, indexed, writer_config, receiver))
+            .map_err(|e| format!("Failed to start index writer: {}", e))?;
+        Ok(Searcher { index, schema, ops })
+    }
 
-        // Register custom Jieba tokenizer for Chinese text
-        index.tokenizers().register("jieba", JiebaTokenizer::new());
+    fn send(&self, op: IndexOp) -> Result<(), String> {
+        self.ops.send(op).map_err(|_| "The search index writer has stopped".to_string())
+    }
 
-        Ok(Searcher { index, schema })
+    /// Brings the index in line with `sessions`, all sessions that should be searchable: only
+    /// sessions that changed since they were indexed are indexed again, and sessions not in the
+    /// list are removed.
+    pub fn sync(&self, sessions: Vec<ChatSession>) -> Result<(), String> {
+        self.send(IndexOp::Sync(sessions))
     }
 
-    /// Rebuilds the entire search index from all chat sessions.
-    pub fn rebuild_index(&self, sessions: Vec<ChatSession>) -> Result<usize, String> {
-        info!("Starting to rebuild search index for {} sessions.", sessions.len());
-        let mut index_writer = self.index.writer(MEMORY_ARENA_NUM_BYTES)
-            .map_err(|e| e.to_string())?;
+    /// Waits until every change sent so far is committed.
+    pub fn flush(&self) -> Result<(), String> {
+        let (done, wait) = mpsc::channel();
+        self.send(IndexOp::Flush(done))?;
+        wait.recv().map_err(|_| "The search index writer has stopped".to_string())
+    }
 
-        // Clear existing documents
-        index_writer.delete_all_documents().map_err(|e| e.to_string())?;
-
-        let mut doc_count = 0;
-        for session in sessions {
-            // Index session title
-            index_writer.add_document(doc!(
-                self.schema.get_field("session_id").unwrap() => session.id.clone(),
-                self.schema.get_field("title").unwrap() => session.title.clone(),
-                self.schema.get_field("content").unwrap() => String::new() // Empty content for title doc
-            )).map_err(|e| e.to_string())?;
-            doc_count += 1;
-
-            // Index each message
-            for message in session.messages {
-                // Only index user and assistant messages
-                if message.role == "user" || message.role == "assistant" {
-                    index_writer.add_document(doc!(
-                        self.schema.get_field("session_id").unwrap() => session.id.clone(),
-                        self.schema.get_field("title").unwrap() => session.title.clone(),
-                        self.schema.get_field("content").unwrap() => message.content
-                    )).map_err(|e| e.to_string())?;
-                    doc_count += 1;
+    /// Splits `key:value` filters off a query; returns them and the remaining text. Words inside
+    /// a quoted phrase are left alone.
+    fn parse_filters(query_str: &str, now: u64) -> Result<(Filters, String), String> {
+        let mut filters = Filters::default();
+        let mut rest = Vec::new();
+        let mut in_phrase = false;
+        for word in query_str.split_whitespace() {
+            let filter = if in_phrase { None } else { word.split_once(':') };
+            match filter {
+                Some(("tag", value)) if normalize_tag(value).is_some() => {
+                    filters.tags.extend(normalize_tag(value));
                 }
+                Some(("role", value)) => match value.to_lowercase().as_str() {
+                    role @ ("user" | "assistant") => filters.roles.push(role.to_string()),
+                    _ => return Err(format!("Unknown role '{}': use role:user or role:assistant", value)),
+                },
+                Some(("tool", value)) if !value.is_empty() => filters.tools.push(value.to_lowercase()),
+                Some(("model", value)) if !value.is_empty() => filters.models.push(value.to_lowercase()),
+                // Session ids are indexed verbatim, so they keep their case.
+                Some(("session", value)) if !value.is_empty() => filters.sessions.push(value.to_string()),
+                Some(("after", value)) => filters.after = Some(parse_date(value, now)?),
+                Some(("before", value)) => filters.before = Some(parse_date(value, now)?),
+                _ => rest.push(word),
+            }
+            if word.matches('"').count() % 2 == 1 {
+                in_phrase = !in_phrase;
+            }
+        }
+        Ok((filters, rest.join(" ")))
+    }
+
+    /// Matches documents whose `field` has any of `values`.
+    fn any_of(&self, field: &str, values: &[String]) -> Box<dyn Query> {
+        let field = self.schema.get_field(field).unwrap();
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = values
+            .iter()
+            .map(|value| {
+                let term = tantivy::Term::from_field_text(field, value);
+                (Occur::Should, Box::new(TermQuery::new(term, IndexRecordOption::Basic)) as Box<dyn Query>)
+            })
+            .collect();
+        if clauses.len() == 1 {
+            clauses.pop().unwrap().1
+        } else {
+            Box::new(BooleanQuery::new(clauses))
+        }
+    }
+
+    /// Searches the index for a given query string and returns the matching messages with a
+    /// highlighted snippet of each. Quoted text such as `"connection refused"` only matches those
+    /// words next to each other. The query may also contain filters, which all have to match:
+    ///
+    /// - `tag:<name>`: sessions with that tag; repeat to require several tags.
+    /// - `role:user`, `role:assistant`: messages from that side of the conversation.
+    /// - `tool:<name>`: assistant replies that called the tool.
+    /// - `model:<name>`: replies generated by the model.
+    /// - `after:<date>`, `before:<date>`: messages from that day on, or from before that day.
+    ///   Dates are `YYYY-MM-DD` (UTC) or a number of days ago, e.g. `after:7d`.
+    ///
+    /// Several `role:`, `tool:` or `model:` filters match any of their values.
+    pub fn search(&self, query_str: &str) -> Result<Vec<SearchHit>, String> {
+        let (filters, text_query) = self.parse_query(query_str)?;
+        let mut clauses = vec![(Occur::Must, text_query.box_clone())];
+        clauses.extend(self.filter_clauses(&filters));
+        self.collect_hits(&*all_of(clauses), &*text_query, MAX_HITS)
+    }
+
+    /// Hits for the given messages that pass the filters in `query_str`, scored by `scores`
+    /// rather than by the text of the query; this is how semantic matches are shown. Words of
+    /// the query are still highlighted where they occur.
+    pub fn message_hits(&self, query_str: &str, scores: &HashMap<String, f32>) -> Result<Vec<SearchHit>, String> {
+        if scores.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (filters, text_query) = self.parse_query(query_str)?;
+        let message_id_field = self.schema.get_field("message_id").unwrap();
+        let ids = TermSetQuery::new(scores.keys().map(|id| tantivy::Term::from_field_text(message_id_field, id)));
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, Box::new(ids))];
+        clauses.extend(self.filter_clauses(&filters));
+        let mut hits = self.collect_hits(&*all_of(clauses), &*text_query, scores.len())?;
+        for hit in &mut hits {
+            if let Some(score) = hit.message_id.as_ref().and_then(|id| scores.get(id)) {
+                hit.score = *score;
             }
         }
+        Ok(hits)
+    }
+
+    /// The words of a query without its filters.
+    pub fn query_text(query_str: &str) -> Result<String, String> {
+        Self::parse_filters(query_str, crate::now_ts()).map(|(_, text)| text)
+    }
 
-        // Commit changes
-        index_writer.commit().map_err(|e| e.to_string())?;
-        info!("Successfully rebuilt index with {} documents.", doc_count);
-        Ok(doc_count)
+    /// Splits a query into its filters and a query for its text, which matches everything if
+    /// there is no text.
+    fn parse_query(&self, query_str: &str) -> Result<(Filters, Box<dyn Query>), String> {
+        let (filters, text) = Self::parse_filters(query_str, crate::now_ts())?;
+        let text_query: Box<dyn Query> = if text.trim().is_empty() {
+            Box::new(AllQuery)
+        } else {
+            let query_parser = QueryParser::for_index(&self.index, vec![
+                self.schema.get_field("title").unwrap(),
+                self.schema.get_field("content").unwrap(),
+            ]);
+            query_parser.parse_query(&text)
+                .map_err(|e| format!("Failed to parse query: {}", e))?
+        };
+        Ok((filters, text_query))
     }
 
-    /// Searches the index for a given query string.
-    pub fn search(&self, query_str: &str) -> Result<Vec<SearchResult>, String> {
+    fn filter_clauses(&self, filters: &Filters) -> Vec<(Occur, Box<dyn Query>)> {
+        let mut clauses = Vec::new();
+        for tag in &filters.tags {
+            clauses.push((Occur::Must, self.any_of("tag", std::slice::from_ref(tag))));
+        }
+        for (field, values) in [
+            ("role", &filters.roles),
+            ("tool", &filters.tools),
+            ("model", &filters.models),
+            ("session_id", &filters.sessions),
+        ] {
+            if !values.is_empty() {
+                clauses.push((Occur::Must, self.any_of(field, values)));
+            }
+        }
+        if filters.after.is_some() || filters.before.is_some() {
+            let range = RangeQuery::new_u64_bounds(
+                "timestamp".to_string(),
+                filters.after.map_or(Bound::Unbounded, Bound::Included),
+                filters.before.map_or(Bound::Unbounded, Bound::Excluded),
+            );
+            clauses.push((Occur::Must, Box::new(range) as Box<dyn Query>));
+        }
+        clauses
+    }
+
+    /// Runs `query` and turns the top documents into hits, with snippets highlighting the terms
+    /// of `highlight`.
+    fn collect_hits(&self, query: &dyn Query, highlight: &dyn Query, limit: usize) -> Result<Vec<SearchHit>, String> {
         let reader = self.index.reader_builder()
             .reload_policy(ReloadPolicy::Manual)
             .try_into()
             .map_err(|e| e.to_string())?;
-
         let searcher = reader.searcher();
-        let query_parser = QueryParser::for_index(&self.index, vec![
-            self.schema.get_field("title").unwrap(),
-            self.schema.get_field("content").unwrap(),
-        ]);
-
-        let query = query_parser.parse_query(query_str)
-            .map_err(|e| format!("Failed to parse query: {}", e))?;
-
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(100))
+        let top_docs = searcher.search(query, &TopDocs::with_limit(limit))
             .map_err(|e| e.to_string())?;
 
-        let mut results_map: std::collections::HashMap<String, f32> = std::collections::HashMap::new();
+        let session_id_field = self.schema.get_field("session_id").unwrap();
+        let message_id_field = self.schema.get_field("message_id").unwrap();
+        let timestamp_field = self.schema.get_field("timestamp").unwrap();
+        let title_field = self.schema.get_field("title").unwrap();
+        let content_field = self.schema.get_field("content").unwrap();
+        let snippet_generator = |field| -> Result<SnippetGenerator, String> {
+            let mut generator = SnippetGenerator::create(&searcher, highlight, field).map_err(|e| e.to_string())?;
+            generator.set_max_num_chars(SNIPPET_MAX_BYTES);
+            Ok(generator)
+        };
+        let title_snippets = snippet_generator(title_field)?;
+        let content_snippets = snippet_generator(content_field)?;
 
+        let mut hits = Vec::with_capacity(top_docs.len());
         for (score, doc_address) in top_docs {
-            let retrieved_doc = searcher.doc::<tantivy::TantivyDocument>(doc_address).map_err(|e| e.to_string())?;
-            let session_id_field = self.schema.get_field("session_id").unwrap();
-            
-            if let Some(session_id_val) = retrieved_doc.get_first(session_id_field) {
-                match session_id_val {
-                    tantivy::schema::OwnedValue::Str(session_id) => {
-                        // If the session is already in the map, add the scores.
-                        // This gives more weight to sessions with more matches.
-                        *results_map.entry(session_id.clone()).or_insert(0.0) += score;
-                    }
-                    _ => {} // Ignore non-string values
+            let retrieved_doc = searcher.doc::<TantivyDocument>(doc_address).map_err(|e| e.to_string())?;
+            let text_of = |field| retrieved_doc.get_first(field).and_then(|v| v.as_str()).unwrap_or_default();
+            let Some(session_id) = retrieved_doc.get_first(session_id_field).and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let message_id = retrieved_doc.get_first(message_id_field).and_then(|v| v.as_str());
+            let (generator, text) = match message_id {
+                Some(_) => (&content_snippets, text_of(content_field)),
+                None => (&title_snippets, text_of(title_field)),
+            };
+            let (snippet, highlights) = snippet_parts(&generator.snippet(text), text);
+            hits.push(SearchHit {
+                session_id: session_id.to_string(),
+                message_id: message_id.map(str::to_string),
+                timestamp: retrieved_doc.get_first(timestamp_field).and_then(|v| v.as_u64()).unwrap_or(0),
+                score,
+                snippet,
+                highlights,
+            });
+        }
+        Ok(hits)
+    }
+
+    /// Adds or updates a session in the search index.
+    pub fn add_or_update_session(&self, session: &ChatSession) -> Result<(), String> {
+        self.send(IndexOp::Upsert(Box::new(session.clone())))
+    }
+
+    /// Removes a session from the search index.
+    pub fn remove_session(&self, session_id: &str) -> Result<(), String> {
+        self.send(IndexOp::Remove(session_id.to_string()))
+    }
+}
+
+/// What the writer thread changes in the index; lets tests make indexing fail.
+trait IndexTarget {
+    fn delete_session(&self, id: &str);
+    fn index_session(&self, session: &ChatSession) -> tantivy::Result<()>;
+    fn delete_all(&self) -> tantivy::Result<()>;
+}
+
+struct SessionWriter {
+    writer: tantivy::IndexWriter,
+    schema: Schema,
+    session_id_field: Field,
+}
+
+impl IndexTarget for SessionWriter {
+    fn delete_session(&self, id: &str) {
+        self.writer.delete_term(tantivy::Term::from_field_text(self.session_id_field, id));
+    }
+
+    fn index_session(&self, session: &ChatSession) -> tantivy::Result<()> {
+        self.delete_session(&session.id);
+        for document in session_documents(&self.schema, session) {
+            self.writer.add_document(document)?;
+        }
+        Ok(())
+    }
+
+    fn delete_all(&self) -> tantivy::Result<()> {
+        self.writer.delete_all_documents().map(|_| ())
+    }
+}
+
+/// Indexes one session and records it in `pending`. On failure the session is dropped from both,
+/// so the next sync retries it; the rest of the batch goes ahead.
+fn upsert(target: &impl IndexTarget, pending: &mut IndexedVersions, session: &ChatSession) {
+    match target.index_session(session) {
+        Ok(()) => {
+            pending.sessions.insert(session.id.clone(), fingerprint(session));
+        }
+        Err(e) => {
+            error!(session_id = %session.id, "Failed to index session: {}", e);
+            target.delete_session(&session.id);
+            pending.sessions.remove(&session.id);
+        }
+    }
+}
+
+/// Applies one change to the writer and to `pending`, the state the next commit records.
+fn apply(
+    target: &impl IndexTarget,
+    pending: &mut IndexedVersions,
+    waiting: &mut Vec<mpsc::Sender<()>>,
+    config: &SearchConfig,
+    op: IndexOp,
+) {
+    match op {
+        IndexOp::Upsert(session) => upsert(target, pending, &session),
+        IndexOp::Remove(id) => {
+            target.delete_session(&id);
+            pending.sessions.remove(&id);
+        }
+        IndexOp::Sync(sessions) => {
+            if pending.version != INDEX_VERSION {
+                info!("Search index is missing, outdated or built with other settings, rebuilding it.");
+                if let Err(e) = target.delete_all() {
+                    error!("Failed to clear the search index: {}", e);
+                    return;
+                }
+                *pending = IndexedVersions {
+                    version: INDEX_VERSION,
+                    analyzer: config.clone(),
+                    sessions: HashMap::new(),
+                };
+            }
+            let (changed, removed) = pending.stale(&sessions);
+            info!(reindexed = changed.len(), removed = removed.len(), "Syncing search index");
+            for id in removed {
+                target.delete_session(&id);
+                pending.sessions.remove(&id);
+            }
+            for session in changed {
+                upsert(target, pending, session);
+            }
+        }
+        IndexOp::Flush(done) => waiting.push(done),
+    }
+}
+
+/// The writer thread: applies changes as they arrive and commits once no more have come for
+/// `COMMIT_DELAY`, or after `MAX_BATCH` changes. Exits when the `Searcher` is dropped.
+fn run_writer(
+    writer: tantivy::IndexWriter,
+    schema: Schema,
+    mut indexed: IndexedVersions,
+    config: SearchConfig,
+    ops: mpsc::Receiver<IndexOp>,
+) {
+    let session_id_field = schema.get_field("session_id").unwrap();
+    let mut target = SessionWriter { writer, schema, session_id_field };
+
+    while let Ok(first) = ops.recv() {
+        // Becomes `indexed` once the batch is committed.
+        let mut pending = indexed.clone();
+        let mut waiting = Vec::new();
+        let mut batch = 0;
+        let mut next = Some(first);
+        while let Some(op) = next.take() {
+            batch += 1;
+            apply(&target, &mut pending, &mut waiting, &config, op);
+            if waiting.is_empty() && batch < MAX_BATCH {
+                next = ops.recv_timeout(COMMIT_DELAY).ok();
+            }
+        }
+
+        let committed = target.writer.prepare_commit().and_then(|mut commit| {
+            commit.set_payload(&serde_json::to_string(&pending).unwrap_or_default());
+            commit.commit()
+        });
+        match committed {
+            Ok(_) => indexed = pending,
+            Err(e) => {
+                error!("Failed to update the search index: {}", e);
+                if let Err(e) = target.writer.rollback() {
+                    error!("Failed to roll back the search index: {}", e);
+                }
+            }
+        }
+        for done in waiting {
+            let _ = done.send(());
+        }
+    }
+}
+
+/// One document for the title and one per user or assistant message, across all branches;
+/// tool-call steps are not indexed, but a reply records the tools called since its prompt.
+/// Every document carries the session's tags.
+fn session_documents(schema: &Schema, session: &ChatSession) -> Vec<TantivyDocument> {
+    let session_id_field = schema.get_field("session_id").unwrap();
+    let message_id_field = schema.get_field("message_id").unwrap();
+    let timestamp_field = schema.get_field("timestamp").unwrap();
+    let title_field = schema.get_field("title").unwrap();
+    let content_field = schema.get_field("content").unwrap();
+    let tag_field = schema.get_field("tag").unwrap();
+    let role_field = schema.get_field("role").unwrap();
+    let tool_field = schema.get_field("tool").unwrap();
+    let model_field = schema.get_field("model").unwrap();
+    let by_id: HashMap<&str, &ChatMessage> = session.messages.iter().map(|m| (m.id.as_str(), m)).collect();
+
+    let mut documents = vec![doc!(
+        session_id_field => session.id.clone(),
+        timestamp_field => session.updated_at,
+        title_field => session.title.clone()
+    )];
+    for message in session
+        .messages
+        .iter()
+        .filter(|m| matches!(m.role, Role::User | Role::Assistant) && !m.is_step())
+    {
+        let role = if message.role == Role::User { "user" } else { "assistant" };
+        let mut document = doc!(
+            session_id_field => session.id.clone(),
+            message_id_field => message.id.clone(),
+            timestamp_field => message.timestamp,
+            role_field => role,
+            content_field => message.content.clone()
+        );
+        if let Some(generation) = &message.generation {
+            document.add_text(model_field, generation.model.to_lowercase());
+        }
+        if message.role == Role::Assistant {
+            // Steps are chained between the prompt and the reply.
+            let mut cursor = message.parent_id.as_deref().and_then(|id| by_id.get(id));
+            let mut steps = 0;
+            while let Some(step) = cursor.filter(|m| m.is_step() && steps < session.messages.len()) {
+                if let Some(call) = step.tool_call.as_ref().filter(|_| step.role == Role::Assistant) {
+                    document.add_text(tool_field, call.tool_name.to_lowercase());
                 }
+                cursor = step.parent_id.as_deref().and_then(|id| by_id.get(id));
+                steps += 1;
             }
         }
-        
-        // Convert map to Vec and sort by score
-        let mut final_results: Vec<SearchResult> = results_map.into_iter()
-            .map(|(session_id, score)| SearchResult { session_id, score })
+        documents.push(document);
+    }
+    for document in &mut documents {
+        for tag in &session.tags {
+            document.add_text(tag_field, tag);
+        }
+    }
+    documents
+}
+
+/// Combines `clauses` that must all match.
+fn all_of(mut clauses: Vec<(Occur, Box<dyn Query>)>) -> Box<dyn Query> {
+    if clauses.len() == 1 {
+        clauses.pop().unwrap().1
+    } else {
+        Box::new(BooleanQuery::new(clauses))
+    }
+}
+
+/// Filters parsed from a search query, see `Searcher::search`.
+#[derive(Debug, Default, PartialEq)]
+struct Filters {
+    tags: Vec<String>,
+    roles: Vec<String>,
+    tools: Vec<String>,
+    models: Vec<String>,
+    sessions: Vec<String>,
+    /// Inclusive lower bound on the timestamp.
+    after: Option<u64>,
+    /// Exclusive upper bound on the timestamp.
+    before: Option<u64>,
+}
+
+/// Parses `YYYY-MM-DD` as the start of that day in UTC, or `<n>d` as `n` days before `now`.
+fn parse_date(value: &str, now: u64) -> Result<u64, String> {
+    let invalid = || format!("Invalid date '{}': use YYYY-MM-DD or a number of days such as 7d", value);
+    if let Some(days) = value.strip_suffix('d') {
+        let days: u64 = days.parse().map_err(|_| invalid())?;
+        return Ok(now.saturating_sub(days * 86_400));
+    }
+    let mut parts = value.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day))) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || year < 1970 {
+        return Err(invalid());
+    }
+    // Days-from-civil, see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Ok(days as u64 * 86_400)
+}
+
+/// The snippet text and its highlights in UTF-16 offsets. Without a highlighted match, e.g. for
+/// a `tag:` filter alone, the snippet is the start of `text`.
+fn snippet_parts(snippet: &Snippet, text: &str) -> (String, Vec<(usize, usize)>) {
+    if snippet.is_empty() {
+        let mut excerpt: String = text.trim().chars().take(EXCERPT_CHARS).collect();
+        if text.trim().chars().nth(EXCERPT_CHARS).is_some() {
+            excerpt.push_str("...");
+        }
+        return (excerpt, Vec::new());
+    }
+    let fragment = snippet.fragment();
+    let utf16_offset = |byte: usize| fragment[..byte].encode_utf16().count();
+    let highlights = snippet
+        .highlighted()
+        .iter()
+        .map(|range| (utf16_offset(range.start), utf16_offset(range.end)))
+        .collect();
+    (fragment.to_string(), highlights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::{GenerationInfo, ToolCallInfo};
+
+    fn tokens(text: &str) -> Vec<Token> {
+        let mut tokenizer = MixedTokenizer::new(&SearchConfig::default());
+        let mut stream = tokenizer.token_stream(text);
+        let mut tokens = Vec::new();
+        while stream.advance() {
+            tokens.push(stream.token().clone());
+        }
+        tokens
+    }
+
+    fn searcher_with(sessions: &[(&str, &str)]) -> Searcher {
+        let searcher = Searcher::with_index(Index::create_in_ram(Searcher::build_schema()), &SearchConfig::default()).unwrap();
+        let sessions = sessions
+            .iter()
+            .map(|(id, content)| {
+                let mut session = ChatSession::new(id.to_string(), "Chat".to_string());
+                session.append(ChatMessage::new(Role::User, *content));
+                session
+            })
             .collect();
-            
-        final_results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        searcher.sync(sessions).unwrap();
+        searcher.flush().unwrap();
+        searcher
+    }
 
-        Ok(final_results)
+    fn hits(searcher: &Searcher, query: &str) -> Vec<String> {
+        let mut ids: Vec<String> = searcher.search(query).unwrap().into_iter().map(|r| r.session_id).collect();
+        ids.sort();
+        ids
     }
 
-    /// Adds or updates a session in the search index.
-    pub fn add_or_update_session(&self, session: &ChatSession) -> Result<(), String> {
-        let mut index_writer: tantivy::IndexWriter<tantivy::TantivyDocument> = self.index.writer(MEMORY_ARENA_NUM_BYTES)
-            .map_err(|e| e.to_string())?;
+    #[test]
+    fn sync_only_reindexes_changed_sessions() {
+        let index = Index::create_in_ram(Searcher::build_schema());
+        let searcher = Searcher::with_index(index.clone(), &SearchConfig::default()).unwrap();
+        let mut a = ChatSession::new("a".to_string(), "Chat".to_string());
+        a.append(ChatMessage::new(Role::User, "alpha"));
+        let mut b = ChatSession::new("b".to_string(), "Chat".to_string());
+        b.append(ChatMessage::new(Role::User, "beta"));
+        searcher.sync(vec![a.clone(), b]).unwrap();
+        searcher.flush().unwrap();
 
-        // First, delete any existing documents for this session
-        let session_id_field = self.schema.get_field("session_id").unwrap();
-        let term = tantivy::Term::from_field_text(session_id_field, &session.id);
-        index_writer.delete_term(term);
-
-        // Then add the new documents
-        // Index session title
-        index_writer.add_document(doc!(
-            self.schema.get_field("session_id").unwrap() => session.id.clone(),
-            self.schema.get_field("title").unwrap() => session.title.clone(),
-            self.schema.get_field("content").unwrap() => String::new() // Empty content for title doc
-        )).map_err(|e| e.to_string())?;
-
-        // Index each message
-        for message in &session.messages {
-            // Only index user and assistant messages
-            if message.role == "user" || message.role == "assistant" {
-                index_writer.add_document(doc!(
-                    self.schema.get_field("session_id").unwrap() => session.id.clone(),
-                    self.schema.get_field("title").unwrap() => session.title.clone(),
-                    self.schema.get_field("content").unwrap() => message.content.clone()
-                )).map_err(|e| e.to_string())?;
+        let indexed = IndexedVersions::load(&index, &SearchConfig::default());
+        assert_eq!(indexed.version, INDEX_VERSION);
+        assert_eq!(indexed.sessions.len(), 2);
+        assert!(indexed.stale(std::slice::from_ref(&a)).0.is_empty());
+
+        a.append(ChatMessage::new(Role::Assistant, "gamma"));
+        let (changed, removed) = indexed.stale(std::slice::from_ref(&a));
+        assert_eq!(changed.len(), 1);
+        assert_eq!(removed, vec!["b"]);
+
+        searcher.sync(vec![a]).unwrap();
+        searcher.flush().unwrap();
+        assert!(hits(&searcher, "beta").is_empty());
+        assert_eq!(hits(&searcher, "gamma"), vec!["a"]);
+    }
+
+    #[test]
+    fn positions_count_words_and_skip_punctuation() {
+        let tokens = tokens("Connection refused, 数据库连接被拒绝");
+        let positions: Vec<usize> = tokens.iter().map(|t| t.position).collect();
+        assert_eq!(positions, (0..tokens.len()).collect::<Vec<_>>());
+        assert!(tokens.iter().all(|t| t.position_length == 1));
+        assert_eq!(tokens[0].text, "connect");
+        assert_eq!(tokens[1].text, "refus");
+        assert!(tokens.iter().all(|t| t.text.chars().any(char::is_alphanumeric)));
+    }
+
+    #[test]
+    fn offsets_are_byte_offsets() {
+        let text = "连接 Refused";
+        for token in tokens(text) {
+            assert!(text[token.offset_from..token.offset_to].to_lowercase().starts_with(&token.text));
+        }
+    }
+
+    #[test]
+    fn english_words_are_stemmed_and_chinese_segmented() {
+        let texts: Vec<String> = tokens("The tests were Running 在数据库里").into_iter().map(|t| t.text).collect();
+        assert_eq!(texts, vec!["test", "were", "run", "在", "数据库", "里"]);
+
+        let config = SearchConfig {
+            jieba: false,
+            language: None,
+            stop_words: true,
+        };
+        let mut tokenizer = MixedTokenizer::new(&config);
+        let mut stream = tokenizer.token_stream("The Running 数据");
+        let mut texts = Vec::new();
+        while stream.advance() {
+            texts.push(stream.token().text.clone());
+        }
+        assert_eq!(texts, vec!["the", "running", "数", "据"]);
+
+        let searcher = searcher_with(&[("a", "I ran the migrations"), ("b", "Running migrations now")]);
+        assert_eq!(hits(&searcher, "run"), vec!["b"]);
+        assert_eq!(hits(&searcher, "migration"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn changed_settings_rebuild_the_index() {
+        let index = Index::create_in_ram(Searcher::build_schema());
+        let searcher = Searcher::with_index(index.clone(), &SearchConfig::default()).unwrap();
+        let mut session = ChatSession::new("a".to_string(), "Chat".to_string());
+        session.append(ChatMessage::new(Role::User, "Running"));
+        searcher.sync(vec![session.clone()]).unwrap();
+        searcher.flush().unwrap();
+        drop(searcher);
+
+        let config = SearchConfig {
+            language: None,
+            ..SearchConfig::default()
+        };
+        assert!(IndexedVersions::load(&index, &config).sessions.is_empty());
+        // The old writer thread releases the index lock once it notices the searcher is gone.
+        let searcher = (0..100)
+            .find_map(|_| {
+                let searcher = Searcher::with_index(index.clone(), &config).ok();
+                if searcher.is_none() {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                searcher
+            })
+            .unwrap();
+        searcher.sync(vec![session]).unwrap();
+        searcher.flush().unwrap();
+        assert_eq!(IndexedVersions::load(&index, &config).sessions.len(), 1);
+        assert!(hits(&searcher, "run").is_empty());
+        assert_eq!(hits(&searcher, "running"), vec!["a"]);
+    }
+
+    #[test]
+    fn english_phrase_matches_adjacent_words_only() {
+        let searcher = searcher_with(&[
+            ("a", "The server said: Connection refused."),
+            ("b", "It refused the connection."),
+        ]);
+        assert_eq!(hits(&searcher, "\"connection refused\""), vec!["a"]);
+        assert_eq!(hits(&searcher, "CONNECTION"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn chinese_phrase_matches_adjacent_words_only() {
+        let searcher = searcher_with(&[
+            ("a", "数据库连接被拒绝了"),
+            ("b", "他拒绝了连接数据库的请求"),
+        ]);
+        assert_eq!(hits(&searcher, "\"连接被拒绝\""), vec!["a"]);
+        assert_eq!(hits(&searcher, "拒绝"), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn hits_point_at_messages_with_highlights() {
+        let mut session = ChatSession::new("a".to_string(), "数据库排查".to_string());
+        session.append(ChatMessage::new(Role::User, "Why does it say Connection refused?"));
+        let message_id = session.append(ChatMessage::new(Role::Assistant, "😀 数据库连接被拒绝，请检查端口。"));
+        let searcher = Searcher::with_index(Index::create_in_ram(Searcher::build_schema()), &SearchConfig::default()).unwrap();
+        searcher.sync(vec![session]).unwrap();
+        searcher.flush().unwrap();
+
+        let hits = searcher.search("拒绝").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id.as_deref(), Some(message_id.as_str()));
+        let utf16: Vec<u16> = hits[0].snippet.encode_utf16().collect();
+        let (start, end) = hits[0].highlights[0];
+        assert_eq!(String::from_utf16(&utf16[start..end]).unwrap(), "拒绝");
+
+        let hits = searcher.search("排查").unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].message_id, None);
+        assert_eq!(hits[0].snippet, "数据库排查");
+    }
+
+    #[test]
+    fn filters_are_split_off_outside_phrases() {
+        let (filters, text) =
+            Searcher::parse_filters("tag:infra role:Assistant k8s \"see tag:infra\" after:2026-10-01", 0).unwrap();
+        assert_eq!(filters.tags, vec!["infra"]);
+        assert_eq!(filters.roles, vec!["assistant"]);
+        assert_eq!(filters.after, Some(1_790_812_800));
+        assert_eq!(text, "k8s \"see tag:infra\"");
+        assert!(Searcher::parse_filters("role:bot", 0).is_err());
+        assert!(Searcher::parse_filters("after:yesterday", 0).is_err());
+    }
+
+    /// Records what is indexed and fails to index the session `"bad"`.
+    #[derive(Default)]
+    struct FlakyTarget(std::cell::RefCell<Vec<String>>);
+
+    impl IndexTarget for FlakyTarget {
+        fn delete_session(&self, id: &str) {
+            self.0.borrow_mut().retain(|indexed| indexed != id);
+        }
+
+        fn index_session(&self, session: &ChatSession) -> tantivy::Result<()> {
+            if session.id == "bad" {
+                return Err(TantivyError::InternalError("disk full".to_string()));
             }
+            self.0.borrow_mut().push(session.id.clone());
+            Ok(())
         }
 
-        // Commit changes
-        index_writer.commit().map_err(|e| e.to_string())?;
-        Ok(())
+        fn delete_all(&self) -> tantivy::Result<()> {
+            self.0.borrow_mut().clear();
+            Ok(())
+        }
     }
 
-    /// Removes a session from the search index.
-    pub fn remove_session(&self, session_id: &str) -> Result<(), String> {
-        let mut index_writer: tantivy::IndexWriter<tantivy::TantivyDocument> = self.index.writer(MEMORY_ARENA_NUM_BYTES)
-            .map_err(|e| e.to_string())?;
+    #[test]
+    fn a_failed_upsert_does_not_stop_the_batch() {
+        let target = FlakyTarget::default();
+        let mut pending = IndexedVersions { version: INDEX_VERSION, ..Default::default() };
+        let mut waiting = Vec::new();
+        let config = SearchConfig::default();
+        let session = |id: &str| ChatSession::new(id.to_string(), "Chat".to_string());
+        let (done, _wait) = mpsc::channel();
+        for op in [
+            IndexOp::Upsert(Box::new(session("a"))),
+            IndexOp::Upsert(Box::new(session("bad"))),
+            IndexOp::Flush(done.clone()),
+            IndexOp::Upsert(Box::new(session("b"))),
+            IndexOp::Flush(done),
+        ] {
+            apply(&target, &mut pending, &mut waiting, &config, op);
+        }
+        assert_eq!(*target.0.borrow(), vec!["a", "b"]);
+        let mut recorded: Vec<&String> = pending.sessions.keys().collect();
+        recorded.sort();
+        assert_eq!(recorded, vec!["a", "b"]);
+        assert_eq!(waiting.len(), 2);
 
-        // Delete all documents for this session
-        let session_id_field = self.schema.get_field("session_id").unwrap();
-        let term = tantivy::Term::from_field_text(session_id_field, session_id);
-        index_writer.delete_term(term);
+        // The failed session is not recorded, so the next sync retries it.
+        apply(&target, &mut pending, &mut waiting, &config, IndexOp::Sync(vec![session("a"), session("bad")]));
+        assert_eq!(*target.0.borrow(), vec!["a"]);
+        assert_eq!(pending.sessions.keys().collect::<Vec<_>>(), vec!["a"]);
+    }
 
-        // Commit changes
-        index_writer.commit().map_err(|e| e.to_string())?;
-        Ok(())
+    #[test]
+    fn session_filter_limits_hits_to_one_chat() {
+        let searcher = searcher_with(&[("Chat-A", "deploy failed"), ("chat-b", "deploy worked")]);
+        assert_eq!(hits(&searcher, "deploy session:Chat-A"), vec!["Chat-A"]);
+        assert_eq!(hits(&searcher, "deploy session:chat-b session:Chat-A"), vec!["Chat-A", "chat-b"]);
+        assert!(hits(&searcher, "deploy session:chat-a").is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn dates_are_utc_days_or_days_ago() {
+        assert_eq!(parse_date("1970-01-01", 0), Ok(0));
+        assert_eq!(parse_date("2000-03-01", 0), Ok(951_868_800));
+        assert_eq!(parse_date("7d", 1_000_000), Ok(1_000_000 - 7 * 86_400));
+    }
+
+    #[test]
+    fn filters_combine_with_text() {
+        let mut session = ChatSession::new("a".to_string(), "Cluster".to_string());
+        let mut prompt = ChatMessage::new(Role::User, "Is the k8s cluster up?");
+        prompt.timestamp = 1_000;
+        session.append(prompt);
+        session.append(
+            ChatMessage::new(Role::Assistant, "").with_tool_call(ToolCallInfo {
+                tool_name: "Kubectl".to_string(),
+                arguments: serde_json::json!({}),
+                status: None,
+                duration_ms: None,
+            }),
+        );
+        let mut reply = ChatMessage::new(Role::Assistant, "The k8s cluster is healthy.").with_generation(GenerationInfo {
+            profile: "default".to_string(),
+            model: "GPT-4o".to_string(),
+            temperature: None,
+        });
+        reply.timestamp = 2_000_000;
+        let reply_id = session.append(reply);
+        let searcher = Searcher::with_index(Index::create_in_ram(Searcher::build_schema()), &SearchConfig::default()).unwrap();
+        searcher.sync(vec![session]).unwrap();
+        searcher.flush().unwrap();
+
+        let ids = |query: &str| -> Vec<Option<String>> {
+            searcher.search(query).unwrap().into_iter().map(|h| h.message_id).collect()
+        };
+        assert_eq!(ids("k8s").len(), 2);
+        assert_eq!(ids("k8s role:assistant"), vec![Some(reply_id.clone())]);
+        assert_eq!(ids("tool:kubectl"), vec![Some(reply_id.clone())]);
+        assert_eq!(ids("k8s model:gpt-4o"), vec![Some(reply_id.clone())]);
+        assert_eq!(ids("k8s after:1970-01-02"), vec![Some(reply_id)]);
+        assert_eq!(ids("k8s before:1970-01-02 role:user").len(), 1);
+        assert!(ids("k8s role:user tool:kubectl").is_empty());
+    }
+}
@@ -0,0 +1,492 @@
+//! Optional semantic search, so that "deploy failure" also finds "the rollout broke".
+//!
+//! Messages are embedded with the configured provider's embeddings endpoint and the vectors kept
+//! in `<data>/embeddings.db`. A query is embedded the same way, compared with every stored vector
+//! by cosine similarity, and the matches are ranked together with the keyword hits. This is off
+//! unless `embeddings.enabled` is set in settings.json, since it sends every message to the
+//! provider a second time.
+
+use crate::history::{ChatMessage, ChatSession, Role};
+use crate::llm::LlmClient;
+use crate::search::{SearchHit, Searcher};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+const DB_FILE: &str = "embeddings.db";
+// Messages embedded per request.
+const BATCH_SIZE: usize = 64;
+// Embedding models take about 8k tokens; longer messages are embedded by their start.
+const MAX_INPUT_CHARS: usize = 8_000;
+const MAX_SEMANTIC_HITS: usize = 50;
+// Below this cosine similarity a message is not considered related to the query.
+const MIN_SIMILARITY: f32 = 0.3;
+const MAX_HITS: usize = 100;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS embeddings (
+    message_id   TEXT PRIMARY KEY,
+    session_id   TEXT NOT NULL,
+    model        TEXT NOT NULL,
+    content_hash INTEGER NOT NULL,
+    vector       BLOB NOT NULL
+);
+CREATE INDEX IF NOT EXISTS embeddings_session ON embeddings(session_id);
+";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbeddingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// An embeddings model of the provider in `openai.base_url`.
+    #[serde(default = "default_model")]
+    pub model: String,
+    /// Share of the semantic score in the combined ranking, from 0 (keywords only) to 1.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_model() -> String {
+    "text-embedding-3-small".to_string()
+}
+
+fn default_weight() -> f32 {
+    0.5
+}
+
+impl Default for EmbeddingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            model: default_model(),
+            weight: default_weight(),
+        }
+    }
+}
+
+/// Message vectors, normalized to unit length so that cosine similarity is a dot product.
+struct VectorStore {
+    conn: Mutex<Connection>,
+}
+
+impl VectorStore {
+    fn open(data_dir: &Path) -> Result<Self, String> {
+        let path = data_dir.join(DB_FILE);
+        let conn = Connection::open(&path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(|e| e.to_string())?;
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create schema: {}", e))?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.conn.lock().map_err(|e| format!("Failed to lock embeddings database: {}", e))
+    }
+
+    /// Model and content hash of each stored message of a session.
+    fn stored(&self, session_id: &str) -> Result<HashMap<String, (String, i64)>, String> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare("SELECT message_id, model, content_hash FROM embeddings WHERE session_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([session_id], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    fn session_ids(&self) -> Result<HashSet<String>, String> {
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT session_id FROM embeddings")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map([], |row| row.get(0)).map_err(|e| e.to_string())?;
+        rows.collect::<Result<_, _>>().map_err(|e| e.to_string())
+    }
+
+    fn upsert(&self, session_id: &str, model: &str, rows: &[(&str, i64, Vec<f32>)]) -> Result<(), String> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for (message_id, content_hash, vector) in rows {
+            tx.execute(
+                "INSERT INTO embeddings (message_id, session_id, model, content_hash, vector) VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT(message_id) DO UPDATE SET session_id = excluded.session_id, model = excluded.model,
+                 content_hash = excluded.content_hash, vector = excluded.vector",
+                params![message_id, session_id, model, content_hash, encode(vector)],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn remove_messages(&self, message_ids: &[String]) -> Result<(), String> {
+        let mut conn = self.lock()?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for id in message_ids {
+            tx.execute("DELETE FROM embeddings WHERE message_id = ?1", [id])
+                .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn remove_session(&self, session_id: &str) -> Result<(), String> {
+        self.lock()?
+            .execute("DELETE FROM embeddings WHERE session_id = ?1", [session_id])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    /// The `limit` messages most similar to `query`, with their cosine similarity.
+    fn nearest(&self, model: &str, query: &[f32], limit: usize) -> Result<Vec<(String, f32)>, String> {
+        let query = normalize(query.to_vec());
+        let conn = self.lock()?;
+        let mut stmt = conn
+            .prepare("SELECT message_id, vector FROM embeddings WHERE model = ?1")
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map([model], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?)))
+            .map_err(|e| e.to_string())?;
+        let mut scored = Vec::new();
+        for row in rows {
+            let (message_id, bytes) = row.map_err(|e| e.to_string())?;
+            let vector = decode(&bytes);
+            if vector.len() == query.len() {
+                let similarity = vector.iter().zip(&query).map(|(a, b)| a * b).sum::<f32>();
+                scored.push((message_id, similarity));
+            }
+        }
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        scored.truncate(limit);
+        Ok(scored)
+    }
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+fn encode(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn decode(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
+
+/// 64-bit FNV-1a of the content. Stored with each vector, so it must not change between builds
+/// the way `DefaultHasher` may.
+fn content_hash(content: &str) -> i64 {
+    let hash = content.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    hash as i64
+}
+
+/// The messages worth embedding: what the user and the assistant said, without tool steps.
+fn embeddable(session: &ChatSession) -> impl Iterator<Item = &ChatMessage> {
+    session.messages.iter().filter(|m| {
+        matches!(m.role, Role::User | Role::Assistant) && !m.is_step() && !m.content.trim().is_empty()
+    })
+}
+
+/// Changes for the background embedder.
+enum EmbedOp {
+    Update(Box<ChatSession>),
+    Remove(String),
+    /// Makes the store match this complete set of sessions.
+    Sync(Vec<ChatSession>),
+}
+
+/// Keeps message embeddings up to date in the background and answers semantic queries.
+pub struct Embedder {
+    ops: mpsc::UnboundedSender<EmbedOp>,
+    store: Arc<VectorStore>,
+    client: Arc<LlmClient>,
+    config: EmbeddingConfig,
+}
+
+impl Embedder {
+    pub fn start(config: EmbeddingConfig, client: LlmClient, data_dir: &Path) -> Result<Self, String> {
+        let store = Arc::new(VectorStore::open(data_dir)?);
+        let client = Arc::new(client);
+        let (ops, receiver) = mpsc::unbounded_channel();
+        tauri::async_runtime::spawn(run_embedder(store.clone(), client.clone(), config.model.clone(), receiver));
+        Ok(Self {
+            ops,
+            store,
+            client,
+            config,
+        })
+    }
+
+    fn send(&self, op: EmbedOp) -> Result<(), String> {
+        self.ops.send(op).map_err(|_| "The embedder has stopped".to_string())
+    }
+
+    /// Embeds the messages of `session` that are new or changed since they were last embedded.
+    pub fn update_session(&self, session: &ChatSession) -> Result<(), String> {
+        self.send(EmbedOp::Update(Box::new(session.clone())))
+    }
+
+    pub fn remove_session(&self, session_id: &str) -> Result<(), String> {
+        self.send(EmbedOp::Remove(session_id.to_string()))
+    }
+
+    /// Brings the store in line with `sessions`, all sessions that should be searchable.
+    pub fn sync(&self, sessions: Vec<ChatSession>) -> Result<(), String> {
+        self.send(EmbedOp::Sync(sessions))
+    }
+
+    /// Ranks `keyword` hits for `query_str` together with messages similar in meaning. Falls
+    /// back to the keyword hits if the query cannot be embedded.
+    pub async fn hybrid_search(&self, searcher: &Searcher, query_str: &str, keyword: Vec<SearchHit>) -> Vec<SearchHit> {
+        match self.semantic_hits(searcher, query_str).await {
+            Ok(semantic) => hybrid_rank(keyword, semantic, self.config.weight),
+            Err(e) => {
+                warn!("Semantic search failed, showing keyword matches only: {}", e);
+                keyword
+            }
+        }
+    }
+
+    async fn semantic_hits(&self, searcher: &Searcher, query_str: &str) -> Result<Vec<SearchHit>, String> {
+        let text = Searcher::query_text(query_str)?.replace('"', " ");
+        if text.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let query = self
+            .client
+            .embed(&self.config.model, &[text])
+            .await
+            .map_err(|e| e.to_string())?
+            .pop()
+            .unwrap_or_default();
+        let scores: HashMap<String, f32> = self
+            .store
+            .nearest(&self.config.model, &query, MAX_SEMANTIC_HITS)?
+            .into_iter()
+            .filter(|(_, similarity)| *similarity >= MIN_SIMILARITY)
+            .collect();
+        // Also applies the query's filters and drops messages no longer indexed.
+        searcher.message_hits(query_str, &scores)
+    }
+}
+
+/// Merges keyword and semantic hits on the same message. BM25 scores are unbounded, so they are
+/// scaled by the best one; semantic scores are cosine similarities and already between 0 and 1.
+fn hybrid_rank(keyword: Vec<SearchHit>, semantic: Vec<SearchHit>, weight: f32) -> Vec<SearchHit> {
+    let weight = weight.clamp(0.0, 1.0);
+    let best_keyword = keyword.iter().map(|h| h.score).fold(0.0, f32::max);
+    let mut merged: HashMap<(String, Option<String>), SearchHit> = HashMap::new();
+    for mut hit in keyword {
+        hit.score = if best_keyword > 0.0 { hit.score / best_keyword } else { 0.0 } * (1.0 - weight);
+        merged.insert((hit.session_id.clone(), hit.message_id.clone()), hit);
+    }
+    for mut hit in semantic {
+        let score = hit.score.clamp(0.0, 1.0) * weight;
+        match merged.entry((hit.session_id.clone(), hit.message_id.clone())) {
+            // Keep the keyword hit, whose snippet shows the matched words.
+            Entry::Occupied(mut entry) => entry.get_mut().score += score,
+            Entry::Vacant(entry) => {
+                hit.score = score;
+                entry.insert(hit);
+            }
+        }
+    }
+    let mut hits: Vec<SearchHit> = merged.into_values().collect();
+    hits.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+    hits.truncate(MAX_HITS);
+    hits
+}
+
+/// The background embedder: handles queued changes, keeping only the latest copy of each session.
+async fn run_embedder(
+    store: Arc<VectorStore>,
+    client: Arc<LlmClient>,
+    model: String,
+    mut ops: mpsc::UnboundedReceiver<EmbedOp>,
+) {
+    while let Some(first) = ops.recv().await {
+        let mut queued: Vec<String> = Vec::new();
+        let mut latest: HashMap<String, Box<ChatSession>> = HashMap::new();
+        let mut next = Some(first);
+        while let Some(op) = next.take() {
+            let result = match op {
+                EmbedOp::Update(session) => {
+                    if !latest.contains_key(&session.id) {
+                        queued.push(session.id.clone());
+                    }
+                    latest.insert(session.id.clone(), session);
+                    Ok(())
+                }
+                EmbedOp::Remove(id) => {
+                    latest.remove(&id);
+                    store.remove_session(&id)
+                }
+                EmbedOp::Sync(sessions) => {
+                    let live: HashSet<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+                    let removed = store
+                        .session_ids()
+                        .map(|ids| ids.into_iter().filter(|id| !live.contains(id.as_str())).collect::<Vec<_>>());
+                    for session in sessions {
+                        if !latest.contains_key(&session.id) {
+                            queued.push(session.id.clone());
+                        }
+                        latest.insert(session.id.clone(), Box::new(session));
+                    }
+                    removed.and_then(|ids| ids.iter().try_for_each(|id| store.remove_session(id)))
+                }
+            };
+            if let Err(e) = result {
+                warn!("Failed to update embeddings: {}", e);
+            }
+            next = ops.try_recv().ok();
+        }
+
+        let mut embedded = 0;
+        for id in queued {
+            let Some(session) = latest.remove(&id) else { continue };
+            match embed_session(&store, &client, &model, &session).await {
+                Ok(count) => embedded += count,
+                Err(e) => {
+                    // Most likely the endpoint or key; the rest would fail the same way. They are
+                    // picked up again on their next change or at the next start.
+                    warn!(session_id = %id, "Failed to embed messages: {}", e);
+                    break;
+                }
+            }
+        }
+        if embedded > 0 {
+            info!(embedded, "Embedded messages for semantic search");
+        }
+    }
+}
+
+/// Splits the embeddable messages of `session` against what is `stored`: returns the ones to
+/// embed, because they are new, edited or embedded by another model, with their content hash,
+/// and the ids of stored messages that are gone.
+fn changed_messages<'a>(
+    stored: &HashMap<String, (String, i64)>,
+    model: &str,
+    session: &'a ChatSession,
+) -> (Vec<(&'a ChatMessage, i64)>, Vec<String>) {
+    let messages: Vec<(&ChatMessage, i64)> = embeddable(session).map(|m| (m, content_hash(&m.content))).collect();
+    let live: HashSet<&str> = messages.iter().map(|(m, _)| m.id.as_str()).collect();
+    let gone = stored.keys().filter(|id| !live.contains(id.as_str())).cloned().collect();
+    let pending = messages
+        .into_iter()
+        .filter(|(m, hash)| stored.get(&m.id) != Some(&(model.to_string(), *hash)))
+        .collect();
+    (pending, gone)
+}
+
+/// Embeds the messages of `session` that are missing or outdated in the store and drops the ones
+/// that are gone. Returns how many were embedded.
+async fn embed_session(store: &VectorStore, client: &LlmClient, model: &str, session: &ChatSession) -> Result<usize, String> {
+    let (pending, gone) = changed_messages(&store.stored(&session.id)?, model, session);
+    if !gone.is_empty() {
+        store.remove_messages(&gone)?;
+    }
+    for chunk in pending.chunks(BATCH_SIZE) {
+        let input: Vec<String> = chunk
+            .iter()
+            .map(|(m, _)| m.content.chars().take(MAX_INPUT_CHARS).collect())
+            .collect();
+        let vectors = client.embed(model, &input).await.map_err(|e| e.to_string())?;
+        let rows: Vec<(&str, i64, Vec<f32>)> = chunk
+            .iter()
+            .zip(vectors)
+            .map(|((m, hash), vector)| (m.id.as_str(), *hash, normalize(vector)))
+            .collect();
+        store.upsert(&session.id, model, &rows)?;
+    }
+    Ok(pending.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hit(session_id: &str, message_id: &str, score: f32) -> SearchHit {
+        SearchHit {
+            session_id: session_id.to_string(),
+            message_id: Some(message_id.to_string()),
+            timestamp: 0,
+            score,
+            snippet: format!("{} keyword", message_id),
+            highlights: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn hybrid_rank_merges_hits_on_the_same_message() {
+        let keyword = vec![hit("s", "a", 10.0), hit("s", "b", 5.0)];
+        let semantic = vec![hit("s", "b", 0.9), hit("s", "c", 0.4)];
+        let ranked = hybrid_rank(keyword, semantic, 0.5);
+
+        let scores: Vec<(&str, f32)> = ranked.iter().map(|h| (h.message_id.as_deref().unwrap(), h.score)).collect();
+        assert_eq!(scores, vec![("b", 0.7), ("a", 0.5), ("c", 0.2)]);
+        // A message found both ways keeps the keyword snippet.
+        assert_eq!(ranked[0].snippet, "b keyword");
+    }
+
+    #[test]
+    fn hybrid_rank_weight_is_clamped() {
+        let ranked = hybrid_rank(vec![hit("s", "a", 3.0)], vec![hit("s", "c", 0.8)], 2.0);
+        assert_eq!(ranked[0].message_id.as_deref(), Some("c"));
+        assert_eq!(ranked[0].score, 0.8);
+        assert_eq!(ranked[1].score, 0.0);
+    }
+
+    #[test]
+    fn vectors_round_trip_at_unit_length() {
+        let vector = normalize(vec![3.0, 4.0]);
+        assert_eq!(vector, vec![0.6, 0.8]);
+        assert_eq!(decode(&encode(&vector)), vector);
+        assert_eq!(encode(&[1.0]), 1.0f32.to_le_bytes().to_vec());
+        assert_eq!(normalize(vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn content_hash_is_stable() {
+        assert_eq!(content_hash(""), 0xcbf2_9ce4_8422_2325u64 as i64);
+        assert_eq!(content_hash("a"), 0xaf63_dc4c_8601_ec8cu64 as i64);
+        assert_ne!(content_hash("deploy"), content_hash("deploy "));
+    }
+
+    #[test]
+    fn only_new_edited_or_other_model_messages_are_embedded() {
+        let mut session = ChatSession::new("s".to_string(), "Chat".to_string());
+        let kept = session.append(ChatMessage::new(Role::User, "kept"));
+        let edited = session.append(ChatMessage::new(Role::Assistant, "edited"));
+        let other_model = session.append(ChatMessage::new(Role::User, "other model"));
+        let new = session.append(ChatMessage::new(Role::Assistant, "new"));
+        session.append(ChatMessage::new(Role::User, "   "));
+
+        let model = "m".to_string();
+        let stored = HashMap::from([
+            (kept.clone(), (model.clone(), content_hash("kept"))),
+            (edited.clone(), (model.clone(), content_hash("before the edit"))),
+            (other_model.clone(), ("old".to_string(), content_hash("other model"))),
+            ("deleted".to_string(), (model.clone(), content_hash("deleted"))),
+        ]);
+        let (pending, gone) = changed_messages(&stored, &model, &session);
+
+        let pending: Vec<&String> = pending.iter().map(|(m, _)| &m.id).collect();
+        assert_eq!(pending, vec![&edited, &other_model, &new]);
+        assert_eq!(gone, vec!["deleted"]);
+    }
+}
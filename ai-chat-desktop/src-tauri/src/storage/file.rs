@@ -0,0 +1,275 @@
+//! File backend: one crash-safe `<data>/.chats/<id>.json` per session.
+//!
+//! Every write goes to a temporary file that is fsynced and then renamed over the target, so a
+//! crash leaves either the old or the new contents. The previous version is kept as
+//! `<id>.json.bak` and used at startup if the main file cannot be parsed.
+
+use super::{branch_page, page_summaries, BranchPage, ListQuery, LoadIssue, SessionStore, SessionSummary};
+use crate::get_app_data_dir;
+use crate::history::ChatSession;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{error, info, warn};
+
+const CHATS_DIR: &str = ".chats";
+const SESSION_EXT: &str = "json";
+const BACKUP_SUFFIX: &str = ".bak";
+const TEMP_SUFFIX: &str = ".tmp";
+const CORRUPT_SUFFIX: &str = ".corrupt";
+
+/// Session files have no cheap way to read just the header, so summaries are cached in memory
+/// after the startup scan and kept in sync on every write.
+pub struct FileStore {
+    dir: PathBuf,
+    summaries: Mutex<HashMap<String, SessionSummary>>,
+}
+
+impl FileStore {
+    pub fn open() -> (Self, Vec<LoadIssue>) {
+        let (mut sessions, issues) = load_sessions();
+        for session in sessions.values_mut() {
+            if session.migrate() {
+                if let Err(e) = save_session(session) {
+                    warn!(session_id = %session.id, "Failed to save migrated session: {}", e);
+                }
+            }
+        }
+        let summaries = sessions
+            .values()
+            .map(|s| (s.id.clone(), SessionSummary::of(s)))
+            .collect();
+        let store = Self {
+            dir: chats_dir(),
+            summaries: Mutex::new(summaries),
+        };
+        (store, issues)
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Result<Option<ChatSession>, String> {
+        let path = session_path(&self.dir, id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        read_session(&path).map(Some)
+    }
+
+    fn save(&self, session: &ChatSession) -> Result<(), String> {
+        save_session(session)?;
+        self.summaries
+            .lock()
+            .map_err(|e| format!("Failed to lock summaries: {}", e))?
+            .insert(session.id.clone(), SessionSummary::of(session));
+        Ok(())
+    }
+
+    fn delete(&self, id: &str) -> Result<(), String> {
+        delete_session(id)?;
+        self.summaries
+            .lock()
+            .map_err(|e| format!("Failed to lock summaries: {}", e))?
+            .remove(id);
+        Ok(())
+    }
+
+    fn list(&self, query: &ListQuery) -> Result<Vec<SessionSummary>, String> {
+        let summaries = self.summaries.lock().map_err(|e| format!("Failed to lock summaries: {}", e))?;
+        Ok(page_summaries(summaries.values().cloned().collect(), query))
+    }
+
+    fn title(&self, id: &str) -> Result<Option<String>, String> {
+        let summaries = self.summaries.lock().map_err(|e| format!("Failed to lock summaries: {}", e))?;
+        Ok(summaries.get(id).map(|s| s.title.clone()))
+    }
+
+    fn load_messages(
+        &self,
+        id: &str,
+        leaf: Option<&str>,
+        start: Option<usize>,
+        limit: usize,
+    ) -> Result<Option<BranchPage>, String> {
+        // A session file can only be read whole.
+        Ok(self.load(id)?.map(|session| branch_page(&session, leaf, start, limit)))
+    }
+
+    fn folders(&self) -> Result<Vec<String>, String> {
+        let summaries = self.summaries.lock().map_err(|e| format!("Failed to lock summaries: {}", e))?;
+        let mut folders: Vec<String> = summaries
+            .values()
+            .filter(|s| s.deleted_at.is_none())
+            .filter_map(|s| s.folder.clone())
+            .collect();
+        folders.sort();
+        folders.dedup();
+        Ok(folders)
+    }
+
+    fn load_all(&self) -> Result<Vec<ChatSession>, String> {
+        // Only reads: cleaning up temp files and restoring backups is left to `open`, since doing
+        // it while the app runs could race with a `write_atomic` in progress.
+        let ids: Vec<String> = self
+            .summaries
+            .lock()
+            .map_err(|e| format!("Failed to lock summaries: {}", e))?
+            .keys()
+            .cloned()
+            .collect();
+        let mut sessions = Vec::with_capacity(ids.len());
+        for id in ids {
+            match self.load(&id) {
+                Ok(Some(session)) => sessions.push(session),
+                Ok(None) => {}
+                Err(e) => warn!(session_id = %id, "Failed to load session: {}", e),
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+pub(super) fn chats_dir() -> PathBuf {
+    let dir = get_app_data_dir().join(CHATS_DIR);
+    if !dir.exists() {
+        fs::create_dir_all(&dir).ok();
+    }
+    dir
+}
+
+fn session_path(dir: &Path, session_id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", session_id, SESSION_EXT))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Writes `contents` to `path` atomically: temp file, fsync, rename, then fsync the directory.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = with_suffix(path, TEMP_SUFFIX);
+    {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    sync_parent_dir(path);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) {
+    if let Some(parent) = path.parent() {
+        if let Ok(dir) = File::open(parent) {
+            dir.sync_all().ok();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) {
+    // Directory handles cannot be fsynced on Windows; the rename is still atomic there.
+}
+
+fn save_session(session: &ChatSession) -> Result<(), String> {
+    let path = session_path(&chats_dir(), &session.id);
+    let content = serde_json::to_string_pretty(session).map_err(|e| e.to_string())?;
+
+    // Keep the last good version around before replacing it.
+    if path.exists() {
+        if let Err(e) = fs::copy(&path, with_suffix(&path, BACKUP_SUFFIX)) {
+            warn!(session_id = %session.id, "Failed to back up session file: {}", e);
+        }
+    }
+    write_atomic(&path, content.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn delete_session(session_id: &str) -> Result<(), String> {
+    let path = session_path(&chats_dir(), session_id);
+    if path.exists() {
+        fs::remove_file(&path).map_err(|e| e.to_string())?;
+    }
+    let backup = with_suffix(&path, BACKUP_SUFFIX);
+    if backup.exists() {
+        fs::remove_file(backup).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Loads every session, recovering from backups where possible.
+///
+/// Files that cannot be read are reported in the returned issues rather than skipped silently;
+/// unrecoverable files are left in place so nothing is lost.
+pub(super) fn load_sessions() -> (HashMap<String, ChatSession>, Vec<LoadIssue>) {
+    let dir = chats_dir();
+    let mut sessions = HashMap::new();
+    let mut issues = Vec::new();
+
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to read sessions directory {:?}: {}", dir, e);
+            return (sessions, issues);
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        // Leftovers from a write that was interrupted before the rename; the target is intact.
+        if file_name.ends_with(TEMP_SUFFIX) {
+            info!("Removing interrupted write {:?}", path);
+            fs::remove_file(&path).ok();
+            continue;
+        }
+        if path.extension().and_then(|e| e.to_str()) != Some(SESSION_EXT) {
+            continue;
+        }
+
+        match read_session(&path) {
+            Ok(session) => {
+                sessions.insert(session.id.clone(), session);
+            }
+            Err(e) => {
+                error!("Failed to load session file {:?}: {}", path, e);
+                let backup = with_suffix(&path, BACKUP_SUFFIX);
+                match read_session(&backup) {
+                    Ok(session) => {
+                        warn!("Restoring session {} from backup {:?}", session.id, backup);
+                        fs::rename(&path, with_suffix(&path, CORRUPT_SUFFIX)).ok();
+                        if let Err(restore_err) = fs::read(&backup).and_then(|bytes| write_atomic(&path, &bytes)) {
+                            error!("Failed to restore {:?} from backup: {}", path, restore_err);
+                        }
+                        sessions.insert(session.id.clone(), session);
+                        issues.push(LoadIssue {
+                            file: file_name,
+                            error: e,
+                            recovered: true,
+                        });
+                    }
+                    Err(_) => issues.push(LoadIssue {
+                        file: file_name,
+                        error: e,
+                        recovered: false,
+                    }),
+                }
+            }
+        }
+    }
+
+    if !issues.is_empty() {
+        warn!("{} session file(s) had load problems: {:?}", issues.len(), issues);
+    }
+    (sessions, issues)
+}
+
+fn read_session(path: &Path) -> Result<ChatSession, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str::<ChatSession>(&content).map_err(|e| e.to_string())
+}
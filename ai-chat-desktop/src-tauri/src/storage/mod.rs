@@ -0,0 +1,404 @@
+//! Persistent storage for chat sessions.
+//!
+//! `SessionStore` abstracts over the backends: one JSON file per session (the original format)
+//! and an embedded SQLite database. The backend is chosen by `storage.backend` in settings.json.
+
+mod file;
+mod sqlite;
+
+pub use file::FileStore;
+pub use sqlite::SqliteStore;
+
+use crate::history::{self, ChatMessage, ChatSession, Role, SiblingInfo};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+const SNIPPET_CHARS: usize = 80;
+
+/// A session file that could not be loaded as-is at startup.
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadIssue {
+    pub file: String,
+    pub error: String,
+    /// True if the session was restored from its backup; false if it is still unreadable.
+    pub recovered: bool,
+}
+
+/// The session fields needed to render the sidebar, without the messages.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub title: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub message_count: usize,
+    /// Start of the last user or assistant message.
+    pub last_snippet: Option<String>,
+    pub tags: Vec<String>,
+    pub folder: Option<String>,
+    pub pinned: bool,
+    pub deleted_at: Option<u64>,
+}
+
+impl SessionSummary {
+    pub fn of(session: &ChatSession) -> Self {
+        let last_dialogue = session
+            .active_branch()
+            .into_iter()
+            .rev()
+            .find(|m| matches!(m.role, Role::User | Role::Assistant) && !m.is_step());
+        Self {
+            id: session.id.clone(),
+            title: session.title.clone(),
+            created_at: session.created_at,
+            updated_at: session.updated_at,
+            message_count: session.messages.len(),
+            last_snippet: last_dialogue.map(|m| snippet(&m.content)),
+            tags: session.tags.clone(),
+            folder: session.folder.clone(),
+            pinned: session.pinned,
+            deleted_at: session.deleted_at,
+        }
+    }
+}
+
+/// Collapses whitespace and truncates at a character boundary.
+pub fn snippet(content: &str) -> String {
+    let collapsed = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    match collapsed.char_indices().nth(SNIPPET_CHARS) {
+        Some((idx, _)) => format!("{}...", &collapsed[..idx]),
+        None => collapsed,
+    }
+}
+
+/// A slice of one branch of a session, see `SessionStore::load_messages`.
+#[derive(Debug, Clone)]
+pub struct BranchPage {
+    pub messages: Vec<(ChatMessage, SiblingInfo)>,
+    /// Index of `messages[0]` within the branch.
+    pub start: usize,
+    /// Length of the whole branch.
+    pub total: usize,
+}
+
+/// Which part of a branch of `total` messages to return: `limit` messages from `start`, or the
+/// last `limit` if `start` is `None`.
+fn page_range(total: usize, start: Option<usize>, limit: usize) -> (usize, usize) {
+    let start = start.unwrap_or(total.saturating_sub(limit)).min(total);
+    (start, limit.min(total - start))
+}
+
+/// Lays out the branch ending at `leaf` (the active one if `None`) and returns a page of it,
+/// cloning only the messages on the page.
+pub fn branch_page(session: &ChatSession, leaf: Option<&str>, start: Option<usize>, limit: usize) -> BranchPage {
+    let layout = history::branch_layout(&session.nodes(), leaf.or(session.active_leaf.as_deref()));
+    let total = layout.len();
+    let (start, count) = page_range(total, start, limit);
+    let messages = layout
+        .into_iter()
+        .skip(start)
+        .take(count)
+        .map(|(i, siblings)| (session.messages[i].clone(), siblings))
+        .collect();
+    BranchPage { messages, start, total }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SessionSort {
+    #[default]
+    UpdatedDesc,
+    CreatedDesc,
+    TitleAsc,
+}
+
+/// Position of a session in a given sort order. Ties are broken by id.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum SortKey {
+    Time(u64),
+    Title(String),
+}
+
+/// Keyset cursor: the sort key and id of the last summary on the previous page.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Cursor {
+    pub key: SortKey,
+    pub id: String,
+}
+
+impl SessionSort {
+    pub fn key(&self, summary: &SessionSummary) -> SortKey {
+        match self {
+            SessionSort::UpdatedDesc => SortKey::Time(summary.updated_at),
+            SessionSort::CreatedDesc => SortKey::Time(summary.created_at),
+            SessionSort::TitleAsc => SortKey::Title(summary.title.clone()),
+        }
+    }
+
+    /// Orders two summaries as they should appear in the list.
+    pub fn compare(&self, a: &SessionSummary, b: &SessionSummary) -> Ordering {
+        let ord = (self.key(a), &a.id).cmp(&(self.key(b), &b.id));
+        match self {
+            SessionSort::TitleAsc => ord,
+            _ => ord.reverse(),
+        }
+    }
+
+    /// Whether `summary` comes strictly after `cursor` in this order.
+    pub fn is_after(&self, summary: &SessionSummary, cursor: &Cursor) -> bool {
+        let ord = (self.key(summary), &summary.id).cmp(&(cursor.key.clone(), &cursor.id));
+        match self {
+            SessionSort::TitleAsc => ord == Ordering::Greater,
+            _ => ord == Ordering::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ListQuery {
+    pub sort: SessionSort,
+    pub after: Option<Cursor>,
+    pub limit: usize,
+    /// Only pinned (`Some(true)`) or only unpinned (`Some(false)`) sessions.
+    pub pinned: Option<bool>,
+    /// Only sessions in this folder.
+    pub folder: Option<String>,
+    /// List the trash instead of the live sessions.
+    pub trashed: bool,
+}
+
+impl ListQuery {
+    pub fn matches(&self, summary: &SessionSummary) -> bool {
+        summary.deleted_at.is_some() == self.trashed
+            && self.pinned.map_or(true, |p| summary.pinned == p)
+            && self.folder.as_ref().map_or(true, |f| summary.folder.as_ref() == Some(f))
+    }
+}
+
+/// Sorts and pages summaries in memory, for backends without an index to do it.
+pub fn page_summaries(mut summaries: Vec<SessionSummary>, query: &ListQuery) -> Vec<SessionSummary> {
+    summaries.sort_by(|a, b| query.sort.compare(a, b));
+    summaries
+        .into_iter()
+        .filter(|s| query.matches(s))
+        .filter(|s| query.after.as_ref().map_or(true, |c| query.sort.is_after(s, c)))
+        .take(query.limit)
+        .collect()
+}
+
+pub trait SessionStore: Send + Sync {
+    /// Loads one session with all its messages.
+    fn load(&self, id: &str) -> Result<Option<ChatSession>, String>;
+
+    /// Inserts or replaces a session.
+    fn save(&self, session: &ChatSession) -> Result<(), String>;
+
+    fn delete(&self, id: &str) -> Result<(), String>;
+
+    /// Returns up to `query.limit` summaries following `query.after` in `query.sort` order.
+    fn list(&self, query: &ListQuery) -> Result<Vec<SessionSummary>, String>;
+
+    /// The title of a session, without loading its messages. `None` if it doesn't exist.
+    fn title(&self, id: &str) -> Result<Option<String>, String>;
+
+    /// Loads a page of the branch ending at `leaf`, or of the active branch if `leaf` is `None`.
+    /// Takes `limit` messages from `start`, or the last `limit` if `start` is `None`. Returns
+    /// `None` if the session does not exist.
+    fn load_messages(
+        &self,
+        id: &str,
+        leaf: Option<&str>,
+        start: Option<usize>,
+        limit: usize,
+    ) -> Result<Option<BranchPage>, String>;
+
+    /// Names of all folders used by live sessions, sorted.
+    fn folders(&self) -> Result<Vec<String>, String>;
+
+    /// Loads every session with its messages. Used for index rebuilds.
+    fn load_all(&self) -> Result<Vec<ChatSession>, String>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    File,
+    #[default]
+    Sqlite,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StorageConfig {
+    #[serde(default)]
+    pub backend: StorageBackend,
+    /// Days a deleted session stays in the trash before it is purged at startup.
+    #[serde(default = "default_trash_retention_days")]
+    pub trash_retention_days: u64,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::default(),
+            trash_retention_days: default_trash_retention_days(),
+        }
+    }
+}
+
+fn default_trash_retention_days() -> u64 {
+    30
+}
+
+/// Returns a session from the in-memory cache, loading it from the store on a miss. Sessions in
+/// the trash are read-only until restored, so they are refused rather than cached.
+pub fn cached_session<'a>(
+    store: &dyn SessionStore,
+    sessions: &'a mut HashMap<String, ChatSession>,
+    id: &str,
+) -> Result<Option<&'a mut ChatSession>, String> {
+    if !sessions.contains_key(id) {
+        match store.load(id)? {
+            Some(session) if session.deleted_at.is_some() => {
+                return Err(format!("Session {} is in the trash", id));
+            }
+            Some(session) => {
+                sessions.insert(id.to_string(), session);
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(sessions.get_mut(id))
+}
+
+/// Takes a session out of the trash and returns it.
+pub fn restore_session(store: &dyn SessionStore, id: &str) -> Result<ChatSession, String> {
+    let mut session = store.load(id)?.ok_or_else(|| "Session not found".to_string())?;
+    if session.deleted_at.is_none() {
+        return Err(format!("Session {} is not in the trash", id));
+    }
+    session.deleted_at = None;
+    store.save(&session).map_err(|e| format!("Failed to save session: {}", e))?;
+    Ok(session)
+}
+
+/// Permanently deletes the sessions that have been in the trash longer than the retention period.
+/// Returns how many were deleted.
+pub fn purge_expired_trash(store: &dyn SessionStore, retention_days: u64, now: u64) -> Result<usize, String> {
+    let cutoff = now.saturating_sub(retention_days.saturating_mul(24 * 60 * 60));
+    let trashed = store.list(&ListQuery {
+        trashed: true,
+        limit: usize::MAX,
+        ..ListQuery::default()
+    })?;
+    let mut purged = 0;
+    for summary in trashed {
+        if summary.deleted_at.map_or(false, |t| t <= cutoff) {
+            store.delete(&summary.id)?;
+            purged += 1;
+        }
+    }
+    Ok(purged)
+}
+
+/// Opens the configured backend, returning any problems found while reading existing data.
+pub fn open(config: &StorageConfig) -> Result<(Box<dyn SessionStore>, Vec<LoadIssue>), String> {
+    match config.backend {
+        StorageBackend::File => {
+            let (store, issues) = FileStore::open();
+            Ok((Box::new(store), issues))
+        }
+        StorageBackend::Sqlite => {
+            let (store, issues) = SqliteStore::open()?;
+            Ok((Box::new(store), issues))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Keeps sessions in memory; only what the trash functions need.
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<String, ChatSession>>);
+
+    impl SessionStore for MemoryStore {
+        fn load(&self, id: &str) -> Result<Option<ChatSession>, String> {
+            Ok(self.0.lock().unwrap().get(id).cloned())
+        }
+
+        fn save(&self, session: &ChatSession) -> Result<(), String> {
+            self.0.lock().unwrap().insert(session.id.clone(), session.clone());
+            Ok(())
+        }
+
+        fn delete(&self, id: &str) -> Result<(), String> {
+            self.0.lock().unwrap().remove(id);
+            Ok(())
+        }
+
+        fn list(&self, query: &ListQuery) -> Result<Vec<SessionSummary>, String> {
+            let summaries = self.0.lock().unwrap().values().map(SessionSummary::of).collect();
+            Ok(page_summaries(summaries, query))
+        }
+
+        fn title(&self, id: &str) -> Result<Option<String>, String> {
+            Ok(self.load(id)?.map(|session| session.title))
+        }
+
+        fn load_messages(
+            &self,
+            id: &str,
+            leaf: Option<&str>,
+            start: Option<usize>,
+            limit: usize,
+        ) -> Result<Option<BranchPage>, String> {
+            Ok(self.load(id)?.map(|session| branch_page(&session, leaf, start, limit)))
+        }
+
+        fn folders(&self) -> Result<Vec<String>, String> {
+            Ok(Vec::new())
+        }
+
+        fn load_all(&self) -> Result<Vec<ChatSession>, String> {
+            Ok(self.0.lock().unwrap().values().cloned().collect())
+        }
+    }
+
+    fn store_with(live: &str, trashed: &str) -> MemoryStore {
+        let store = MemoryStore::default();
+        store.save(&ChatSession::new(live.to_string(), "Live".to_string())).unwrap();
+        let mut session = ChatSession::new(trashed.to_string(), "Trashed".to_string());
+        session.deleted_at = Some(100);
+        store.save(&session).unwrap();
+        store
+    }
+
+    #[test]
+    fn sessions_in_the_trash_are_not_cached() {
+        let store = store_with("live", "trashed");
+        let mut sessions = HashMap::new();
+        assert!(cached_session(&store, &mut sessions, "live").unwrap().is_some());
+        assert!(cached_session(&store, &mut sessions, "missing").unwrap().is_none());
+        assert_eq!(
+            cached_session(&store, &mut sessions, "trashed").unwrap_err(),
+            "Session trashed is in the trash"
+        );
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[test]
+    fn only_sessions_in_the_trash_can_be_restored() {
+        let store = store_with("live", "trashed");
+        assert_eq!(restore_session(&store, "live").unwrap_err(), "Session live is not in the trash");
+        assert!(restore_session(&store, "missing").is_err());
+
+        assert_eq!(restore_session(&store, "trashed").unwrap().deleted_at, None);
+        assert_eq!(store.load("trashed").unwrap().unwrap().deleted_at, None);
+        let mut sessions = HashMap::new();
+        assert!(cached_session(&store, &mut sessions, "trashed").unwrap().is_some());
+    }
+}
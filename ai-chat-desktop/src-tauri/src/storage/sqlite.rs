@@ -0,0 +1,539 @@
+//! SQLite backend: `<data>/sessions.db` with one row per session and one row per message.
+//!
+//! Fields that are queried or sorted on get their own columns. Everything else on a session or
+//! message is kept in a JSON `extra` column, so new optional fields on `ChatSession` and
+//! `ChatMessage` round-trip without a schema change.
+
+use super::{
+    file, page_range, snippet, BranchPage, ListQuery, LoadIssue, SessionSort, SessionStore, SessionSummary, SortKey,
+};
+use crate::get_app_data_dir;
+use crate::history::{self, ChatMessage, ChatSession, MessageNode, Role, SESSION_SCHEMA_VERSION};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde_json::{Map, Value};
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+const DB_FILE: &str = "sessions.db";
+const FILE_MIGRATION_KEY: &str = "migrated_from_files";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sessions (
+    id         TEXT PRIMARY KEY,
+    title      TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    extra      TEXT NOT NULL DEFAULT '{}'
+);
+CREATE INDEX IF NOT EXISTS sessions_updated_at ON sessions(updated_at DESC);
+CREATE TABLE IF NOT EXISTS messages (
+    session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    seq        INTEGER NOT NULL,
+    role       TEXT NOT NULL,
+    content    TEXT NOT NULL,
+    timestamp  INTEGER NOT NULL,
+    extra      TEXT NOT NULL DEFAULT '{}',
+    PRIMARY KEY (session_id, seq)
+);
+CREATE TABLE IF NOT EXISTS meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+";
+
+const SESSION_COLUMNS: [&str; 4] = ["id", "title", "created_at", "updated_at"];
+const MESSAGE_COLUMNS: [&str; 3] = ["role", "content", "timestamp"];
+
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens the database and, the first time, imports the sessions from `.chats/`.
+    pub fn open() -> Result<(Self, Vec<LoadIssue>), String> {
+        let path = get_app_data_dir().join(DB_FILE);
+        let conn = Connection::open(&path).map_err(|e| format!("Failed to open {:?}: {}", path, e))?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(|e| e.to_string())?;
+        conn.pragma_update(None, "foreign_keys", "ON").map_err(|e| e.to_string())?;
+        conn.execute_batch(SCHEMA).map_err(|e| format!("Failed to create schema: {}", e))?;
+
+        let store = Self {
+            conn: Mutex::new(conn),
+        };
+        let mut issues = store.migrate_from_files()?;
+        issues.extend(store.migrate_schema()?);
+        Ok((store, issues))
+    }
+
+    /// Upgrades sessions written by an older version of the app, see `ChatSession::migrate`.
+    /// Sessions that cannot be read are left untouched and reported.
+    fn migrate_schema(&self) -> Result<Vec<LoadIssue>, String> {
+        let mut conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        let ids: Vec<String> = {
+            let mut stmt = conn
+                .prepare("SELECT id FROM sessions WHERE COALESCE(json_extract(extra, '$.schema_version'), 0) < ?1")
+                .map_err(|e| e.to_string())?;
+            let rows = stmt
+                .query_map([SESSION_SCHEMA_VERSION], |row| row.get(0))
+                .map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+        };
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        info!("Upgrading {} sessions to schema version {}", ids.len(), SESSION_SCHEMA_VERSION);
+        let mut issues = Vec::new();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for id in ids {
+            match read_session(&tx, &id) {
+                Ok(Some(mut session)) => {
+                    session.migrate();
+                    write_session(&tx, &session)?;
+                }
+                Ok(None) => {}
+                Err(error) => issues.push(LoadIssue {
+                    file: id,
+                    error,
+                    recovered: false,
+                }),
+            }
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(issues)
+    }
+
+    fn migrate_from_files(&self) -> Result<Vec<LoadIssue>, String> {
+        let mut conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        let migrated: Option<String> = conn
+            .query_row("SELECT value FROM meta WHERE key = ?1", [FILE_MIGRATION_KEY], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        if migrated.is_some() {
+            return Ok(Vec::new());
+        }
+
+        // The JSON files are left in place as a backup of the pre-migration state.
+        let (sessions, issues) = file::load_sessions();
+        info!("Migrating {} sessions from .chats/ into SQLite", sessions.len());
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for session in sessions.values() {
+            write_session(&tx, session)?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+            params![FILE_MIGRATION_KEY, crate::now_ts().to_string()],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+
+        // Unrecoverable files were not migrated; keep reporting them until the user deals with them.
+        Ok(issues)
+    }
+}
+
+impl SessionStore for SqliteStore {
+    fn load(&self, id: &str) -> Result<Option<ChatSession>, String> {
+        let conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        read_session(&conn, id)
+    }
+
+    fn save(&self, session: &ChatSession) -> Result<(), String> {
+        let mut conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        write_session(&tx, session)?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn delete(&self, id: &str) -> Result<(), String> {
+        let conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        conn.execute("DELETE FROM sessions WHERE id = ?1", [id])
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+
+    fn list(&self, query: &ListQuery) -> Result<Vec<SessionSummary>, String> {
+        let (column, direction, comparison) = match query.sort {
+            SessionSort::UpdatedDesc => ("s.updated_at", "DESC", "<"),
+            SessionSort::CreatedDesc => ("s.created_at", "DESC", "<"),
+            SessionSort::TitleAsc => ("s.title", "ASC", ">"),
+        };
+        let mut values = Vec::new();
+        let mut conditions = vec![format!(
+            "json_extract(s.extra, '$.deleted_at') IS {}NULL",
+            if query.trashed { "NOT " } else { "" }
+        )];
+        if let Some(cursor) = &query.after {
+            values.push(match &cursor.key {
+                SortKey::Time(t) => SqlValue::Integer(*t as i64),
+                SortKey::Title(t) => SqlValue::Text(t.clone()),
+            });
+            values.push(SqlValue::Text(cursor.id.clone()));
+            conditions.push(format!("({}, s.id) {} (?{}, ?{})", column, comparison, values.len() - 1, values.len()));
+        }
+        if let Some(pinned) = query.pinned {
+            values.push(SqlValue::Integer(pinned as i64));
+            conditions.push(format!("COALESCE(json_extract(s.extra, '$.pinned'), 0) = ?{}", values.len()));
+        }
+        if let Some(folder) = &query.folder {
+            values.push(SqlValue::Text(folder.clone()));
+            conditions.push(format!("json_extract(s.extra, '$.folder') = ?{}", values.len()));
+        }
+        let where_clause = format!("WHERE {}", conditions.join(" AND "));
+        // SQLite treats a negative LIMIT as "no limit".
+        values.push(SqlValue::Integer(i64::try_from(query.limit).unwrap_or(-1)));
+        let sql = format!(
+            "SELECT s.id, s.title, s.created_at, s.updated_at,
+                    (SELECT COUNT(*) FROM messages m WHERE m.session_id = s.id),
+                    (WITH RECURSIVE branch(id, depth) AS (
+                         SELECT json_extract(s.extra, '$.active_leaf'), 0
+                         UNION ALL
+                         SELECT json_extract(m.extra, '$.parent_id'), b.depth + 1
+                         FROM branch b JOIN messages m
+                           ON m.session_id = s.id AND json_extract(m.extra, '$.id') = b.id
+                         -- Guard against cycles in hand-edited files.
+                         WHERE b.depth < (SELECT COUNT(*) FROM messages c WHERE c.session_id = s.id)
+                     )
+                     SELECT m.content FROM branch b JOIN messages m
+                       ON m.session_id = s.id AND json_extract(m.extra, '$.id') = b.id
+                     WHERE m.role IN ('user', 'assistant') AND json_extract(m.extra, '$.tool_call') IS NULL
+                     ORDER BY b.depth LIMIT 1),
+                    json_extract(s.extra, '$.tags'),
+                    json_extract(s.extra, '$.folder'),
+                    COALESCE(json_extract(s.extra, '$.pinned'), 0),
+                    json_extract(s.extra, '$.deleted_at')
+             FROM sessions s
+             {where_clause}
+             ORDER BY {column} {direction}, s.id {direction}
+             LIMIT ?{limit_param}",
+            limit_param = values.len(),
+        );
+
+        let conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params_from_iter(values), |row| {
+                let last: Option<String> = row.get(5)?;
+                let tags: Option<String> = row.get(6)?;
+                Ok(SessionSummary {
+                    id: row.get(0)?,
+                    title: row.get(1)?,
+                    created_at: row.get::<_, i64>(2)? as u64,
+                    updated_at: row.get::<_, i64>(3)? as u64,
+                    message_count: row.get::<_, i64>(4)? as usize,
+                    last_snippet: last.as_deref().map(snippet),
+                    tags: tags.and_then(|t| serde_json::from_str(&t).ok()).unwrap_or_default(),
+                    folder: row.get(7)?,
+                    pinned: row.get::<_, i64>(8)? != 0,
+                    deleted_at: row.get::<_, Option<i64>>(9)?.map(|t| t as u64),
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    }
+
+    fn title(&self, id: &str) -> Result<Option<String>, String> {
+        let conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        conn.query_row("SELECT title FROM sessions WHERE id = ?1", [id], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())
+    }
+
+    fn load_messages(
+        &self,
+        id: &str,
+        leaf: Option<&str>,
+        start: Option<usize>,
+        limit: usize,
+    ) -> Result<Option<BranchPage>, String> {
+        let conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        let extra: Option<String> = conn
+            .query_row("SELECT extra FROM sessions WHERE id = ?1", [id], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+        let Some(extra) = extra else {
+            return Ok(None);
+        };
+        let active_leaf = parse_extra(&extra)
+            .get("active_leaf")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        // Lay out the branch from the tree's shape alone, then read only the messages on the page.
+        let (seqs, nodes) = read_nodes(&conn, id)?;
+        let layout = history::branch_layout(&nodes, leaf.or(active_leaf.as_deref()));
+        let total = layout.len();
+        let (start, count) = page_range(total, start, limit);
+
+        let mut stmt = conn
+            .prepare("SELECT role, content, timestamp, extra FROM messages WHERE session_id = ?1 AND seq = ?2")
+            .map_err(|e| e.to_string())?;
+        let mut messages = Vec::with_capacity(count);
+        for (i, siblings) in layout.into_iter().skip(start).take(count) {
+            let (role, content, timestamp, extra) = stmt
+                .query_row(params![id, seqs[i]], |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                })
+                .map_err(|e| e.to_string())?;
+            messages.push((parse_message(role, content, timestamp, &extra, id)?, siblings));
+        }
+        Ok(Some(BranchPage { messages, start, total }))
+    }
+
+    fn folders(&self) -> Result<Vec<String>, String> {
+        let conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT DISTINCT json_extract(extra, '$.folder') AS folder FROM sessions
+                 WHERE folder IS NOT NULL AND json_extract(extra, '$.deleted_at') IS NULL
+                 ORDER BY folder",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map([], |row| row.get(0)).map_err(|e| e.to_string())?;
+        rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+    }
+
+    fn load_all(&self) -> Result<Vec<ChatSession>, String> {
+        let conn = self.conn.lock().map_err(|e| format!("Failed to lock database: {}", e))?;
+        let ids: Vec<String> = {
+            let mut stmt = conn.prepare("SELECT id FROM sessions").map_err(|e| e.to_string())?;
+            let rows = stmt.query_map([], |row| row.get(0)).map_err(|e| e.to_string())?;
+            rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())?
+        };
+        let mut sessions = Vec::with_capacity(ids.len());
+        for id in ids {
+            match read_session(&conn, &id) {
+                Ok(Some(session)) => sessions.push(session),
+                Ok(None) => {}
+                Err(e) => warn!(session_id = %id, "Skipping unreadable session: {}", e),
+            }
+        }
+        Ok(sessions)
+    }
+}
+
+/// Splits a serialized struct into the named columns and a JSON object holding the rest.
+fn split_columns(value: Value, columns: &[&str]) -> (Map<String, Value>, String) {
+    let mut object = match value {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    let mut named = Map::new();
+    for column in columns {
+        if let Some(v) = object.remove(*column) {
+            named.insert(column.to_string(), v);
+        }
+    }
+    (named, Value::Object(object).to_string())
+}
+
+fn write_session(conn: &Connection, session: &ChatSession) -> Result<(), String> {
+    let mut value = serde_json::to_value(session).map_err(|e| e.to_string())?;
+    let messages = value
+        .as_object_mut()
+        .and_then(|o| o.remove("messages"))
+        .unwrap_or(Value::Array(Vec::new()));
+    let (_, extra) = split_columns(value, &SESSION_COLUMNS);
+
+    conn.execute(
+        "INSERT INTO sessions (id, title, created_at, updated_at, extra) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT(id) DO UPDATE SET title = excluded.title, created_at = excluded.created_at,
+             updated_at = excluded.updated_at, extra = excluded.extra",
+        params![session.id, session.title, session.created_at as i64, session.updated_at as i64, extra],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Messages are rewritten wholesale; sessions are small enough that diffing is not worth it.
+    conn.execute("DELETE FROM messages WHERE session_id = ?1", [&session.id])
+        .map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "INSERT INTO messages (session_id, seq, role, content, timestamp, extra)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )
+        .map_err(|e| e.to_string())?;
+    let messages = match messages {
+        Value::Array(items) => items,
+        _ => Vec::new(),
+    };
+    for (seq, (message, value)) in session.messages.iter().zip(messages).enumerate() {
+        let (named, extra) = split_columns(value, &MESSAGE_COLUMNS);
+        let role = named.get("role").and_then(Value::as_str).unwrap_or("user");
+        stmt.execute(params![
+            session.id,
+            seq as i64,
+            role,
+            message.content,
+            message.timestamp as i64,
+            extra
+        ])
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn read_session(conn: &Connection, id: &str) -> Result<Option<ChatSession>, String> {
+    let row = conn
+        .query_row(
+            "SELECT title, created_at, updated_at, extra FROM sessions WHERE id = ?1",
+            [id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    let Some((title, created_at, updated_at, extra)) = row else {
+        return Ok(None);
+    };
+
+    let messages = read_messages(conn, id, 0, usize::MAX)?;
+
+    let mut object = parse_extra(&extra);
+    object.insert("id".into(), Value::String(id.to_string()));
+    object.insert("title".into(), Value::String(title));
+    object.insert("created_at".into(), Value::from(created_at));
+    object.insert("updated_at".into(), Value::from(updated_at));
+    object.insert("messages".into(), serde_json::to_value(&messages).map_err(|e| e.to_string())?);
+    serde_json::from_value(Value::Object(object))
+        .map(Some)
+        .map_err(|e| format!("Failed to read session {}: {}", id, e))
+}
+
+fn read_messages(conn: &Connection, id: &str, offset: usize, limit: usize) -> Result<Vec<ChatMessage>, String> {
+    // SQLite treats a negative LIMIT as "no limit".
+    let limit = i64::try_from(limit).unwrap_or(-1);
+    let mut stmt = conn
+        .prepare(
+            "SELECT role, content, timestamp, extra FROM messages WHERE session_id = ?1
+             ORDER BY seq LIMIT ?2 OFFSET ?3",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![id, limit, offset as i64], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut messages = Vec::new();
+    for row in rows {
+        let (role, content, timestamp, extra) = row.map_err(|e| e.to_string())?;
+        messages.push(parse_message(role, content, timestamp, &extra, id)?);
+    }
+    Ok(messages)
+}
+
+/// Fails rather than skipping the row: `write_session` rewrites every message of a session, so
+/// a message left out of a load would be deleted for good by the next save.
+fn parse_message(
+    role: String,
+    content: String,
+    timestamp: i64,
+    extra: &str,
+    session_id: &str,
+) -> Result<ChatMessage, String> {
+    let mut object = parse_extra(extra);
+    object.insert("role".into(), Value::String(role));
+    object.insert("content".into(), Value::String(content));
+    object.insert("timestamp".into(), Value::from(timestamp));
+    serde_json::from_value(Value::Object(object))
+        .map_err(|e| format!("Failed to read a message of session {}: {}", session_id, e))
+}
+
+/// The shape of a session's message tree, without message contents, with each node's `seq`.
+fn read_nodes(conn: &Connection, id: &str) -> Result<(Vec<i64>, Vec<MessageNode>), String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT seq, json_extract(extra, '$.id'), json_extract(extra, '$.parent_id'), role,
+                    json_extract(extra, '$.tool_call') IS NOT NULL
+             FROM messages WHERE session_id = ?1 ORDER BY seq",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map([id], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, bool>(4)?,
+            ))
+        })
+        .map_err(|e| e.to_string())?;
+
+    let mut seqs = Vec::new();
+    let mut nodes = Vec::new();
+    for row in rows {
+        let (seq, message_id, parent_id, role, is_step) = row.map_err(|e| e.to_string())?;
+        // Every message has an id since schema version 1, see `migrate_schema`.
+        let Some(message_id) = message_id else { continue };
+        seqs.push(seq);
+        nodes.push(MessageNode {
+            id: message_id,
+            parent_id,
+            role: serde_json::from_value(Value::String(role)).unwrap_or(Role::System),
+            is_step,
+        });
+    }
+    Ok((seqs, nodes))
+}
+
+fn parse_extra(extra: &str) -> Map<String, Value> {
+    match serde_json::from_str(extra) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_messages_fail_the_load() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let mut session = ChatSession::new("s".into(), "Session".into());
+        session.messages.push(ChatMessage::new(Role::User, "hi"));
+        write_session(&conn, &session).unwrap();
+        assert_eq!(read_session(&conn, "s").unwrap().unwrap().messages.len(), 1);
+
+        conn.execute("UPDATE messages SET extra = json_set(extra, '$.id', 42)", []).unwrap();
+        assert!(read_session(&conn, "s").is_err());
+    }
+
+    #[test]
+    fn snippets_follow_the_active_branch() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        let store = SqliteStore {
+            conn: Mutex::new(conn),
+        };
+        let mut session = ChatSession::new("s".into(), "Session".into());
+        let prompt = session.append(ChatMessage::new(Role::User, "question"));
+        let first = session.append(ChatMessage::new(Role::Assistant, "first answer"));
+        session.append_after(Some(prompt), ChatMessage::new(Role::Assistant, "second answer"));
+        session.active_leaf = Some(first);
+        store.save(&session).unwrap();
+
+        let listed = store.list(&ListQuery { limit: usize::MAX, ..ListQuery::default() }).unwrap();
+        assert_eq!(listed[0].last_snippet.as_deref(), Some("first answer"));
+        assert_eq!(listed[0].last_snippet, SessionSummary::of(&session).last_snippet);
+    }
+}
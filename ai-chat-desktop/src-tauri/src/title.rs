@@ -0,0 +1,99 @@
+//! Session titles: a quick heuristic from the first prompt, replaced by a model-written title once
+//! the first exchange is done.
+use crate::error::{AgentError, ErrorKind};
+use crate::history::{ChatMessage, Role};
+use crate::llm::LlmClient;
+use async_openai::types::{
+    ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs, CreateChatCompletionRequestArgs,
+};
+
+pub const PLACEHOLDER_TITLE: &str = "New Chat";
+
+const HEURISTIC_TITLE_CHARS: usize = 20;
+const MAX_TITLE_CHARS: usize = 60;
+// Only the start of the conversation is needed to name it.
+const MAX_EXCERPT_CHARS: usize = 2_000;
+
+const TITLE_INSTRUCTION: &str = "Write a short, descriptive title (at most 6 words) for the conversation below. \
+Use the same language as the conversation. Reply with the title only: no quotes, no trailing punctuation.";
+
+/// The first characters of the first user message.
+pub fn heuristic_title(messages: &[ChatMessage]) -> String {
+    messages
+        .iter()
+        .find(|m| m.role == Role::User)
+        .map(|m| {
+            let mut t = m.content.trim().to_string();
+            // Ensure we truncate at a character boundary to avoid panic
+            if let Some((max_index, _)) = t.char_indices().nth(HEURISTIC_TITLE_CHARS) {
+                t.truncate(max_index);
+                t.push_str("...");
+            }
+            t
+        })
+        .unwrap_or_else(|| PLACEHOLDER_TITLE.to_string())
+}
+
+/// Asks the model for a title for the conversation so far.
+pub async fn generate_title(
+    client: &LlmClient,
+    model: &str,
+    messages: &[ChatMessage],
+) -> Result<String, AgentError> {
+    let mut excerpt = String::new();
+    for message in messages
+        .iter()
+        .filter(|m| matches!(m.role, Role::User | Role::Assistant) && !m.is_step())
+    {
+        let speaker = if message.role == Role::User { "User" } else { "Assistant" };
+        excerpt.push_str(&format!("{}: {}\n\n", speaker, message.content.trim()));
+        if excerpt.chars().count() > MAX_EXCERPT_CHARS {
+            break;
+        }
+    }
+    let excerpt: String = excerpt.chars().take(MAX_EXCERPT_CHARS).collect();
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(model)
+        .messages(vec![
+            ChatCompletionRequestSystemMessageArgs::default()
+                .content(TITLE_INSTRUCTION)
+                .build()
+                .unwrap()
+                .into(),
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(excerpt)
+                .build()
+                .unwrap()
+                .into(),
+        ])
+        .max_tokens(30u16)
+        .build()
+        .map_err(|e| AgentError::new(ErrorKind::InvalidRequest, e.to_string()))?;
+
+    let response = client.chat(&request, |_, _, _| {}).await?;
+    let raw = response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .unwrap_or_default();
+    clean_title(&raw).ok_or_else(|| AgentError::internal("The model returned an empty title"))
+}
+
+/// Strips the quotes, labels and trailing punctuation models tend to add anyway.
+fn clean_title(raw: &str) -> Option<String> {
+    let line = raw.lines().map(str::trim).find(|l| !l.is_empty())?;
+    let line = line
+        .strip_prefix("Title:")
+        .or_else(|| line.strip_prefix("title:"))
+        .unwrap_or(line)
+        .trim();
+    let title = line
+        .trim_matches(|c: char| matches!(c, '"' | '\'' | '“' | '”' | '「' | '」' | '《' | '》' | '*' | '#'))
+        .trim_end_matches(|c: char| matches!(c, '.' | '。' | '!' | '！'))
+        .trim();
+    if title.is_empty() {
+        return None;
+    }
+    Some(title.chars().take(MAX_TITLE_CHARS).collect())
+}
@@ -0,0 +1,670 @@
+//! Tools that run inside the app, so basic capabilities do not need an MCP server (and with it
+//! Node or Java) installed.
+//!
+//! They are listed under the `builtin` pseudo server next to the MCP servers, switched on and off
+//! in the same Tools menu and called by the agent loop like any other tool. File tools only see
+//! the folders in `tools.allowed_dirs` and the knowledge base folders.
+//!
+//! `remember`, `recall` and `forget` give the agent a long-term memory shared by all chats.
+
+use crate::export::{civil_from_days, format_utc};
+use crate::knowledge;
+use crate::memory;
+use crate::AppState;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Pseudo server the built-in tools are listed under.
+pub const SERVER_NAME: &str = "builtin";
+
+// Larger files are returned in part; the model can ask for a line range.
+const MAX_READ_BYTES: usize = 100_000;
+const MAX_DIR_ENTRIES: usize = 500;
+const DEFAULT_CHAT_RESULTS: usize = 10;
+const MAX_CHAT_RESULTS: usize = 50;
+const DEFAULT_RECALL_RESULTS: usize = 5;
+const MAX_RECALL_RESULTS: usize = 20;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ToolsConfig {
+    /// Folders `read_file` and `list_directory` may look into, recursively. Nothing outside them
+    /// is readable.
+    #[serde(default)]
+    pub allowed_dirs: Vec<PathBuf>,
+}
+
+/// What a tool call can see besides its arguments.
+pub struct ToolContext<'a> {
+    pub state: &'a AppState,
+    /// The chat the agent is answering in.
+    pub session_id: &'a str,
+}
+
+/// A tool implemented in the app.
+trait BuiltinTool: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// What the tool does and its arguments, for the system prompt.
+    fn description(&self) -> String;
+    fn call(&self, arguments: &Value, context: &ToolContext) -> Result<String, String>;
+}
+
+/// The built-in tools available in this run.
+pub struct ToolRegistry {
+    tools: Vec<Box<dyn BuiltinTool>>,
+}
+
+impl ToolRegistry {
+    /// `search_knowledge` is only registered when the knowledge base is configured, the memory
+    /// tools when memory is enabled.
+    pub fn new(
+        config: &ToolsConfig,
+        knowledge: &knowledge::KnowledgeConfig,
+        memory: &memory::MemoryConfig,
+    ) -> Self {
+        let sandbox = Sandbox::new(config.allowed_dirs.iter().chain(&knowledge.folders));
+        let mut tools: Vec<Box<dyn BuiltinTool>> = vec![
+            Box::new(ReadFile(sandbox.clone())),
+            Box::new(ListDirectory(sandbox)),
+            Box::new(SearchChats),
+            Box::new(CurrentTime),
+            Box::new(Calculator),
+        ];
+        if !knowledge.folders.is_empty() {
+            tools.push(Box::new(SearchKnowledge));
+        }
+        if memory.enabled {
+            tools.push(Box::new(Remember));
+            tools.push(Box::new(Recall));
+            tools.push(Box::new(Forget));
+        }
+        ToolRegistry { tools }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.tools.iter().map(|t| t.name().to_string()).collect()
+    }
+
+    pub fn description(&self, name: &str) -> Option<String> {
+        self.find(name).map(|t| t.description())
+    }
+
+    /// Runs a tool. Errors are meant for the model, which can try again differently.
+    pub fn call(&self, name: &str, arguments: &Value, context: &ToolContext) -> Result<String, String> {
+        let tool = self.find(name).ok_or_else(|| format!("Unknown built-in tool '{}'", name))?;
+        tool.call(arguments, context)
+    }
+
+    fn find(&self, name: &str) -> Option<&dyn BuiltinTool> {
+        self.tools.iter().find(|t| t.name() == name).map(|t| t.as_ref())
+    }
+}
+
+fn str_arg<'a>(arguments: &'a Value, name: &str) -> Result<&'a str, String> {
+    arguments
+        .get(name)
+        .and_then(Value::as_str)
+        .filter(|v| !v.trim().is_empty())
+        .ok_or_else(|| format!("Missing '{}' argument", name))
+}
+
+fn usize_arg(arguments: &Value, name: &str) -> Option<usize> {
+    arguments.get(name).and_then(Value::as_u64).map(|v| v as usize)
+}
+
+// --- Files ---
+
+/// The folders file tools may read, resolved so that `..` and symlinks cannot lead outside them.
+#[derive(Clone)]
+struct Sandbox {
+    roots: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    fn new<'a>(dirs: impl Iterator<Item = &'a PathBuf>) -> Self {
+        let roots = dirs
+            .filter_map(|dir| match dir.canonicalize() {
+                Ok(root) => Some(root),
+                Err(e) => {
+                    warn!(dir = %dir.display(), "Ignoring allowed folder: {}", e);
+                    None
+                }
+            })
+            .collect();
+        Sandbox { roots }
+    }
+
+    fn describe(&self) -> String {
+        if self.roots.is_empty() {
+            return "No folders are allowed yet: the user can add them to tools.allowed_dirs in settings.json.".to_string();
+        }
+        let roots: Vec<String> = self.roots.iter().map(|r| r.display().to_string()).collect();
+        format!("Only paths inside these folders are allowed: {}.", roots.join(", "))
+    }
+
+    /// Resolves `path`, which is absolute or relative to the first allowed folder, and checks
+    /// that it lies inside an allowed folder.
+    fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let first = self.roots.first().ok_or_else(|| self.describe())?;
+        let resolved = first
+            .join(path)
+            .canonicalize()
+            .map_err(|e| format!("Cannot access '{}': {}", path, e))?;
+        if self.roots.iter().any(|root| resolved.starts_with(root)) {
+            Ok(resolved)
+        } else {
+            Err(format!("Access to '{}' is not allowed. {}", path, self.describe()))
+        }
+    }
+}
+
+struct ReadFile(Sandbox);
+
+impl BuiltinTool for ReadFile {
+    fn name(&self) -> &'static str {
+        "read_file"
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Reads a text file. Arguments: {{\"path\": string, \"start_line\": number (optional), \"end_line\": number (optional)}}. {}",
+            self.0.describe()
+        )
+    }
+
+    fn call(&self, arguments: &Value, _context: &ToolContext) -> Result<String, String> {
+        let path = self.0.resolve(str_arg(arguments, "path")?)?;
+        let start = usize_arg(arguments, "start_line").unwrap_or(1).max(1);
+        let end = usize_arg(arguments, "end_line").unwrap_or(usize::MAX);
+        read_lines(&path, start, end)
+    }
+}
+
+/// Lines `start` to `end` of a file, 1-based and inclusive, up to `MAX_READ_BYTES` in all. The
+/// file is read line by line, so only what is returned is held in memory; a single line longer
+/// than that is cut.
+fn read_lines(path: &std::path::Path, start: usize, end: usize) -> Result<String, String> {
+    let read_error = |e: std::io::Error| format!("Failed to read {}: {}", path.display(), e);
+    let file = std::fs::File::open(path).map_err(read_error)?;
+    let metadata = file.metadata().map_err(read_error)?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", path.display()));
+    }
+    let size = metadata.len();
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let mut result = String::new();
+    let mut number = 0;
+    while let Some(cut) = read_line_capped(&mut reader, &mut line, MAX_READ_BYTES).map_err(read_error)? {
+        number += 1;
+        if number < start {
+            continue;
+        }
+        if number > end {
+            break;
+        }
+        let text = String::from_utf8_lossy(&line);
+        if !result.is_empty() && result.len() + text.len() > MAX_READ_BYTES {
+            result.push_str(&format!(
+                "[Truncated at line {} of a {}-byte file; ask for a line range to read further]\n",
+                number, size
+            ));
+            break;
+        }
+        result.push_str(&text);
+        if cut {
+            result.push_str(&format!(" [Line {} truncated]", number));
+        }
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Reads the next line without its line break into `line`, keeping at most `max` bytes and
+/// skipping the rest. Returns `None` at the end of the file, otherwise whether the line was cut.
+fn read_line_capped(reader: &mut impl BufRead, line: &mut Vec<u8>, max: usize) -> std::io::Result<Option<bool>> {
+    line.clear();
+    let mut read_any = false;
+    let mut cut = false;
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            break;
+        }
+        read_any = true;
+        let newline = available.iter().position(|&b| b == b'\n');
+        let content = &available[..newline.unwrap_or(available.len())];
+        let room = max.saturating_sub(line.len());
+        cut |= content.len() > room;
+        line.extend_from_slice(&content[..content.len().min(room)]);
+        let consumed = newline.map_or(available.len(), |i| i + 1);
+        reader.consume(consumed);
+        if newline.is_some() {
+            break;
+        }
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(read_any.then_some(cut))
+}
+
+struct ListDirectory(Sandbox);
+
+impl BuiltinTool for ListDirectory {
+    fn name(&self) -> &'static str {
+        "list_directory"
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "Lists the files and folders in a folder, with file sizes in bytes. Arguments: {{\"path\": string}}. {}",
+            self.0.describe()
+        )
+    }
+
+    fn call(&self, arguments: &Value, _context: &ToolContext) -> Result<String, String> {
+        let path = self.0.resolve(str_arg(arguments, "path")?)?;
+        let mut entries: Vec<String> = std::fs::read_dir(&path)
+            .map_err(|e| format!("Failed to list {}: {}", path.display(), e))?
+            .flatten()
+            .map(|entry| {
+                let name = entry.file_name().to_string_lossy().to_string();
+                match entry.metadata() {
+                    Ok(metadata) if metadata.is_dir() => format!("{}/", name),
+                    Ok(metadata) => format!("{} ({})", name, metadata.len()),
+                    Err(_) => name,
+                }
+            })
+            .collect();
+        entries.sort();
+        let total = entries.len();
+        entries.truncate(MAX_DIR_ENTRIES);
+        let mut result = format!("{} ({} entries)\n{}", path.display(), total, entries.join("\n"));
+        if total > MAX_DIR_ENTRIES {
+            result.push_str(&format!("\n[{} more entries not shown]", total - MAX_DIR_ENTRIES));
+        }
+        Ok(result)
+    }
+}
+
+// --- Search ---
+
+struct SearchChats;
+
+impl BuiltinTool for SearchChats {
+    fn name(&self) -> &'static str {
+        "search_chats"
+    }
+
+    fn description(&self) -> String {
+        "Searches the user's past chats. Arguments: {\"query\": string, \"limit\": number (optional, default 10)}. \
+The query takes the same filters as the search box, e.g. role:assistant, tag:<tag>, after:2024-01-31 or after:7d."
+            .to_string()
+    }
+
+    fn call(&self, arguments: &Value, context: &ToolContext) -> Result<String, String> {
+        let query = str_arg(arguments, "query")?;
+        let limit = usize_arg(arguments, "limit").map_or(DEFAULT_CHAT_RESULTS, |l| l.clamp(1, MAX_CHAT_RESULTS));
+        let hits = context.state.searcher.search(query)?;
+        if hits.is_empty() {
+            return Ok(format!("No past chats match '{}'.", query));
+        }
+        let mut result = String::new();
+        for hit in hits.into_iter().take(limit) {
+            let title = context.state.store.title(&hit.session_id)?.unwrap_or_default();
+            result.push_str(&format!("- [{}] {}: {}\n", format_utc(hit.timestamp), title, hit.snippet));
+        }
+        Ok(result)
+    }
+}
+
+struct SearchKnowledge;
+
+impl BuiltinTool for SearchKnowledge {
+    fn name(&self) -> &'static str {
+        knowledge::TOOL_NAME
+    }
+
+    fn description(&self) -> String {
+        knowledge::TOOL_DESCRIPTION.to_string()
+    }
+
+    fn call(&self, arguments: &Value, context: &ToolContext) -> Result<String, String> {
+        let knowledge = context.state.knowledge.as_ref().ok_or("The knowledge base is unavailable.")?;
+        knowledge.call_tool(arguments)
+    }
+}
+
+// --- Memory ---
+
+fn memory<'a>(context: &ToolContext<'a>) -> Result<&'a memory::Memory, String> {
+    context.state.memory.as_ref().ok_or_else(|| "Memory is unavailable.".to_string())
+}
+
+struct Remember;
+
+impl BuiltinTool for Remember {
+    fn name(&self) -> &'static str {
+        "remember"
+    }
+
+    fn description(&self) -> String {
+        "Saves a fact to your long-term memory, which you keep across all chats with the user. Use it for \
+lasting facts and preferences the user tells you, not for things only this chat needs. \
+Arguments: {\"key\": string, \"content\": string}. The key is a short name such as \"preferred-language\"; \
+remembering under an existing key replaces it."
+            .to_string()
+    }
+
+    fn call(&self, arguments: &Value, context: &ToolContext) -> Result<String, String> {
+        let key = str_arg(arguments, "key")?;
+        let content = str_arg(arguments, "content")?;
+        let entry = memory(context)?.remember(key, content, Some(context.session_id), crate::now_ts())?;
+        Ok(format!("Remembered '{}'.", entry.key))
+    }
+}
+
+struct Recall;
+
+impl BuiltinTool for Recall {
+    fn name(&self) -> &'static str {
+        "recall"
+    }
+
+    fn description(&self) -> String {
+        "Searches your long-term memory. Arguments: {\"query\": string, \"limit\": number (optional, default 5)}."
+            .to_string()
+    }
+
+    fn call(&self, arguments: &Value, context: &ToolContext) -> Result<String, String> {
+        let query = str_arg(arguments, "query")?;
+        let limit = usize_arg(arguments, "limit").map_or(DEFAULT_RECALL_RESULTS, |l| l.clamp(1, MAX_RECALL_RESULTS));
+        let entries = memory(context)?.recall(query, limit)?;
+        if entries.is_empty() {
+            return Ok(format!("Nothing remembered matches '{}'.", query));
+        }
+        let mut result = String::new();
+        for entry in entries {
+            result.push_str(&format!("- {} (updated {}): {}\n", entry.key, format_utc(entry.updated_at), entry.content));
+        }
+        Ok(result)
+    }
+}
+
+struct Forget;
+
+impl BuiltinTool for Forget {
+    fn name(&self) -> &'static str {
+        "forget"
+    }
+
+    fn description(&self) -> String {
+        "Deletes a fact from your long-term memory, e.g. when the user says it is wrong or asks you to forget it. \
+Arguments: {\"key\": string}."
+            .to_string()
+    }
+
+    fn call(&self, arguments: &Value, context: &ToolContext) -> Result<String, String> {
+        let key = str_arg(arguments, "key")?;
+        match memory(context)?.forget(key)? {
+            Some(entry) => Ok(format!("Forgot '{}'.", entry.key)),
+            None => Ok(format!("Nothing is remembered under '{}'.", key)),
+        }
+    }
+}
+
+// --- Time ---
+
+struct CurrentTime;
+
+impl BuiltinTool for CurrentTime {
+    fn name(&self) -> &'static str {
+        "current_time"
+    }
+
+    fn description(&self) -> String {
+        "Returns the current date and time in UTC. Takes no arguments.".to_string()
+    }
+
+    fn call(&self, _arguments: &Value, _context: &ToolContext) -> Result<String, String> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_secs();
+        let (year, month, day) = civil_from_days((now / 86_400) as i64);
+        let weekday = ["Thursday", "Friday", "Saturday", "Sunday", "Monday", "Tuesday", "Wednesday"][(now / 86_400 % 7) as usize];
+        Ok(format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z ({}, unix time {})",
+            year,
+            month,
+            day,
+            now % 86_400 / 3_600,
+            now % 3_600 / 60,
+            now % 60,
+            weekday,
+            now
+        ))
+    }
+}
+
+// --- Calculator ---
+
+struct Calculator;
+
+impl BuiltinTool for Calculator {
+    fn name(&self) -> &'static str {
+        "calculator"
+    }
+
+    fn description(&self) -> String {
+        "Evaluates an arithmetic expression exactly as written. Arguments: {\"expression\": string}. Supports \
++ - * / % ^, parentheses, pi, e and the functions sqrt, abs, ln, log10, exp, sin, cos, tan, floor, ceil and round."
+            .to_string()
+    }
+
+    fn call(&self, arguments: &Value, _context: &ToolContext) -> Result<String, String> {
+        let expression = str_arg(arguments, "expression")?;
+        let value = evaluate(expression)?;
+        Ok(format!("{} = {}", expression.trim(), value))
+    }
+}
+
+/// Evaluates an arithmetic expression. `^` binds tighter than unary minus, so `-2^2` is -4.
+fn evaluate(expression: &str) -> Result<f64, String> {
+    let mut parser = Parser {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+    };
+    let value = parser.sum()?;
+    if let Some(c) = parser.peek() {
+        return Err(format!("Unexpected '{}' at position {}", c, parser.pos + 1));
+    }
+    if !value.is_finite() {
+        return Err("The result is not a finite number".to_string());
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn sum(&mut self) -> Result<f64, String> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat('+') {
+                value += self.product()?;
+            } else if self.eat('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                value /= self.unary()?;
+            } else if self.eat('%') {
+                value %= self.unary()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.eat('-') {
+            return Ok(-self.unary()?);
+        }
+        if self.eat('+') {
+            return self.unary();
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            // Right-associative: 2^3^2 is 2^9.
+            return Ok(base.powf(self.unary()?));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<f64, String> {
+        if self.eat('(') {
+            let value = self.sum()?;
+            if !self.eat(')') {
+                return Err("Missing ')'".to_string());
+            }
+            return Ok(value);
+        }
+        let start = self.pos;
+        if self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+            while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                self.pos += 1;
+            }
+            // Exponent, as in 1.5e3.
+            if self.peek() == Some('e') && self.chars.get(self.pos + 1).is_some_and(|c| c.is_ascii_digit() || *c == '-') {
+                self.pos += 2;
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+            }
+            let number: String = self.chars[start..self.pos].iter().collect();
+            return number.parse().map_err(|_| format!("Invalid number '{}'", number));
+        }
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        match name.as_str() {
+            "" => Err(match self.peek() {
+                Some(c) => format!("Unexpected '{}' at position {}", c, self.pos + 1),
+                None => "Unexpected end of expression".to_string(),
+            }),
+            "pi" => Ok(std::f64::consts::PI),
+            "e" => Ok(std::f64::consts::E),
+            _ => {
+                let function: fn(f64) -> f64 = match name.as_str() {
+                    "sqrt" => f64::sqrt,
+                    "abs" => f64::abs,
+                    "ln" => f64::ln,
+                    "log10" | "log" => f64::log10,
+                    "exp" => f64::exp,
+                    "sin" => f64::sin,
+                    "cos" => f64::cos,
+                    "tan" => f64::tan,
+                    "floor" => f64::floor,
+                    "ceil" => f64::ceil,
+                    "round" => f64::round,
+                    _ => return Err(format!("Unknown function or constant '{}'", name)),
+                };
+                if !self.eat('(') {
+                    return Err(format!("Expected '(' after '{}'", name));
+                }
+                let argument = self.sum()?;
+                if !self.eat(')') {
+                    return Err("Missing ')'".to_string());
+                }
+                Ok(function(argument))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calculator_follows_precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(evaluate("(1 + 2) * 3"), Ok(9.0));
+        assert_eq!(evaluate("2^3^2"), Ok(512.0));
+        assert_eq!(evaluate("-2^2"), Ok(-4.0));
+        assert_eq!(evaluate("10 % 4 - 1.5e1"), Ok(-13.0));
+        assert_eq!(evaluate("sqrt(16) + abs(-2)"), Ok(6.0));
+        assert!((evaluate("cos(pi)").unwrap() + 1.0).abs() < 1e-12);
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("2 +").is_err());
+        assert!(evaluate("(1").is_err());
+        assert!(evaluate("foo(1)").is_err());
+    }
+
+    #[test]
+    fn reads_line_ranges_without_loading_the_whole_file() {
+        let dir = std::env::temp_dir().join(format!("read-file-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.txt");
+        std::fs::write(&path, format!("one\r\ntwo\n{}\nfour", "x".repeat(MAX_READ_BYTES + 10))).unwrap();
+
+        assert_eq!(read_lines(&path, 1, 2).unwrap(), "one\ntwo\n");
+        let long = read_lines(&path, 3, 3).unwrap();
+        assert!(long.starts_with(&"x".repeat(MAX_READ_BYTES)));
+        assert!(long.ends_with("[Line 3 truncated]\n"));
+        assert!(read_lines(&path, 2, 4).unwrap().contains("[Truncated at line 3 of"));
+        assert_eq!(read_lines(&path, 4, usize::MAX).unwrap(), "four\n");
+        assert!(read_lines(&dir, 1, 1).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn sandbox_keeps_paths_inside_allowed_folders() {
+        let dir = std::env::temp_dir().join(format!("sandbox-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("allowed")).unwrap();
+        std::fs::write(dir.join("allowed").join("a.txt"), "a").unwrap();
+        std::fs::write(dir.join("secret.txt"), "s").unwrap();
+        let sandbox = Sandbox::new([dir.join("allowed")].iter());
+
+        assert!(sandbox.resolve("a.txt").is_ok());
+        assert!(sandbox.resolve(&dir.join("allowed").join("a.txt").to_string_lossy()).is_ok());
+        assert!(sandbox.resolve("../secret.txt").is_err());
+        assert!(sandbox.resolve(&dir.join("secret.txt").to_string_lossy()).is_err());
+        assert!(Sandbox::new(std::iter::empty()).resolve("a.txt").is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,4 +1,4 @@
-use crate::ChatMessage;
+use crate::history::{ChatMessage, Role};
 
 const MAX_MESSAGES: usize = 40; // Adjust this based on your needs
 
@@ -9,7 +9,7 @@ pub fn select_context_messages(messages: &[ChatMessage], max_messages: Option<us
     
     // Always include system messages as they set up important context
     let system_messages: Vec<_> = messages.iter()
-        .filter(|m| m.role == "system")
+        .filter(|m| m.role == Role::System)
         .cloned()
         .collect();
     
@@ -18,7 +18,7 @@ pub fn select_context_messages(messages: &[ChatMessage], max_messages: Option<us
     // Get the most recent N messages that aren't system messages
     let recent_messages: Vec<_> = messages.iter()
         .rev() // Reverse to get most recent first
-        .filter(|m| m.role != "system")
+        .filter(|m| m.role != Role::System)
         .take(window_size)
         .cloned()
         .collect();
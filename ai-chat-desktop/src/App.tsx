@@ -2,33 +2,133 @@ import { useState, useEffect, useRef } from "react";
 import { invoke } from "@tauri-apps/api/core";
 import { listen } from "@tauri-apps/api/event";
 import SmartContentRenderer from "./components/SmartContentRenderer";
-import Sidebar from "./components/Sidebar";
+import Sidebar, { ExportFormat, SearchHit } from "./components/Sidebar";
 import "./App.css"; // Keep this import for now, even if empty
 import McpToolsMenu from "./components/McpToolsMenu";
+import ToolStep from "./components/ToolStep";
+import PlanChecklist, { Plan, StepInput } from "./components/PlanChecklist";
 
 // --- TypeScript Interfaces ---
 
+type Role = "system" | "user" | "assistant" | "tool";
+
+interface ToolCallInfo {
+  tool_name: string;
+  arguments: unknown;
+  status?: "success" | "error";
+  duration_ms?: number;
+}
+
+interface GenerationInfo {
+  profile: string;
+  model: string;
+  temperature?: number;
+}
+
 interface ChatMessage {
-  role: string;
+  // Missing on the optimistic copy of a prompt until the session is reloaded.
+  id?: string;
+  parent_id?: string;
+  role: Role;
   content: string;
   timestamp: number;
+  tool_call?: ToolCallInfo;
+  generation?: GenerationInfo;
+  // The plan a reply was worked out with, in plan mode.
+  plan?: Plan;
+  // Position among the alternatives at this point of the conversation.
+  sibling_anchor?: string;
+  sibling_index?: number;
+  sibling_count?: number;
 }
 
-interface ChatSession {
+// A session as returned for the chat view: only the messages on the active branch.
+interface SessionView {
   id: string;
   title: string;
   messages: ChatMessage[];
   created_at: number;
   updated_at: number;
+  tags?: string[];
+  active_leaf: string | null;
+}
+
+interface MessagePage {
+  messages: ChatMessage[];
+  start: number;
+  total: number;
+}
+
+interface SessionSummary {
+  id: string;
+  title: string;
+  created_at: number;
+  updated_at: number;
+  message_count: number;
+  last_snippet: string | null;
+  tags: string[];
+  folder: string | null;
+  pinned: boolean;
+  deleted_at: number | null;
+}
+
+interface SessionPage {
+  items: SessionSummary[];
+  nextCursor: string | null;
+}
+
+const SESSION_PAGE_SIZE = 50;
+const MAX_PINNED_SESSIONS = 500;
+
+interface AgentError {
+  kind: "rateLimit" | "network" | "auth" | "invalidRequest" | "contextOverflow" | "tool" | "internal";
+  message: string;
+  status?: number;
+  retryAfterMs?: number;
 }
 
 interface AgentStatus {
-  type: "thinking" | "using_tool";
+  type: "thinking" | "using_tool" | "retrying";
   data?: {
-    tool_name: string;
+    tool_name?: string;
+    attempt?: number;
+    delay_ms?: number;
+    error?: AgentError;
   };
 }
 
+const ERROR_KIND_LABELS: Record<AgentError["kind"], string> = {
+  rateLimit: "Rate limited",
+  network: "Network error",
+  auth: "Authentication error",
+  invalidRequest: "Invalid request",
+  contextOverflow: "Conversation too long for the model",
+  tool: "Tool error",
+  internal: "Error",
+};
+
+const formatError = (error: unknown): string => {
+  if (error && typeof error === "object" && "kind" in error && "message" in error) {
+    const e = error as AgentError;
+    return `${ERROR_KIND_LABELS[e.kind] ?? "Error"}: ${e.message}`;
+  }
+  return String(error);
+};
+
+interface SessionLoadIssue {
+  file: string;
+  error: string;
+  recovered: boolean;
+}
+
+interface ImportResult {
+  title: string;
+  status: "imported" | "duplicate" | "failed";
+  sessionId: string | null;
+  messageCount: number;
+  error: string | null;
+}
+
 interface AgentEvent {
   status: AgentStatus | null;
 }
@@ -39,13 +139,32 @@ function App() {
   const [messages, setMessages] = useState<ChatMessage[]>([]);
   const [inputValue, setInputValue] = useState("");
   const [isLoading, setIsLoading] = useState(false);
-  const [sessions, setSessions] = useState<ChatSession[]>([]);
+  const [sessions, setSessions] = useState<SessionSummary[]>([]);
+  const [sessionsCursor, setSessionsCursor] = useState<string | null>(null);
   const [currentSessionId, setCurrentSessionId] = useState<string | null>(null);
   const [sidebarCollapsed, setSidebarCollapsed] = useState(false);
   const [agentStatus, setAgentStatus] = useState<AgentStatus | null>(null);
   const [activeTools, setActiveTools] = useState<string[]>([]);
+  // Plan mode: the agent writes a plan of steps first and works through them.
+  const [planMode, setPlanMode] = useState(false);
+  // The plan of the task running in the current chat.
+  const [livePlan, setLivePlan] = useState<Plan | null>(null);
+  const [folders, setFolders] = useState<string[]>([]);
+  const [folderFilter, setFolderFilter] = useState<string | null>(null);
+  const [showTrash, setShowTrash] = useState(false);
+  const [editingMessageId, setEditingMessageId] = useState<string | null>(null);
+  const [editValue, setEditValue] = useState("");
+  // Message opened from a search hit, scrolled into view and briefly highlighted.
+  const [scrollTargetId, setScrollTargetId] = useState<string | null>(null);
 
   const messagesEndRef = useRef<HTMLDivElement>(null);
+  // Read by async handlers that may outlive the render they were created in.
+  const currentSessionIdRef = useRef<string | null>(null);
+  currentSessionIdRef.current = currentSessionId;
+  const folderFilterRef = useRef<string | null>(null);
+  folderFilterRef.current = folderFilter;
+  const showTrashRef = useRef(false);
+  showTrashRef.current = showTrash;
 
   useEffect(() => {
     if (messagesEndRef.current) {
@@ -53,6 +172,14 @@ function App() {
     }
   }, [messages]);
 
+  // Runs after the scroll-to-bottom above, so a search hit wins.
+  useEffect(() => {
+    if (!scrollTargetId) return;
+    document.getElementById(`message-${scrollTargetId}`)?.scrollIntoView({ block: "center" });
+    const timeout = setTimeout(() => setScrollTargetId(null), 2000);
+    return () => clearTimeout(timeout);
+  }, [scrollTargetId, messages]);
+
   const safeInvoke = async (cmd: string, args?: any) => {
     try {
       return await invoke(cmd, args);
@@ -64,31 +191,82 @@ function App() {
 
   // --- Session Management ---
 
-  const loadSessions = async () => {
+  // Reloads the pinned sessions and the first page of the others; further pages are fetched on
+  // demand. Both are restricted to the selected folder, if any.
+  const loadSessions = async (): Promise<SessionSummary[]> => {
     try {
-      const list = await safeInvoke("get_all_sessions");
-      setSessions(list as ChatSession[]);
+      if (showTrashRef.current) {
+        const trash = (await safeInvoke("list_sessions", { limit: SESSION_PAGE_SIZE, trashed: true })) as SessionPage;
+        setSessions(trash.items);
+        setSessionsCursor(trash.nextCursor);
+        return trash.items;
+      }
+      const folder = folderFilterRef.current;
+      const pinned = (await safeInvoke("list_sessions", {
+        limit: MAX_PINNED_SESSIONS,
+        pinned: true,
+        folder,
+      })) as SessionPage;
+      const page = (await safeInvoke("list_sessions", {
+        limit: SESSION_PAGE_SIZE,
+        pinned: false,
+        folder,
+      })) as SessionPage;
+      const items = [...pinned.items, ...page.items];
+      setSessions(items);
+      setSessionsCursor(page.nextCursor);
+      setFolders((await safeInvoke("list_folders")) as string[]);
+      return items;
     } catch (error) {
       console.error("Error loading sessions:", error);
       setSessions([]);
+      setSessionsCursor(null);
+      return [];
+    }
+  };
+
+  const loadMoreSessions = async () => {
+    if (!sessionsCursor) return;
+    try {
+      const page = (await safeInvoke("list_sessions", {
+        cursor: sessionsCursor,
+        limit: SESSION_PAGE_SIZE,
+        ...(showTrashRef.current ? { trashed: true } : { pinned: false, folder: folderFilterRef.current }),
+      })) as SessionPage;
+      setSessions((prev) => [...prev, ...page.items]);
+      setSessionsCursor(page.nextCursor);
+    } catch (error) {
+      console.error("Error loading more sessions:", error);
     }
   };
 
   useEffect(() => {
     let isInitializingTools = false;
+    const reportLoadIssues = async () => {
+      try {
+        const issues = (await safeInvoke("get_session_load_issues")) as SessionLoadIssue[];
+        if (issues.length === 0) return;
+        const lines = issues.map((i) =>
+          `• ${i.file}: ${i.recovered ? "restored from backup" : `unreadable (${i.error})`}`
+        );
+        alert(`Some chat files could not be loaded as-is:\n${lines.join("\n")}`);
+      } catch (error) {
+        console.warn("Could not fetch session load issues:", error);
+      }
+    };
+
     const initializeApp = async () => {
       try {
-        const loadedSessions = (await safeInvoke("get_all_sessions")) as ChatSession[];
-        setSessions(loadedSessions);
+        const loadedSessions = await loadSessions();
 
         if (loadedSessions.length > 0) {
           await handleSelectSession(loadedSessions[0].id);
         } else {
           // If no sessions exist, create one directly here
-          const newSession = (await safeInvoke("finalize_and_new_chat")) as ChatSession;
+          const newSession = (await safeInvoke("finalize_and_new_chat")) as SessionView;
           setCurrentSessionId(newSession.id);
           setMessages(newSession.messages || []);
-          setSessions([newSession]); // Initialize sessions with just this new one
+          await loadSessions();
         }
       } catch (error) {
         console.error("Error initializing app:", error);
@@ -114,6 +292,7 @@ function App() {
     };
 
     initializeApp();
+    reportLoadIssues();
 
     // Listen for MCP server status changes to initialize tools
     const unlistenMcpStatus = listen('mcp_server_status_changed', async () => {
@@ -135,8 +314,27 @@ function App() {
       setAgentStatus(event.payload.status);
     });
 
+    // Tool calls are streamed in as they happen; the final reply is appended when run_agent_task returns.
+    const unlistenSteps = listen<ChatMessage>("agent_step", (event) => {
+      setMessages((prev) => [...prev, event.payload]);
+    });
+
+    const unlistenPlan = listen<{ sessionId: string; plan: Plan }>("agent_plan", (event) => {
+      if (event.payload.sessionId === currentSessionIdRef.current) {
+        setLivePlan(event.payload.plan);
+      }
+    });
+
+    // Titles are generated in the background after the first exchange.
+    const unlistenTitles = listen<{ id: string; title: string }>("session_title_changed", (event) => {
+      setSessions((prev) => prev.map((s) => (s.id === event.payload.id ? { ...s, title: event.payload.title } : s)));
+    });
+
     return () => {
       unlisten.then((f) => f());
+      unlistenTitles.then((f) => f());
+      unlistenSteps.then((f) => f());
+      unlistenPlan.then((f) => f());
       unlistenMcpStatus.then((f) => f());
       clearTimeout(initialToolInitTimeout);
     };
@@ -147,7 +345,7 @@ function App() {
       // Check if the current session is empty and titled "New Chat"
       if (currentSessionId) {
           const currentSession = sessions.find(s => s.id === currentSessionId);
-          if (currentSession && currentSession.messages.length === 0 && currentSession.title === "New Chat") {
+          if (currentSession && currentSession.message_count === 0 && currentSession.title === "New Chat") {
               // If it's an empty "New Chat", just select it again (no-op effectively)
               await handleSelectSession(currentSessionId);
               return;
@@ -155,7 +353,7 @@ function App() {
       }
 
       // Otherwise, create a new session
-      const newSession = (await safeInvoke("finalize_and_new_chat")) as ChatSession;
+      const newSession = (await safeInvoke("finalize_and_new_chat")) as SessionView;
       // After backend has finalized old session and created new one, reload all sessions
       await loadSessions();
       setCurrentSessionId(newSession.id);
@@ -171,7 +369,7 @@ function App() {
     try {
       const new_session = await safeInvoke("select_session", { idToSelect: id });
       await loadSessions();
-      const s = new_session as ChatSession;
+      const s = new_session as SessionView;
       setCurrentSessionId(s.id);
       setMessages(s.messages || []);
     } catch (error) {
@@ -180,6 +378,26 @@ function App() {
     }
   };
 
+  const handleOpenSearchHit = async (hit: SearchHit) => {
+    try {
+      let view = (await safeInvoke("select_session", { idToSelect: hit.session_id })) as SessionView;
+      // The match may be on another branch of the chat.
+      if (hit.message_id && !view.messages.some((m) => m.id === hit.message_id)) {
+        view = (await safeInvoke("switch_branch", {
+          sessionId: hit.session_id,
+          messageId: hit.message_id,
+        })) as SessionView;
+      }
+      await loadSessions();
+      setCurrentSessionId(view.id);
+      setMessages(view.messages || []);
+      setScrollTargetId(hit.message_id);
+    } catch (error) {
+      console.error("Failed to open search result:", error);
+      alert(`Error opening search result: ${error}`);
+    }
+  };
+
   const handleRenameChat = async (id: string, newTitle: string) => {
     try {
       await safeInvoke("rename_session", { id, newTitle });
@@ -209,6 +427,177 @@ function App() {
     }
   };
 
+  // Reloads the active branch, e.g. to pick up message ids after a reply.
+  const reloadMessages = async (sessionId: string) => {
+    try {
+      const page = (await safeInvoke("get_session_messages", { id: sessionId })) as MessagePage;
+      setMessages(page.messages);
+    } catch (error) {
+      console.error("Failed to reload messages:", error);
+    }
+  };
+
+  // --- Branching ---
+
+  const handleSwitchSibling = async (messageId: string, step: number) => {
+    if (!currentSessionId || isLoading) return;
+    try {
+      const view = (await safeInvoke("switch_sibling", {
+        sessionId: currentSessionId,
+        messageId,
+        step,
+      })) as SessionView;
+      setMessages(view.messages);
+    } catch (error) {
+      console.error("Failed to switch branch:", error);
+      alert(`Error switching branch: ${error}`);
+    }
+  };
+
+  // The new answer is added next to the old one; the arrows switch between them.
+  const handleRegenerate = async (messageId: string) => {
+    if (!currentSessionId || isLoading) return;
+    setIsLoading(true);
+    setAgentStatus(null);
+    try {
+      await safeInvoke("regenerate_response", {
+        sessionId: currentSessionId,
+        messageId,
+        activeTools: activeTools,
+        plan: planMode,
+      });
+    } catch (error) {
+      alert(`Error regenerating response: ${formatError(error)}`);
+    } finally {
+      await reloadMessages(currentSessionId);
+      setIsLoading(false);
+      setAgentStatus(null);
+      setLivePlan(null);
+    }
+  };
+
+  // Editing a prompt keeps the original: the new text starts a sibling branch from the same point.
+  const handleSubmitEdit = async (message: ChatMessage) => {
+    const text = editValue.trim();
+    setEditingMessageId(null);
+    if (!currentSessionId || !text || isLoading) return;
+    try {
+      const view = (await safeInvoke("fork_from_message", {
+        sessionId: currentSessionId,
+        messageId: message.parent_id ?? null,
+        newSession: false,
+      })) as SessionView;
+      setMessages(view.messages);
+      await sendPrompt(text);
+    } catch (error) {
+      console.error("Failed to edit message:", error);
+      alert(`Error editing message: ${error}`);
+    }
+  };
+
+  const handleForkToNewChat = async (messageId: string) => {
+    if (!currentSessionId || isLoading) return;
+    try {
+      const view = (await safeInvoke("fork_from_message", {
+        sessionId: currentSessionId,
+        messageId,
+        newSession: true,
+      })) as SessionView;
+      setCurrentSessionId(view.id);
+      setMessages(view.messages);
+      await loadSessions();
+    } catch (error) {
+      console.error("Failed to fork chat:", error);
+      alert(`Error forking chat: ${error}`);
+    }
+  };
+
+  // --- Organizing ---
+
+  const handleSelectFolder = async (folder: string | null) => {
+    folderFilterRef.current = folder;
+    setFolderFilter(folder);
+    await loadSessions();
+  };
+
+  const handleToggleTrash = async () => {
+    showTrashRef.current = !showTrash;
+    setShowTrash(!showTrash);
+    await loadSessions();
+  };
+
+  const handleRestoreChat = async (id: string) => {
+    try {
+      await safeInvoke("restore_session", { id });
+      await loadSessions();
+    } catch (error) {
+      console.error("Failed to restore session:", error);
+      alert(`Error restoring chat: ${error}`);
+    }
+  };
+
+  // With no ids, empties the whole trash.
+  const handleEmptyTrash = async (ids?: string[]) => {
+    const what = ids ? "this chat" : "all chats in the trash";
+    if (!confirm(`Permanently delete ${what}? This cannot be undone.`)) return;
+    try {
+      await safeInvoke("empty_trash", { ids: ids ?? null });
+      await loadSessions();
+    } catch (error) {
+      console.error("Failed to empty trash:", error);
+      alert(`Error emptying trash: ${error}`);
+    }
+  };
+
+  const updateSessionMeta = async (cmd: string, args: Record<string, unknown>) => {
+    try {
+      await safeInvoke(cmd, args);
+      await loadSessions();
+    } catch (error) {
+      console.error(`Failed to update session (${cmd}):`, error);
+      alert(`Error updating chat: ${error}`);
+    }
+  };
+
+  const handleTogglePin = (id: string, pinned: boolean) => updateSessionMeta("set_session_pinned", { id, pinned });
+  const handleSetTags = (id: string, tags: string[]) => updateSessionMeta("set_session_tags", { id, tags });
+  const handleSetFolder = (id: string, folder: string | null) => updateSessionMeta("set_session_folder", { id, folder });
+
+  // Each command asks for the destination itself; nothing is written if the dialog is cancelled.
+  const handleExportChats = async (ids: string[], format: ExportFormat) => {
+    if (ids.length === 0) return;
+    try {
+      if (ids.length === 1) {
+        await safeInvoke("export_session", { id: ids[0], format });
+      } else {
+        await safeInvoke("export_sessions", { ids, format });
+      }
+    } catch (error) {
+      console.error("Failed to export chats:", error);
+      alert(`Error exporting chats: ${error}`);
+    }
+  };
+
+  const handleImportChats = async () => {
+    try {
+      const results = (await safeInvoke("import_conversations")) as ImportResult[] | null;
+      if (!results) return; // Dialog cancelled
+      await loadSessions();
+      const count = (status: ImportResult["status"]) => results.filter((r) => r.status === status).length;
+      const failures = results
+        .filter((r) => r.status === "failed")
+        .map((r) => `• ${r.title}: ${r.error}`);
+      alert(
+        `Imported ${count("imported")} of ${results.length} conversations` +
+          (count("duplicate") > 0 ? `, ${count("duplicate")} already imported` : "") +
+          (failures.length > 0 ? `.\n\nFailed:\n${failures.join("\n")}` : ".")
+      );
+    } catch (error) {
+      console.error("Failed to import chats:", error);
+      alert(`Error importing chats: ${error}`);
+    }
+  };
+
   // --- Tool Management ---
   const handleToggleTool = (toolName: string) => {
     setActiveTools((prev) =>
@@ -232,6 +621,10 @@ function App() {
     }
 
     setInputValue("");
+    await sendPrompt(trimmedValue);
+  };
+
+  const sendPrompt = async (trimmedValue: string) => {
     setIsLoading(true);
     setAgentStatus(null); // Reset status on new message
 
@@ -244,6 +637,7 @@ function App() {
       const reply = await safeInvoke("run_agent_task", { 
         message: trimmedValue,
         activeTools: activeTools,
+        plan: planMode,
       });
       setMessages((prev) => [
         ...prev,
@@ -253,11 +647,33 @@ function App() {
     } catch (error) {
       setMessages((prev) => [
         ...prev,
-        { role: "assistant", content: `❌ Error: ${error}`, timestamp: Date.now() },
+        { role: "assistant", content: `❌ ${formatError(error)}`, timestamp: Date.now() },
       ]);
     } finally {
+      const sessionId = currentSessionIdRef.current;
+      if (sessionId) await reloadMessages(sessionId);
       setIsLoading(false);
       setAgentStatus(null); // Clear status when done
+      setLivePlan(null);
+    }
+  };
+
+  // Edits to the steps of the running plan that have not started yet.
+  // Refused if the agent changed the plan since; the current plan is then reloaded to edit again.
+  const handleEditPlan = async (steps: StepInput[]) => {
+    if (!currentSessionId || !livePlan) return;
+    const sessionId = currentSessionId;
+    try {
+      setLivePlan(
+        (await safeInvoke("update_plan", { sessionId, version: livePlan.version, steps })) as Plan
+      );
+    } catch (error) {
+      alert(`Error editing the plan: ${error}`);
+      try {
+        setLivePlan((await safeInvoke("get_plan", { sessionId })) as Plan | null);
+      } catch (reloadError) {
+        console.error("Failed to reload the plan:", reloadError);
+      }
     }
   };
 
@@ -282,6 +698,9 @@ function App() {
       statusText = "Thinking...";
     } else if (agentStatus.type === "using_tool") {
       statusText = `Using tool: ${agentStatus.data?.tool_name}...`;
+    } else if (agentStatus.type === "retrying") {
+      const seconds = Math.ceil((agentStatus.data?.delay_ms ?? 0) / 1000);
+      statusText = `${formatError(agentStatus.data?.error)} — retrying in ${seconds}s (attempt ${agentStatus.data?.attempt})...`;
     }
 
     return (
@@ -298,30 +717,122 @@ function App() {
         currentSessionId={currentSessionId}
         onConfigOpenAI={handleConfigOpenAI}
         onSelect={handleSelectSession}
+        onOpenSearchHit={handleOpenSearchHit}
         onNewChat={handleNewChat}
         collapsed={sidebarCollapsed}
         onToggle={() => setSidebarCollapsed((c) => !c)}
         onRenameChat={handleRenameChat}
         onDeleteChat={handleDeleteChat}
+        onExportChats={handleExportChats}
+        onImportChats={handleImportChats}
+        folders={folders}
+        selectedFolder={folderFilter}
+        onSelectFolder={handleSelectFolder}
+        onTogglePin={handleTogglePin}
+        onSetTags={handleSetTags}
+        onSetFolder={handleSetFolder}
+        showTrash={showTrash}
+        onToggleTrash={handleToggleTrash}
+        onRestoreChat={handleRestoreChat}
+        onEmptyTrash={handleEmptyTrash}
+        hasMore={sessionsCursor !== null}
+        onLoadMore={loadMoreSessions}
       />
       <div className="flex flex-col flex-1 overflow-x-hidden">
         <div className="flex-1 overflow-y-auto p-4" ref={messagesEndRef}>
-          {messages.map((message, idx) => (
+          {messages.map((message, idx) => {
+            if (message.tool_call) {
+              // A finished call is shown once, by its result.
+              if (message.role === "assistant" && messages[idx + 1]?.role === "tool") return null;
+              return <ToolStep key={idx} role={message.role} content={message.content} toolCall={message.tool_call} />;
+            }
+            const isUser = message.role === "user";
+            const hasSiblings = (message.sibling_count ?? 1) > 1;
+            return (
             <div
-              key={idx}
-              className={`p-3 rounded-lg mb-2 max-w-[85%] word-wrap break-words relative animate-messageSlideIn ${
-                message.role === "user"
-                  ? "bg-blue-600 ml-auto text-right shadow-lg shadow-blue-500/30"
-                  : "bg-gray-700 mr-auto border border-gray-600 backdrop-blur-md shadow-lg shadow-black/20"
+              key={message.id ?? idx}
+              id={message.id ? `message-${message.id}` : undefined}
+              className={`group mb-2 max-w-[85%] ${isUser ? "ml-auto" : "mr-auto"}`}
+            >
+            <div
+              className={`p-3 rounded-lg word-wrap break-words relative animate-messageSlideIn ${
+                message.id !== undefined && message.id === scrollTargetId ? "ring-2 ring-yellow-400 " : ""
+              }${
+                isUser
+                  ? "bg-blue-600 text-right shadow-lg shadow-blue-500/30"
+                  : "bg-gray-700 border border-gray-600 backdrop-blur-md shadow-lg shadow-black/20"
               }`}
             >
-              {message.role === "assistant" || message.role === "bot" ? (
-                <SmartContentRenderer content={message.content} />
+              {editingMessageId !== null && editingMessageId === message.id ? (
+                <div className="flex flex-col items-end gap-2">
+                  <textarea
+                    value={editValue}
+                    onChange={(e) => setEditValue(e.target.value)}
+                    className="w-full min-w-[16rem] bg-gray-800 border border-gray-600 rounded p-2 text-white text-left"
+                    rows={3}
+                    autoFocus
+                  />
+                  <div className="space-x-2 text-sm">
+                    <button onClick={() => setEditingMessageId(null)} className="text-gray-300 hover:text-white">Cancel</button>
+                    <button onClick={() => handleSubmitEdit(message)} className="font-semibold hover:text-gray-200">Send</button>
+                  </div>
+                </div>
+              ) : message.role === "assistant" ? (
+                <>
+                  {message.plan && <PlanChecklist plan={message.plan} />}
+                  <SmartContentRenderer content={message.content} />
+                </>
               ) : (
                 <div className="text-white font-medium leading-relaxed overflow-wrap-break-word break-words">{message.content}</div>
               )}
             </div>
-          ))}
+            {message.id && (
+              <div className={`flex items-center gap-2 mt-1 text-xs text-gray-400 ${isUser ? "justify-end" : ""}`}>
+                {hasSiblings && (
+                  <span className="flex items-center gap-1">
+                    <button onClick={() => handleSwitchSibling(message.sibling_anchor ?? message.id!, -1)} disabled={isLoading} className="hover:text-white">&lt;</button>
+                    {(message.sibling_index ?? 0) + 1}/{message.sibling_count}
+                    <button onClick={() => handleSwitchSibling(message.sibling_anchor ?? message.id!, 1)} disabled={isLoading} className="hover:text-white">&gt;</button>
+                  </span>
+                )}
+                {message.generation && (
+                  <span title={`Profile: ${message.generation.profile}`}>
+                    {message.generation.model}
+                    {message.generation.temperature !== undefined && ` · t=${message.generation.temperature}`}
+                  </span>
+                )}
+                <span className="hidden group-hover:flex gap-2">
+                  {message.role === "assistant" && (
+                    <button onClick={() => handleRegenerate(message.id!)} disabled={isLoading} className="hover:text-white">
+                      Regenerate
+                    </button>
+                  )}
+                  {isUser && (
+                    <button
+                      onClick={() => {
+                        setEditingMessageId(message.id!);
+                        setEditValue(message.content);
+                      }}
+                      disabled={isLoading}
+                      className="hover:text-white"
+                    >
+                      Edit
+                    </button>
+                  )}
+                  <button onClick={() => handleForkToNewChat(message.id!)} disabled={isLoading} className="hover:text-white">
+                    Fork
+                  </button>
+                </span>
+              </div>
+            )}
+            </div>
+            );
+          })}
+          {isLoading && livePlan && (
+            <div className="max-w-[85%] mr-auto">
+              <PlanChecklist plan={livePlan} onEditPending={handleEditPlan} />
+            </div>
+          )}
         </div>
         <div className="p-4 bg-gray-900 border-t border-gray-700 backdrop-blur-md">
           {renderAgentStatus()}
@@ -330,6 +841,16 @@ function App() {
               activeTools={activeTools}
               onToggleTool={handleToggleTool}
             />
+            <button
+              onClick={() => setPlanMode((p) => !p)}
+              disabled={isLoading}
+              title="Plan mode: the assistant plans the task as steps first, then works through them"
+              className={`px-3 py-3 rounded-lg text-sm font-semibold transition-colors disabled:opacity-60 ${
+                planMode ? "bg-purple-700 text-white" : "bg-gray-800 border border-gray-700 text-gray-400 hover:text-white"
+              }`}
+            >
+              Plan
+            </button>
             <input
               type="text"
               value={inputValue}
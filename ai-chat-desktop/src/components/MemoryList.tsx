@@ -0,0 +1,64 @@
+import React, { useState, useEffect } from "react";
+import { invoke } from "@tauri-apps/api/core";
+
+// What the agent saved with its `remember` tool.
+interface MemoryEntry {
+  id: string;
+  key: string;
+  content: string;
+  source_session: string | null;
+  created_at: number;
+  updated_at: number;
+}
+
+// Lists the agent's long-term memories so the user can review and delete them.
+const MemoryList: React.FC = () => {
+  const [memories, setMemories] = useState<MemoryEntry[] | null>(null);
+
+  const loadMemories = async () => {
+    try {
+      setMemories(await invoke<MemoryEntry[]>("list_memories"));
+    } catch (error) {
+      console.error("Failed to list memories:", error);
+      setMemories([]);
+    }
+  };
+
+  useEffect(() => {
+    loadMemories();
+  }, []);
+
+  const handleDelete = async (id: string) => {
+    try {
+      await invoke("delete_memory", { id });
+    } catch (error) {
+      console.error("Failed to delete memory:", error);
+    }
+    loadMemories();
+  };
+
+  if (memories === null) {
+    return null;
+  }
+  if (memories.length === 0) {
+    return <div className="py-2 px-3 text-xs text-gray-500">Nothing remembered yet.</div>;
+  }
+  return (
+    <>
+      {memories.map((m) => (
+        <div key={m.id} className="group py-2 px-3 rounded-md mb-1 text-sm text-gray-300 hover:bg-gray-800">
+          <div className="flex justify-between gap-2 text-xs text-gray-400">
+            <span className="whitespace-nowrap overflow-hidden text-ellipsis font-bold" title={m.key}>{m.key}</span>
+            <span className="shrink-0 group-hover:hidden">{new Date(m.updated_at * 1000).toLocaleDateString()}</span>
+            <button className="shrink-0 hidden group-hover:inline hover:text-red-400" onClick={() => handleDelete(m.id)}>
+              Delete
+            </button>
+          </div>
+          <div className="break-words line-clamp-3" title={m.content}>{m.content}</div>
+        </div>
+      ))}
+    </>
+  );
+};
+
+export default MemoryList;
@@ -0,0 +1,117 @@
+import React, { useState } from "react";
+
+export interface PlanStep {
+  description: string;
+  // Tools the model expects the step to use.
+  tools: string[];
+  status: "pending" | "running" | "done" | "failed";
+  result?: string;
+}
+
+export interface Plan {
+  steps: PlanStep[];
+  status: "running" | "done" | "failed";
+  revisions: number;
+  // Bumped whenever the agent changes the plan; edits name the version they were made on.
+  version: number;
+}
+
+export interface StepInput {
+  description: string;
+  tools: string[];
+}
+
+interface PlanChecklistProps {
+  plan: Plan;
+  // Set while the plan runs: replaces the steps not started yet.
+  onEditPending?: (steps: StepInput[]) => void;
+}
+
+const STATUS_ICONS: Record<PlanStep["status"], string> = {
+  pending: "☐",
+  running: "⏳",
+  done: "☑",
+  failed: "✗",
+};
+
+const toInputs = (steps: PlanStep[]): StepInput[] =>
+  steps.map(({ description, tools }) => ({ description, tools }));
+
+// The steps of a plan-and-execute run with their progress. Pending steps can be edited,
+// removed and added to while the plan runs.
+const PlanChecklist: React.FC<PlanChecklistProps> = ({ plan, onEditPending }) => {
+  const [newStep, setNewStep] = useState("");
+  const pending = plan.steps.filter((s) => s.status === "pending");
+  const editable = onEditPending !== undefined && plan.status === "running";
+
+  return (
+    <div className="mb-2 p-3 rounded-lg bg-gray-900 border border-gray-700 text-sm">
+      <div className="flex justify-between text-xs text-gray-400 mb-2">
+        <span className="uppercase font-bold">Plan</span>
+        <span>
+          {plan.status}
+          {plan.revisions > 0 && ` · revised ${plan.revisions}×`}
+        </span>
+      </div>
+      {plan.steps.map((step, i) => {
+        const pendingIndex = pending.indexOf(step);
+        const canEdit = editable && step.status === "pending";
+        return (
+          <div key={`${i}-${step.description}`} className="group flex gap-2 py-1">
+            <span className={step.status === "failed" ? "text-red-400" : step.status === "done" ? "text-green-400" : "text-gray-400"}>
+              {STATUS_ICONS[step.status]}
+            </span>
+            <div className="flex-1 min-w-0">
+              {canEdit ? (
+                <input
+                  type="text"
+                  defaultValue={step.description}
+                  onBlur={(e) => {
+                    if (e.target.value === step.description) return;
+                    const steps = toInputs(pending);
+                    steps[pendingIndex] = { ...steps[pendingIndex], description: e.target.value };
+                    onEditPending!(steps);
+                  }}
+                  onKeyDown={(e) => {
+                    if (e.key === "Enter") e.currentTarget.blur();
+                  }}
+                  className="w-full bg-transparent border-b border-transparent focus:border-gray-600 text-white focus:outline-none"
+                />
+              ) : (
+                <div className={step.status === "running" ? "text-white font-bold" : "text-gray-300"}>{step.description}</div>
+              )}
+              {step.tools.length > 0 && <div className="text-xs text-gray-500">{step.tools.join(", ")}</div>}
+              {step.result && <div className="text-xs text-gray-400 break-words line-clamp-3" title={step.result}>{step.result}</div>}
+            </div>
+            {canEdit && (
+              <button
+                className="opacity-0 group-hover:opacity-100 text-gray-400 hover:text-red-400 transition-opacity"
+                onClick={() => onEditPending!(toInputs(pending.filter((s) => s !== step)))}
+                title="Remove step"
+              >
+                ×
+              </button>
+            )}
+          </div>
+        );
+      })}
+      {editable && (
+        <input
+          type="text"
+          value={newStep}
+          placeholder="+ Add a step"
+          onChange={(e) => setNewStep(e.target.value)}
+          onKeyDown={(e) => {
+            if (e.key === "Enter" && newStep.trim() !== "") {
+              onEditPending!([...toInputs(pending), { description: newStep.trim(), tools: [] }]);
+              setNewStep("");
+            }
+          }}
+          className="w-full mt-1 bg-transparent text-xs text-gray-300 placeholder-gray-500 focus:outline-none"
+        />
+      )}
+    </div>
+  );
+};
+
+export default PlanChecklist;
@@ -1,51 +1,152 @@
 import React, { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import MemoryList from "./MemoryList";
 
 interface SessionItem {
   id: string;
   title: string;
+  last_snippet?: string | null;
+  tags?: string[];
+  folder?: string | null;
+  pinned?: boolean;
+  deleted_at?: number | null;
 }
 
+// Which field of a session is being edited inline.
+type EditField = "title" | "tags" | "folder";
+
+export type ExportFormat = "markdown" | "html" | "json";
+
+export interface SearchHit {
+  session_id: string;
+  title: string;
+  // null when the session title matched.
+  message_id: string | null;
+  timestamp: number;
+  score: number;
+  snippet: string;
+  // [start, end) ranges of `snippet` to highlight.
+  highlights: [number, number][];
+}
+
+const EXPORT_FORMATS: { format: ExportFormat; label: string }[] = [
+  { format: "markdown", label: "Markdown" },
+  { format: "html", label: "HTML" },
+  { format: "json", label: "JSON bundle" },
+];
+
 interface SidebarProps {
   sessions: SessionItem[];
   currentSessionId: string | null;
   onSelect: (id: string) => void;
+  onOpenSearchHit: (hit: SearchHit) => void;
   onNewChat: () => void;
   collapsed: boolean;
   onToggle: () => void;
   onConfigOpenAI: () => void;
   onRenameChat: (id: string, newTitle: string) => void;
   onDeleteChat: (id: string) => void;
+  onExportChats: (ids: string[], format: ExportFormat) => void;
+  onImportChats: () => void;
+  folders: string[];
+  selectedFolder: string | null;
+  onSelectFolder: (folder: string | null) => void;
+  onTogglePin: (id: string, pinned: boolean) => void;
+  onSetTags: (id: string, tags: string[]) => void;
+  onSetFolder: (id: string, folder: string | null) => void;
+  showTrash: boolean;
+  onToggleTrash: () => void;
+  onRestoreChat: (id: string) => void;
+  onEmptyTrash: (ids?: string[]) => void;
+  hasMore: boolean;
+  onLoadMore: () => void;
 }
 
+// The snippet with its matched ranges marked.
+const renderSnippet = (hit: SearchHit) => {
+  const parts: React.ReactNode[] = [];
+  let pos = 0;
+  hit.highlights.forEach(([start, end], i) => {
+    if (start > pos) parts.push(hit.snippet.slice(pos, start));
+    parts.push(
+      <mark key={i} className="bg-yellow-500/40 text-white rounded-sm">
+        {hit.snippet.slice(start, end)}
+      </mark>
+    );
+    pos = end;
+  });
+  parts.push(hit.snippet.slice(pos));
+  return parts;
+};
+
 const Sidebar: React.FC<SidebarProps> = ({
   sessions,
   currentSessionId,
   onSelect,
+  onOpenSearchHit,
   onNewChat,
   collapsed,
   onToggle,
   onConfigOpenAI,
   onRenameChat,
   onDeleteChat,
+  onExportChats,
+  onImportChats,
+  folders,
+  selectedFolder,
+  onSelectFolder,
+  onTogglePin,
+  onSetTags,
+  onSetFolder,
+  showTrash,
+  onToggleTrash,
+  onRestoreChat,
+  onEmptyTrash,
+  hasMore,
+  onLoadMore,
 }) => {
   const [editingSessionId, setEditingSessionId] = useState<string | null>(null);
+  const [editingField, setEditingField] = useState<EditField>("title");
   const [menuOpenSessionId, setMenuOpenSessionId] = useState<string | null>(null);
+  const [exportAllMenuOpen, setExportAllMenuOpen] = useState(false);
   const [newTitle, setNewTitle] = useState<string>("");
   const [searchQuery, setSearchQuery] = useState("");
-  const [filteredSessions, setFilteredSessions] = useState<SessionItem[]>(sessions);
+  // null when not searching.
+  const [searchHits, setSearchHits] = useState<SearchHit[] | null>(null);
+  const [showMemories, setShowMemories] = useState(false);
+
+  const startEditing = (s: SessionItem, field: EditField) => {
+    const value = field === "title" ? s.title : field === "tags" ? (s.tags ?? []).join(", ") : s.folder ?? "";
+    setNewTitle(value);
+    setEditingField(field);
+    setEditingSessionId(s.id);
+    setMenuOpenSessionId(null);
+  };
+
+  const commitEditing = (s: SessionItem) => {
+    if (editingField === "title") {
+      if (newTitle.trim() !== "" && newTitle !== s.title) {
+        onRenameChat(s.id, newTitle);
+      }
+    } else if (editingField === "tags") {
+      onSetTags(s.id, newTitle.split(",").map((t) => t.trim()).filter((t) => t !== ""));
+    } else if (newTitle.trim() !== (s.folder ?? "")) {
+      onSetFolder(s.id, newTitle.trim() === "" ? null : newTitle.trim());
+    }
+    setEditingSessionId(null);
+  };
 
   useEffect(() => {
     const handleSearch = async () => {
       if (searchQuery.trim() === "") {
-        setFilteredSessions(sessions);
+        setSearchHits(null);
       } else {
         try {
-          const results = await invoke<SessionItem[]>("search_chat_sessions", { query: searchQuery });
-          setFilteredSessions(results);
+          const results = await invoke<SearchHit[]>("search_chat_sessions", { query: searchQuery });
+          setSearchHits(results);
         } catch (error) {
           console.error("Failed to search sessions:", error);
-          setFilteredSessions([]); // Clear on error
+          setSearchHits([]); // Clear on error
         }
       }
     };
@@ -72,32 +173,150 @@ const Sidebar: React.FC<SidebarProps> = ({
           <div className="px-0 pt-3 mt-1 mb-2">
             <input
               type="text"
-              placeholder="Search history..."
+              placeholder="Search... (tag: role: tool: model: after:)"
               value={searchQuery}
               onChange={(e) => setSearchQuery(e.target.value)}
               className="w-full bg-gray-800 border border-gray-700 rounded-md px-3 py-2 text-sm text-white placeholder-gray-500 focus:outline-none focus:ring-1 focus:ring-blue-500"
             />
           </div>
-          <div className="font-bold text-sm text-gray-400 px-0 pt-3 mt-3 border-b border-gray-700 pb-2 mb-2 uppercase">Chat History</div>
+          {(folders.length > 0 || selectedFolder !== null) && (
+            <select
+              value={selectedFolder ?? ""}
+              onChange={(e) => onSelectFolder(e.target.value === "" ? null : e.target.value)}
+              className="w-full bg-gray-800 border border-gray-700 rounded-md px-2 py-1 text-sm text-white focus:outline-none"
+            >
+              <option value="">All folders</option>
+              {folders.map((f) => (
+                <option key={f} value={f}>📁 {f}</option>
+              ))}
+            </select>
+          )}
+          <div className="relative flex justify-between items-center font-bold text-sm text-gray-400 px-0 pt-3 mt-3 border-b border-gray-700 pb-2 mb-2">
+            <span className="uppercase">{showMemories ? "Memory" : showTrash ? "Trash" : "Chat History"}</span>
+            <span className="flex gap-2">
+            {showMemories ? (
+              <button
+                className="text-xs font-normal text-gray-400 hover:text-white transition-colors"
+                onClick={() => setShowMemories(false)}
+                title="Back to chats"
+              >
+                Back
+              </button>
+            ) : (
+            <>
+            {!showTrash && (
+              <button
+                className="text-xs font-normal text-gray-400 hover:text-white transition-colors"
+                onClick={() => setShowMemories(true)}
+                title="What the assistant remembers across chats"
+              >
+                Memory
+              </button>
+            )}
+            <button
+              className="text-xs font-normal text-gray-400 hover:text-white transition-colors"
+              onClick={onToggleTrash}
+              title={showTrash ? "Back to chats" : "Deleted chats"}
+            >
+              {showTrash ? "Back" : "Trash"}
+            </button>
+            {showTrash ? (
+              <button
+                className="text-xs font-normal text-gray-400 hover:text-red-400 transition-colors"
+                onClick={() => onEmptyTrash()}
+              >
+                Empty
+              </button>
+            ) : (
+            <>
+            <button
+              className="text-xs font-normal text-gray-400 hover:text-white transition-colors"
+              onClick={onImportChats}
+              title="Import a ChatGPT conversations.json or a JSONL file"
+            >
+              Import
+            </button>
+            <button
+              className="text-xs font-normal text-gray-400 hover:text-white transition-colors"
+              onClick={() => setExportAllMenuOpen((open) => !open)}
+              title="Export the listed chats"
+            >
+              Export
+            </button>
+            </>
+            )}
+            </>
+            )}
+            </span>
+            {exportAllMenuOpen && (
+              <div className="absolute top-full right-0 bg-gray-800 rounded-md shadow-lg z-10 min-w-[120px] overflow-hidden font-normal">
+                {EXPORT_FORMATS.map(({ format, label }) => (
+                  <div
+                    key={format}
+                    className="px-3 py-2 text-gray-300 cursor-pointer hover:bg-gray-700 transition-colors"
+                    onClick={() => {
+                      const ids = searchHits === null
+                        ? sessions.map((s) => s.id)
+                        : [...new Set(searchHits.map((h) => h.session_id))];
+                      onExportChats(ids, format);
+                      setExportAllMenuOpen(false);
+                    }}
+                  >
+                    {label}
+                  </div>
+                ))}
+              </div>
+            )}
+          </div>
           <div className="flex-1 overflow-y-auto px-0 pb-2">
-            {filteredSessions.map((s) => (
+            {showMemories ? (
+              <MemoryList />
+            ) : (
+            <>
+            {showTrash && sessions.map((s) => (
+              <div key={s.id} className="group flex justify-between items-center py-2 px-3 rounded-md mb-1 text-sm text-gray-400 hover:bg-gray-800">
+                <span className="flex-1 min-w-0 whitespace-nowrap overflow-hidden text-ellipsis" title={s.title}>{s.title}</span>
+                <span className="flex gap-2 text-xs opacity-0 group-hover:opacity-100 transition-opacity">
+                  <button className="hover:text-white" onClick={() => onRestoreChat(s.id)}>Restore</button>
+                  <button className="hover:text-red-400" onClick={() => onEmptyTrash([s.id])}>Delete</button>
+                </span>
+              </div>
+            ))}
+            {showTrash && sessions.length === 0 && (
+              <div className="py-2 px-3 text-xs text-gray-500">The trash is empty.</div>
+            )}
+            {!showTrash && searchHits !== null && searchHits.map((hit, i) => (
+              <div
+                key={`${hit.session_id}-${hit.message_id ?? "title"}-${i}`}
+                className={`py-2 px-3 rounded-md mb-1 cursor-pointer text-sm text-gray-300 transition-colors hover:bg-gray-800 hover:text-white ${hit.session_id === currentSessionId ? "bg-gray-800" : ""}`}
+                onClick={() => onOpenSearchHit(hit)}
+              >
+                <div className="flex justify-between gap-2 text-xs text-gray-400">
+                  <span className="whitespace-nowrap overflow-hidden text-ellipsis font-bold">{hit.title}</span>
+                  <span className="shrink-0">{new Date(hit.timestamp * 1000).toLocaleDateString()}</span>
+                </div>
+                <div className="break-words line-clamp-3">{renderSnippet(hit)}</div>
+              </div>
+            ))}
+            {!showTrash && searchHits !== null && searchHits.length === 0 && (
+              <div className="py-2 px-3 text-xs text-gray-500">No matches.</div>
+            )}
+            {!showTrash && searchHits === null && sessions.map((s) => (
               <div
                 key={s.id}
                 className={`group relative flex justify-between items-center py-2 px-3 rounded-md mb-1 cursor-pointer text-sm text-gray-300 transition-colors duration-200 ease-in-out hover:bg-gray-800 hover:text-white ${s.id === currentSessionId ? "bg-blue-700 text-white font-bold" : ""}`} // Missing backtick was here
                 onClick={editingSessionId === s.id ? undefined : () => onSelect(s.id)}
-                title={s.title}
+                title={s.last_snippet ? `${s.title}\n${s.last_snippet}` : s.title}
               >
                 {editingSessionId === s.id ? (
                   <input
                     type="text"
                     value={newTitle}
+                    placeholder={editingField === "tags" ? "tag1, tag2" : editingField === "folder" ? "Folder (empty for none)" : ""}
                     onChange={(e) => setNewTitle(e.target.value)}
                     onBlur={() => {
                       console.log("onBlur fired for session:", s.id); // Debugging line
-                      if (newTitle.trim() !== "" && newTitle !== s.title) {
-                        onRenameChat(s.id, newTitle);
-                      }
-                      setEditingSessionId(null);
+                      commitEditing(s);
                     }}
                     onKeyDown={(e) => {
                       console.log("onKeyDown fired for session:", s.id, "key:", e.key); // Debugging line
@@ -109,7 +328,17 @@ const Sidebar: React.FC<SidebarProps> = ({
                     className="flex-1 bg-gray-800 border border-gray-700 rounded-md text-white px-2 py-1 text-sm mr-2"
                   />
                 ) : (
-                  <span className="flex-1 whitespace-nowrap overflow-hidden text-ellipsis">{s.title}</span>
+                  <span className="flex-1 min-w-0">
+                    <span className="block whitespace-nowrap overflow-hidden text-ellipsis">
+                      {s.pinned && "📌 "}
+                      {s.title}
+                    </span>
+                    {s.tags && s.tags.length > 0 && (
+                      <span className="block whitespace-nowrap overflow-hidden text-ellipsis text-xs font-normal text-gray-400">
+                        {s.tags.map((t) => `#${t}`).join(" ")}
+                      </span>
+                    )}
+                  </span>
                 )}
                 <div className="flex items-center space-x-1 opacity-0 group-hover:opacity-100 transition-opacity duration-200">
                   <button
@@ -127,13 +356,39 @@ const Sidebar: React.FC<SidebarProps> = ({
                         className="px-3 py-2 text-gray-300 cursor-pointer hover:bg-gray-700 transition-colors"
                         onClick={(e) => {
                           e.stopPropagation();
-                          setNewTitle(s.title);
-                          setEditingSessionId(s.id);
-                          setMenuOpenSessionId(null);
+                          startEditing(s, "title");
                         }}
                       >
                         Rename
                       </div>
+                      <div
+                        className="px-3 py-2 text-gray-300 cursor-pointer hover:bg-gray-700 transition-colors"
+                        onClick={(e) => {
+                          e.stopPropagation();
+                          onTogglePin(s.id, !s.pinned);
+                          setMenuOpenSessionId(null);
+                        }}
+                      >
+                        {s.pinned ? "Unpin" : "Pin"}
+                      </div>
+                      <div
+                        className="px-3 py-2 text-gray-300 cursor-pointer hover:bg-gray-700 transition-colors"
+                        onClick={(e) => {
+                          e.stopPropagation();
+                          startEditing(s, "tags");
+                        }}
+                      >
+                        Edit tags
+                      </div>
+                      <div
+                        className="px-3 py-2 text-gray-300 cursor-pointer hover:bg-gray-700 transition-colors"
+                        onClick={(e) => {
+                          e.stopPropagation();
+                          startEditing(s, "folder");
+                        }}
+                      >
+                        Move to folder
+                      </div>
                       <div
                         className="px-3 py-2 text-gray-300 cursor-pointer hover:bg-gray-700 transition-colors"
                         onClick={(e) => {
@@ -144,11 +399,34 @@ const Sidebar: React.FC<SidebarProps> = ({
                       >
                         Delete
                       </div>
+                      {EXPORT_FORMATS.map(({ format, label }) => (
+                        <div
+                          key={format}
+                          className="px-3 py-2 text-gray-300 cursor-pointer hover:bg-gray-700 transition-colors"
+                          onClick={(e) => {
+                            e.stopPropagation();
+                            onExportChats([s.id], format);
+                            setMenuOpenSessionId(null);
+                          }}
+                        >
+                          Export as {label}
+                        </div>
+                      ))}
                     </div>
                   )}
                 </div>
               </div>
             ))}
+            {hasMore && searchQuery.trim() === "" && (
+              <button
+                className="w-full py-1 text-xs text-gray-400 hover:text-white transition-colors"
+                onClick={onLoadMore}
+              >
+                Load more
+              </button>
+            )}
+            </>
+            )}
           </div>
         </div>
       )}
@@ -0,0 +1,44 @@
+import React from "react";
+
+interface ToolCallInfo {
+  tool_name: string;
+  arguments: unknown;
+  status?: "success" | "error";
+  duration_ms?: number;
+}
+
+interface ToolStepProps {
+  role: string;
+  content: string;
+  toolCall: ToolCallInfo;
+}
+
+// Renders one intermediate agent step (a tool call and, once finished, its result) as a collapsible block.
+const ToolStep: React.FC<ToolStepProps> = ({ role, content, toolCall }) => {
+  const isResult = role === "tool";
+  const icon = !isResult ? "⏳" : toolCall.status === "error" ? "❌" : "✅";
+  const duration = toolCall.duration_ms !== undefined ? ` · ${toolCall.duration_ms} ms` : "";
+
+  return (
+    <details className="mb-2 mr-auto max-w-[85%] rounded-lg border border-gray-700 bg-gray-900/60 text-xs text-gray-300">
+      <summary className="cursor-pointer select-none px-3 py-2 font-mono">
+        {icon} {toolCall.tool_name}
+        <span className="text-gray-500">{duration}</span>
+      </summary>
+      <div className="border-t border-gray-700 px-3 py-2 space-y-2">
+        <div>
+          <div className="text-gray-500 mb-1">Arguments</div>
+          <pre className="whitespace-pre-wrap break-words">{JSON.stringify(toolCall.arguments ?? null, null, 2)}</pre>
+        </div>
+        {isResult && (
+          <div>
+            <div className="text-gray-500 mb-1">Result</div>
+            <pre className="whitespace-pre-wrap break-words max-h-64 overflow-y-auto">{content}</pre>
+          </div>
+        )}
+      </div>
+    </details>
+  );
+};
+
+export default ToolStep;